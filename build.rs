@@ -0,0 +1,57 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Exposes build info to the `/version` endpoint
+fn main() {
+    let git_commit = std::env::var("GIT_COMMIT")
+        .ok()
+        .filter(|commit| !commit.is_empty())
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|commit| commit.trim().to_owned())
+        })
+        .unwrap_or_else(|| "unknown".to_owned());
+    let build_timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or_default();
+
+    println!("cargo:rustc-env=GIT_COMMIT={git_commit}");
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={build_timestamp}");
+
+    // Rerun on a new commit or checkout, otherwise cached build info would be reported
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    rerun_if_git_ref_changed();
+}
+
+/// Watches `HEAD` and the branch ref it points to. Missing files aren't watched, since cargo
+/// would rerun the script on every build
+fn rerun_if_git_ref_changed() {
+    let git_dir = Path::new(".git");
+    let head = git_dir.join("HEAD");
+    if !head.exists() {
+        return;
+    }
+    println!("cargo:rerun-if-changed={}", head.display());
+
+    let head_ref = std::fs::read_to_string(&head)
+        .ok()
+        .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_owned()));
+    if let Some(head_ref) = head_ref {
+        let ref_path = git_dir.join(head_ref);
+        if ref_path.exists() {
+            println!("cargo:rerun-if-changed={}", ref_path.display());
+        }
+    }
+
+    let packed_refs = git_dir.join("packed-refs");
+    if packed_refs.exists() {
+        println!("cargo:rerun-if-changed={}", packed_refs.display());
+    }
+}
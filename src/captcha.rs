@@ -1,7 +1,13 @@
 use crate::error::AppError;
+use crate::store::ExpiringStore;
 use chrono::{DateTime, Utc};
 use near_sdk::{serde::Deserialize, serde_json};
 use reqwest::Client;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+/// Google verification endpoint used by the primary provider unless overridden
+const DEFAULT_VERIFY_URL: &str = "https://www.google.com/recaptcha/api/siteverify";
 
 #[derive(Deserialize, Debug, Clone, Default)]
 #[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
@@ -9,12 +15,43 @@ pub struct CaptchaConfig {
     action: String,
     threshold: f64,
     secret: String,
+    /// Additionally accepted actions
+    #[serde(default)]
+    allowed_actions: Vec<String>,
+    /// Accepted hostnames the captcha was solved on, any hostname is accepted if empty
+    #[serde(default)]
+    allowed_hostnames: Vec<String>,
+    /// Time window in seconds within which a consumed captcha token is rejected on reuse
+    #[serde(default)]
+    reuse_window: Option<u64>,
+    /// Verification url of the primary provider, Google if not set
+    #[serde(default)]
+    verify_url: Option<String>,
+    /// Providers tried in order if the primary one fails to respond properly
+    #[serde(default)]
+    fallback_providers: Vec<CaptchaProviderConfig>,
+}
+
+/// Fallback captcha provider with `siteverify` compatible API, e.g. `www.recaptcha.net`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
+pub struct CaptchaProviderConfig {
+    /// Name of the provider logged on verification
+    name: String,
+    url: String,
+    /// Defaults to the primary provider secret
+    #[serde(default)]
+    secret: Option<String>,
 }
 
 #[derive(Clone)]
 pub struct CaptchaClient {
     inner_client: Client,
     config: CaptchaConfig,
+    /// Primary provider followed by fallback ones
+    providers: Vec<CaptchaProviderConfig>,
+    /// Hashes of consumed captcha tokens
+    consumed_tokens: Option<ExpiringStore<Vec<u8>, ()>>,
 }
 
 #[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
@@ -42,10 +79,14 @@ pub enum CaptchaError {
     ResponseError(CaptchaErrorCode),
     #[error("Invalid action")]
     InvalidAction,
+    #[error("Invalid hostname")]
+    InvalidHostname,
     #[error("Request failure {0}")]
     RequestFailure(reqwest::Error),
     #[error("Request parse failure {0}")]
     ParseFailure(serde_json::Error),
+    #[error("No captcha provider configured")]
+    NoProviders,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -63,8 +104,7 @@ pub struct CaptchaResponse {
     action: String,
     #[serde(rename = "challenge_ts")]
     _challenge_ts: DateTime<Utc>,
-    #[serde(rename = "hostname")]
-    _hostname: String,
+    hostname: String,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -74,21 +114,89 @@ pub struct CaptchaErrorResponse {
     error_codes: Vec<CaptchaErrorCode>,
 }
 
+impl CaptchaError {
+    /// Checks if the captcha provider failed to respond properly, as opposed to the user failing captcha
+    pub fn is_provider_failure(&self) -> bool {
+        matches!(
+            self,
+            Self::RequestFailure(_) | Self::ParseFailure(_) | Self::NoProviders
+        )
+    }
+}
+
 impl CaptchaClient {
     pub fn new(config: CaptchaConfig) -> Result<Self, AppError> {
         let inner_client = Client::builder().pool_max_idle_per_host(0).build()?;
+        let primary = CaptchaProviderConfig {
+            name: "primary".to_owned(),
+            url: config
+                .verify_url
+                .clone()
+                .unwrap_or_else(|| DEFAULT_VERIFY_URL.to_owned()),
+            secret: Some(config.secret.clone()),
+        };
+        let fallbacks =
+            config
+                .fallback_providers
+                .iter()
+                .cloned()
+                .map(|provider| CaptchaProviderConfig {
+                    secret: provider.secret.or_else(|| Some(config.secret.clone())),
+                    ..provider
+                });
 
         Ok(Self {
+            providers: std::iter::once(primary).chain(fallbacks).collect(),
+            consumed_tokens: config
+                .reuse_window
+                .map(|window| ExpiringStore::new(Duration::from_secs(window))),
             inner_client,
             config,
         })
     }
 
-    async fn fetch_captcha(&self, token: &str) -> Result<String, CaptchaError> {
-        let params: [(&str, &str); 2] = [("secret", &self.config.secret), ("response", token)];
+    /// Rejects a captcha token already consumed within the reuse window, without consuming it
+    pub fn check_not_consumed(&self, token: &str) -> Result<(), AppError> {
+        match &self.consumed_tokens {
+            Some(consumed_tokens)
+                if consumed_tokens
+                    .get(&Sha256::digest(token.as_bytes()).to_vec())
+                    .is_some() =>
+            {
+                tracing::warn!("Captcha token is reused");
+                Err(AppError::CaptchaReused)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Marks a captcha token as consumed, rejecting tokens already consumed within the reuse window
+    pub fn consume(&self, token: &str) -> Result<(), AppError> {
+        let consumed_tokens = match &self.consumed_tokens {
+            Some(consumed_tokens) => consumed_tokens,
+            None => return Ok(()),
+        };
+
+        if consumed_tokens.try_insert(Sha256::digest(token.as_bytes()).to_vec(), ()) {
+            Ok(())
+        } else {
+            tracing::warn!("Captcha token is reused");
+            Err(AppError::CaptchaReused)
+        }
+    }
+
+    async fn fetch_captcha(
+        &self,
+        provider: &CaptchaProviderConfig,
+        token: &str,
+    ) -> Result<String, CaptchaError> {
+        let params: [(&str, &str); 2] = [
+            ("secret", provider.secret.as_deref().unwrap_or_default()),
+            ("response", token),
+        ];
 
         self.inner_client
-            .post("https://www.google.com/recaptcha/api/siteverify")
+            .post(&provider.url)
             .form(&params)
             .send()
             .await
@@ -107,10 +215,17 @@ impl CaptchaClient {
                 Err(CaptchaError::ResponseError(CaptchaErrorCode::BadRequest))
             }
             Ok(CaptchaResult::Success(CaptchaResponse { action, .. }))
-                if action != self.config.action =>
+                if !self.is_allowed_action(&action) =>
             {
+                tracing::warn!("Captcha was solved for unexpected action `{action}`");
                 Err(CaptchaError::InvalidAction)
             }
+            Ok(CaptchaResult::Success(CaptchaResponse { hostname, .. }))
+                if !self.is_allowed_hostname(&hostname) =>
+            {
+                tracing::warn!("Captcha was solved on unexpected hostname `{hostname}`");
+                Err(CaptchaError::InvalidHostname)
+            }
             Ok(CaptchaResult::Success(response)) => Ok(response),
             Ok(CaptchaResult::Failure(CaptchaErrorResponse { error_codes, .. })) => Err(
                 CaptchaError::ResponseError(error_codes.first().copied().unwrap_or_default()),
@@ -119,10 +234,44 @@ impl CaptchaClient {
         }
     }
 
+    fn is_allowed_action(&self, action: &str) -> bool {
+        action == self.config.action || self.config.allowed_actions.iter().any(|a| a == action)
+    }
+
+    fn is_allowed_hostname(&self, hostname: &str) -> bool {
+        self.config.allowed_hostnames.is_empty()
+            || self.config.allowed_hostnames.iter().any(|h| h == hostname)
+    }
+
+    /// Verifies a captcha token with providers in order, until one of them responds properly
     pub async fn verify(&self, token: &str) -> Result<bool, CaptchaError> {
         tracing::trace!("Verify captcha token `{token}`");
 
-        let fetched = self.fetch_captcha(token).await?;
+        let mut result = Err(CaptchaError::NoProviders);
+
+        for provider in &self.providers {
+            result = self.verify_with_provider(provider, token).await;
+
+            match &result {
+                Err(e) if e.is_provider_failure() => {
+                    tracing::warn!("Captcha provider `{}` failure: {e}", provider.name)
+                }
+                _ => {
+                    tracing::info!("Captcha verified by provider `{}`", provider.name);
+                    break;
+                }
+            }
+        }
+
+        result
+    }
+
+    async fn verify_with_provider(
+        &self,
+        provider: &CaptchaProviderConfig,
+        token: &str,
+    ) -> Result<bool, CaptchaError> {
+        let fetched = self.fetch_captcha(provider, token).await?;
 
         let CaptchaResponse { score, .. } = self.parse_captcha_response(fetched)?;
 
@@ -132,7 +281,12 @@ impl CaptchaClient {
 
 #[cfg(test)]
 mod tests {
-    use super::{CaptchaClient, CaptchaConfig, CaptchaError, CaptchaErrorCode, CaptchaResponse};
+    use super::{
+        CaptchaClient, CaptchaConfig, CaptchaError, CaptchaErrorCode, CaptchaProviderConfig,
+        CaptchaResponse,
+    };
+    use crate::error::AppError;
+    use crate::utils::test_utils::spawn_mock_server;
     use assert_matches::assert_matches;
     use chrono::Utc;
 
@@ -154,6 +308,51 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_captcha_token_reuse() {
+        let client = CaptchaClient::new(CaptchaConfig {
+            reuse_window: Some(60),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Checks alone, as done by dry runs, don't consume the token
+        for _ in 0..2 {
+            assert_matches!(client.check_not_consumed("some_token"), Ok(()));
+        }
+        assert_matches!(client.consume("some_token"), Ok(()));
+        assert_matches!(
+            client.check_not_consumed("some_token"),
+            Err(AppError::CaptchaReused)
+        );
+        assert_matches!(client.consume("some_token"), Err(AppError::CaptchaReused));
+        assert_matches!(
+            client.clone().consume("some_token"),
+            Err(AppError::CaptchaReused)
+        );
+        assert_matches!(client.consume("other_token"), Ok(()));
+
+        // Reuse isn't tracked by default
+        let client = CaptchaClient::default();
+        assert_matches!(client.consume("some_token"), Ok(()));
+        assert_matches!(client.consume("some_token"), Ok(()));
+    }
+
+    #[test]
+    fn test_captcha_provider_failure() {
+        let client = CaptchaClient::default();
+
+        assert_matches!(
+            client.parse_captcha_response("<html>Service Unavailable</html>".to_owned()),
+            Err(e) if e.is_provider_failure()
+        );
+        assert!(
+            !CaptchaError::ResponseError(CaptchaErrorCode::TimeoutOrDuplicate)
+                .is_provider_failure()
+        );
+        assert!(!CaptchaError::InvalidAction.is_provider_failure());
+    }
+
     #[test]
     fn test_captcha_bad_request() {
         let client = CaptchaClient::default();
@@ -192,6 +391,79 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_captcha_allowlist() {
+        struct TestCase {
+            name: &'static str,
+            action: &'static str,
+            hostname: &'static str,
+            expected: Result<(), &'static str>,
+        }
+        let client = CaptchaClient::new(CaptchaConfig {
+            threshold: 0.5,
+            action: "homepage".to_owned(),
+            secret: String::default(),
+            allowed_actions: vec!["verify".to_owned()],
+            allowed_hostnames: vec!["app.example.com".to_owned()],
+            reuse_window: None,
+            verify_url: None,
+            fallback_providers: vec![],
+        })
+        .unwrap();
+        let now = Utc::now();
+
+        let test_cases = [
+            TestCase {
+                name: "Configured action and allowed hostname",
+                action: "homepage",
+                hostname: "app.example.com",
+                expected: Ok(()),
+            },
+            TestCase {
+                name: "Allowed action and allowed hostname",
+                action: "verify",
+                hostname: "app.example.com",
+                expected: Ok(()),
+            },
+            TestCase {
+                name: "Mismatching action",
+                action: "login",
+                hostname: "app.example.com",
+                expected: Err("Invalid action"),
+            },
+            TestCase {
+                name: "Mismatching hostname",
+                action: "homepage",
+                hostname: "evil.example.com",
+                expected: Err("Invalid hostname"),
+            },
+        ];
+
+        for TestCase {
+            name,
+            action,
+            hostname,
+            expected,
+        } in test_cases
+        {
+            let result = client.parse_captcha_response(format!(
+                r#"{{
+                    "success": true,
+                    "score": 0.9,
+                    "action": "{action}",
+                    "challenge_ts": "{now}",
+                    "hostname": "{hostname}"
+                }}"#
+            ));
+
+            assert_eq!(
+                result.map(|_| ()).map_err(|e| e.to_string()),
+                expected.map_err(ToOwned::to_owned),
+                "Test case `{name}` failed"
+            );
+        }
+    }
+
     #[test]
     fn test_captcha_error_codes() {
         struct TestCase {
@@ -271,12 +543,103 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_captcha_fallback_providers() {
+        use axum::{http::StatusCode, routing::post, Router};
+
+        let passed = || async {
+            format!(
+                r#"{{
+                    "success": true,
+                    "score": 0.9,
+                    "action": "homepage",
+                    "challenge_ts": "{}",
+                    "hostname": "http://some_url"
+                }}"#,
+                Utc::now()
+            )
+        };
+        let failed = || async {
+            r#"{
+                "success": false,
+                "error-codes": ["timeout-or-duplicate"]
+            }"#
+        };
+        let down = || async { (StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable") };
+
+        let passing_url = spawn_mock_server(Router::new().route("/siteverify", post(passed)));
+        let failing_url = spawn_mock_server(Router::new().route("/siteverify", post(failed)));
+        let down_url = spawn_mock_server(Router::new().route("/siteverify", post(down)));
+
+        let gen_client = |primary_url: &str, fallback_urls: &[&str]| {
+            CaptchaClient::new(CaptchaConfig {
+                threshold: 0.5,
+                action: "homepage".to_owned(),
+                verify_url: Some(format!("{primary_url}/siteverify")),
+                fallback_providers: fallback_urls
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, url)| CaptchaProviderConfig {
+                        name: format!("fallback_{idx}"),
+                        url: format!("{url}/siteverify"),
+                        secret: None,
+                    })
+                    .collect(),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+
+        // Primary down, secondary up
+        assert_matches!(
+            gen_client(&down_url, &[&passing_url])
+                .verify("some_token")
+                .await,
+            Ok(true)
+        );
+        assert_matches!(
+            gen_client(&down_url, &[&down_url, &failing_url])
+                .verify("some_token")
+                .await,
+            Err(CaptchaError::ResponseError(
+                CaptchaErrorCode::TimeoutOrDuplicate
+            ))
+        );
+
+        // Definitive failure isn't retried with fallbacks
+        assert_matches!(
+            gen_client(&failing_url, &[&passing_url])
+                .verify("some_token")
+                .await,
+            Err(CaptchaError::ResponseError(
+                CaptchaErrorCode::TimeoutOrDuplicate
+            ))
+        );
+
+        // All down
+        assert_matches!(
+            gen_client(&down_url, &[&down_url])
+                .verify("some_token")
+                .await,
+            Err(e) if e.is_provider_failure()
+        );
+        assert_matches!(
+            gen_client(&down_url, &[]).verify("some_token").await,
+            Err(e) if e.is_provider_failure()
+        );
+    }
+
     impl Default for CaptchaClient {
         fn default() -> Self {
             Self::new(CaptchaConfig {
                 threshold: 0.5,
                 action: "homepage".to_owned(),
                 secret: String::default(),
+                allowed_actions: Vec::new(),
+                allowed_hostnames: Vec::new(),
+                reuse_window: None,
+                verify_url: None,
+                fallback_providers: Vec::new(),
             })
             .unwrap()
         }
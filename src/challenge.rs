@@ -0,0 +1,288 @@
+use crate::error::AppError;
+use crate::signer::decode_signature;
+use base64::{engine::general_purpose, Engine};
+use chrono::Utc;
+use near_crypto::{KeyType, PublicKey, SecretKey, Signature};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::AccountId;
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
+pub struct ChallengeConfig {
+    /// Whether a solved challenge is required to issue a token
+    #[serde(default)]
+    pub required: bool,
+    /// Lifetime of issued challenges in seconds
+    #[serde(default = "default_challenge_lifetime")]
+    pub lifetime: u64,
+    /// Whether to refuse signing more than one token per challenge nonce
+    #[serde(default)]
+    pub single_use: bool,
+}
+
+impl Default for ChallengeConfig {
+    fn default() -> Self {
+        Self {
+            required: false,
+            lifetime: default_challenge_lifetime(),
+            single_use: false,
+        }
+    }
+}
+
+fn default_challenge_lifetime() -> u64 {
+    300
+}
+
+/// Nonce bound to a claimer, signed by the oracle
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct Challenge {
+    pub claimer: AccountId,
+    pub nonce: String,
+    pub timestamp: u64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChallengeReq {
+    pub claimer: String,
+}
+
+/// Oracle signed challenge to be signed by the claimer
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChallengeResponse {
+    #[serde(rename = "m")]
+    pub message: String,
+    #[serde(rename = "sig")]
+    pub signature_ed25519: String,
+}
+
+/// Oracle signed challenge along with the claimer signature over it
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
+pub struct ChallengeSolution {
+    #[serde(rename = "m")]
+    pub message: String,
+    #[serde(rename = "sig")]
+    pub signature_ed25519: String,
+    /// Claimer public key, e.g. `ed25519:...`
+    pub public_key: String,
+    /// Base64 encoded claimer signature over the raw challenge message
+    pub signature: String,
+}
+
+/// Creates a new challenge for a claimer signed with the oracle signing key
+pub fn issue(claimer: AccountId, signing_key: &SecretKey) -> Result<ChallengeResponse, AppError> {
+    let challenge = Challenge {
+        claimer,
+        nonce: Uuid::new_v4().simple().to_string(),
+        timestamp: Utc::now().timestamp() as u64,
+    };
+    let raw_message = challenge.try_to_vec().map_err(|_| AppError::SigningError)?;
+
+    let raw_signature_ed25519 = match signing_key.sign(&raw_message) {
+        Signature::ED25519(signature) => signature.to_bytes(),
+        _ => return Err(AppError::SigningError),
+    };
+
+    Ok(ChallengeResponse {
+        message: general_purpose::STANDARD.encode(&raw_message),
+        signature_ed25519: general_purpose::STANDARD.encode(raw_signature_ed25519),
+    })
+}
+
+impl ChallengeSolution {
+    /// Checks that the challenge was issued by the oracle for the claimer, isn't expired
+    /// and is signed with a key controlling the claimer account. Returns the verified challenge
+    pub fn verify(
+        &self,
+        claimer: &AccountId,
+        signing_key: &SecretKey,
+        lifetime: u64,
+    ) -> Result<Challenge, AppError> {
+        let raw_message = decode_base64(&self.message, "message")?;
+        let oracle_signature = decode_signature(
+            KeyType::ED25519,
+            &decode_base64(&self.signature_ed25519, "signature")?,
+        )?;
+
+        if !oracle_signature.verify(&raw_message, &signing_key.public_key()) {
+            tracing::debug!("Challenge wasn't issued by the oracle");
+            return Err(AppError::ChallengeFailed);
+        }
+
+        let challenge = Challenge::try_from_slice(&raw_message).map_err(|e| {
+            AppError::MalformedRequest(format!("Invalid challenge message. Error: {e}"))
+        })?;
+
+        if &challenge.claimer != claimer {
+            tracing::debug!(
+                "Challenge was issued for `{}` instead of `{claimer}`",
+                challenge.claimer
+            );
+            return Err(AppError::ChallengeFailed);
+        }
+
+        if challenge.timestamp + lifetime < Utc::now().timestamp() as u64 {
+            tracing::debug!("Challenge is expired");
+            return Err(AppError::ChallengeFailed);
+        }
+
+        let public_key = PublicKey::from_str(&self.public_key)
+            .map_err(|e| AppError::MalformedRequest(format!("Invalid public key. Error: {e}")))?;
+
+        if !controls_implicit_account(&public_key, claimer) {
+            tracing::debug!("Public key `{public_key}` doesn't control `{claimer}` account");
+            return Err(AppError::ChallengeFailed);
+        }
+
+        let claimer_signature = decode_signature(
+            public_key.key_type(),
+            &decode_base64(&self.signature, "claimer signature")?,
+        )?;
+
+        if !claimer_signature.verify(&raw_message, &public_key) {
+            tracing::debug!("Invalid claimer signature over the challenge");
+            return Err(AppError::ChallengeFailed);
+        }
+
+        Ok(challenge)
+    }
+}
+
+/// Checks that an account is the implicit account of an ed25519 public key.
+/// Access keys of named accounts can't be checked without querying the chain
+fn controls_implicit_account(public_key: &PublicKey, account_id: &AccountId) -> bool {
+    if public_key.key_type() != KeyType::ED25519 {
+        return false;
+    }
+
+    let implicit_account_id = public_key
+        .key_data()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+
+    account_id.as_str() == implicit_account_id
+}
+
+fn decode_base64(value: &str, name: &str) -> Result<Vec<u8>, AppError> {
+    general_purpose::STANDARD
+        .decode(value)
+        .map_err(|e| AppError::MalformedRequest(format!("Invalid {name} encoding. Error: {e}")))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{issue, ChallengeResponse, ChallengeSolution};
+    use crate::error::AppError;
+    use assert_matches::assert_matches;
+    use base64::{engine::general_purpose, Engine};
+    use near_crypto::{KeyType, SecretKey, Signature};
+    use near_sdk::AccountId;
+
+    pub fn implicit_account(key: &SecretKey) -> AccountId {
+        AccountId::new_unchecked(
+            key.public_key()
+                .key_data()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+        )
+    }
+
+    pub fn solve(challenge: &ChallengeResponse, key: &SecretKey) -> ChallengeSolution {
+        let raw_message = general_purpose::STANDARD
+            .decode(&challenge.message)
+            .unwrap();
+        let signature = match key.sign(&raw_message) {
+            Signature::ED25519(signature) => signature.to_bytes(),
+            _ => unreachable!(),
+        };
+
+        ChallengeSolution {
+            message: challenge.message.clone(),
+            signature_ed25519: challenge.signature_ed25519.clone(),
+            public_key: key.public_key().to_string(),
+            signature: general_purpose::STANDARD.encode(signature),
+        }
+    }
+
+    #[test]
+    fn test_challenge_valid_signature() {
+        let oracle_key = SecretKey::from_random(KeyType::ED25519);
+        let claimer_key = SecretKey::from_random(KeyType::ED25519);
+        let claimer = implicit_account(&claimer_key);
+
+        let challenge = issue(claimer.clone(), &oracle_key).unwrap();
+        let solution = solve(&challenge, &claimer_key);
+
+        assert_matches!(
+            solution.verify(&claimer, &oracle_key, 300),
+            Ok(challenge) if challenge.claimer == claimer
+        );
+    }
+
+    #[test]
+    fn test_challenge_invalid_signature() {
+        let oracle_key = SecretKey::from_random(KeyType::ED25519);
+        let claimer_key = SecretKey::from_random(KeyType::ED25519);
+        let claimer = implicit_account(&claimer_key);
+        let challenge = issue(claimer.clone(), &oracle_key).unwrap();
+
+        // Signed by a key not controlling the claimer account
+        let other_key = SecretKey::from_random(KeyType::ED25519);
+        let solution = solve(&challenge, &other_key);
+        assert_matches!(
+            solution.verify(&claimer, &oracle_key, 300),
+            Err(AppError::ChallengeFailed)
+        );
+
+        // Signed over another message
+        let mut solution = solve(&challenge, &claimer_key);
+        let other_challenge = issue(claimer.clone(), &oracle_key).unwrap();
+        solution.signature = solve(&other_challenge, &claimer_key).signature;
+        assert_matches!(
+            solution.verify(&claimer, &oracle_key, 300),
+            Err(AppError::ChallengeFailed)
+        );
+
+        // Not issued by the oracle
+        let solution = solve(&issue(claimer.clone(), &other_key).unwrap(), &claimer_key);
+        assert_matches!(
+            solution.verify(&claimer, &oracle_key, 300),
+            Err(AppError::ChallengeFailed)
+        );
+
+        // Issued for another claimer
+        let solution = solve(&challenge, &claimer_key);
+        let other_claimer = implicit_account(&other_key);
+        assert_matches!(
+            solution.verify(&other_claimer, &oracle_key, 300),
+            Err(AppError::ChallengeFailed)
+        );
+
+        // Named accounts can't be proven
+        let named_claimer = AccountId::new_unchecked("test.near".to_owned());
+        let solution = solve(
+            &issue(named_claimer.clone(), &oracle_key).unwrap(),
+            &claimer_key,
+        );
+        assert_matches!(
+            solution.verify(&named_claimer, &oracle_key, 300),
+            Err(AppError::ChallengeFailed)
+        );
+
+        // Malformed claimer signature
+        let mut solution = solve(&challenge, &claimer_key);
+        solution.signature = "not base64".to_owned();
+        assert_matches!(
+            solution.verify(&claimer, &oracle_key, 300),
+            Err(AppError::MalformedRequest(_))
+        );
+    }
+}
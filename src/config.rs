@@ -1,8 +1,15 @@
 use crate::captcha::CaptchaConfig;
-use crate::signer::SignerConfig;
+use crate::challenge::ChallengeConfig;
+use crate::error::AppError;
+use crate::signer::{SignerConfig, SignerCredentials};
+use crate::utils::Secret;
 use crate::verification_provider::VerificationProviderConfig;
-use config::{self, ConfigError};
-use near_sdk::serde::Deserialize;
+use chrono::{DateTime, Utc};
+use near_sdk::borsh::{self, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
@@ -13,18 +20,367 @@ pub struct AppConfig {
     pub captcha: CaptchaConfig,
     #[serde(default = "default_allow_named_sub_accounts")]
     pub allow_named_sub_accounts: bool,
+    /// Time window in seconds within which a claimer isn't allowed to switch external account
+    #[serde(default)]
+    pub claimer_identity_window: Option<u64>,
+    #[serde(default)]
+    pub access_log_level: AccessLogLevel,
+    /// Stops issuing attestations while the service stays up. Reloaded on SIGHUP
+    #[serde(default)]
+    pub maintenance_mode: bool,
+    /// Minimum age in seconds of the earliest user verification case required to issue a token
+    #[serde(default)]
+    pub min_account_age: Option<u64>,
+    /// Proof of claimer account control with an oracle signed challenge
+    #[serde(default)]
+    pub challenge: ChallengeConfig,
+    #[serde(default)]
+    pub unverified_user_response: UnverifiedUserResponse,
+    /// Whether to refuse issuing a token for a user with rejected KYC
+    #[serde(default)]
+    pub refuse_on_kyc_rejected: bool,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// Skips captcha verification for local development. Activated only with `DEV_MODE` env variable set
+    #[serde(default)]
+    pub dev_skip_captcha: bool,
+    /// Logs external account ids unmasked for local development. Activated only with `DEV_MODE` env variable set
+    #[serde(default)]
+    pub dev_log_ext_account: bool,
+    /// Serves the `/verify_signature` debug endpoint
+    #[serde(default)]
+    pub verify_signature_endpoint: bool,
+    /// API key required in `X-Api-Key` header to include KYC status into responses
+    #[serde(default)]
+    pub kyc_api_key: Option<Secret>,
+    /// Maximum number of concurrent requests from a single client IP, unlimited if not set
+    #[serde(default)]
+    pub max_requests_per_ip: Option<usize>,
+    /// Whether to trim and lowercase claimer account id before validation
+    #[serde(default = "default_normalize_claimer")]
+    pub normalize_claimer: bool,
+    /// Whether to require implicit claimers to solve a challenge or be linked as a NEAR wallet in the provider
+    #[serde(default)]
+    pub require_claimer_binding: bool,
+    /// Whether to list remaining verification steps in responses for pending users
+    #[serde(default)]
+    pub pending_steps: bool,
+    /// Instance id echoed in `X-Oracle-Instance` response header, defaults to the hostname
+    #[serde(default)]
+    pub instance_id: Option<String>,
+    #[serde(default)]
+    pub one_time_claims: OneTimeClaimsConfig,
+    /// Overall time budget in milliseconds shared by captcha verification and user fetching, unlimited if not set
+    #[serde(default)]
+    pub request_budget_ms: Option<u64>,
+    /// Minimum duration in milliseconds of `/verify` responses regardless of the outcome, not padded if not set
+    #[serde(default)]
+    pub min_verify_duration_ms: Option<u64>,
+    /// Start of the campaign window, requests before it are rejected
+    #[serde(default)]
+    pub accept_from: Option<DateTime<Utc>>,
+    /// End of the campaign window, requests after it are rejected
+    #[serde(default)]
+    pub accept_until: Option<DateTime<Utc>>,
+    /// Whether to accept or refuse requests when the captcha provider fails
+    #[serde(default)]
+    pub captcha_failure_mode: CaptchaFailureMode,
+    /// Limit of tokens issued per external account regardless of the claimer, unlimited if not set
+    #[serde(default)]
+    pub ext_account_rate_limit: Option<ExtAccountRateLimitConfig>,
+    /// Maximum number of `/verify/batch` items verified concurrently
+    #[serde(default = "default_max_batch_concurrency")]
+    pub max_batch_concurrency: usize,
+    /// Maximum number of items in a `/verify/batch` request
+    #[serde(default = "default_max_batch_size")]
+    pub max_batch_size: usize,
+    #[serde(default)]
+    pub response_fields: ResponseFieldsConfig,
+    /// Whether to reject verification requests with unknown fields
+    #[serde(default)]
+    pub strict_request_parsing: bool,
+    /// Error rate of verification requests the instance is reported unready at, not tracked if not set
+    #[serde(default)]
+    pub error_rate_threshold: Option<ErrorRateThresholdConfig>,
 }
 
-pub fn load_config() -> Result<AppConfig, ConfigError> {
-    config::Config::builder()
+impl AppConfig {
+    /// Checks that the campaign window, if bounded on both ends, starts before it ends
+    pub fn check_campaign_window(&self) -> Result<(), AppError> {
+        match (self.accept_from, self.accept_until) {
+            (Some(from), Some(until)) if from > until => Err(AppError::Generic(format!(
+                "Campaign window starts at {from} after its end at {until}"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// SHA-256 of the borsh serialized verification policy the oracle applies, changed by any of its settings.
+    /// Ignored case ids aren't part of it, since they're reloaded at runtime during incident response
+    pub fn policy_hash(&self) -> [u8; 32] {
+        let provider = &self.verification_provider;
+        let mut uniqueness_case_statuses = provider
+            .uniqueness_case_statuses
+            .0
+            .iter()
+            .map(|status| *status as u8)
+            .collect::<Vec<_>>();
+        uniqueness_case_statuses.sort_unstable();
+        uniqueness_case_statuses.dedup();
+        let sorted = |values: &[String]| {
+            let mut values = values.to_vec();
+            values.sort_unstable();
+            values.dedup();
+            values
+        };
+
+        let policy = VerificationPolicy {
+            uniqueness_case_statuses,
+            strict_kyc_liveness: provider.strict_kyc_liveness,
+            require_liveness_audit: provider.require_liveness_audit,
+            kyc_validity_period: provider.kyc_validity_period,
+            max_verification_cases: provider.max_verification_cases.map(|max| max as u64),
+            refuse_on_kyc_rejected: self.refuse_on_kyc_rejected,
+            min_account_age: self.min_account_age,
+            allow_named_sub_accounts: self.allow_named_sub_accounts,
+            require_claimer_binding: self.require_claimer_binding,
+            claimer_identity_window: self.claimer_identity_window,
+            one_time_claims: self.one_time_claims.enabled,
+            jwt_lifetime: self.signer.jwt_lifetime,
+            kyc_jwt_lifetime: self.signer.kyc_jwt_lifetime,
+            uniqueness_scopes: sorted(&provider.required_scopes.uniqueness),
+            kyc_scopes: sorted(&provider.required_scopes.kyc),
+            captcha_fail_open: self.captcha_failure_mode == CaptchaFailureMode::FailOpen,
+            accept_from: self.accept_from.map(|accept_from| accept_from.timestamp()),
+            accept_until: self
+                .accept_until
+                .map(|accept_until| accept_until.timestamp()),
+            allowed_audiences: sorted(
+                &self
+                    .signer
+                    .allowed_audiences
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+            ),
+        };
+
+        Sha256::digest(policy.try_to_vec().expect("Policy is always serializable")).into()
+    }
+}
+
+/// Settings deciding whether and for how long a user is attested, hashed in a fixed field order
+#[derive(BorshSerialize)]
+struct VerificationPolicy {
+    uniqueness_case_statuses: Vec<u8>,
+    strict_kyc_liveness: bool,
+    require_liveness_audit: bool,
+    kyc_validity_period: Option<u64>,
+    max_verification_cases: Option<u64>,
+    refuse_on_kyc_rejected: bool,
+    min_account_age: Option<u64>,
+    allow_named_sub_accounts: bool,
+    require_claimer_binding: bool,
+    claimer_identity_window: Option<u64>,
+    one_time_claims: bool,
+    jwt_lifetime: u64,
+    kyc_jwt_lifetime: Option<u64>,
+    uniqueness_scopes: Vec<String>,
+    kyc_scopes: Vec<String>,
+    captcha_fail_open: bool,
+    accept_from: Option<i64>,
+    accept_until: Option<i64>,
+    allowed_audiences: Vec<String>,
+}
+
+/// Readiness degradation on a high rate of failed verification requests
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
+pub struct ErrorRateThresholdConfig {
+    /// Rate of failed requests from 0 to 1 above which the instance is unready
+    pub max_error_rate: f64,
+    /// Window in seconds the error rate is tracked within, reset afterwards
+    #[serde(default = "default_error_rate_window")]
+    pub window: u64,
+    /// Minimum number of requests within the window to judge the error rate by
+    #[serde(default = "default_error_rate_min_requests")]
+    pub min_requests: u32,
+}
+
+/// Renaming of verification response fields for front-ends expecting other names
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
+pub struct ResponseFieldsConfig {
+    /// New names by the default ones, e.g. `m` to `message`
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// Whether to keep fields under the default names along with the aliases
+    #[serde(default)]
+    pub keep_default_names: bool,
+}
+
+impl ResponseFieldsConfig {
+    /// Checks that aliases are unique and don't collide with any of the response field names,
+    /// since colliding fields would silently overwrite each other
+    pub fn check_aliases(&self, field_names: &[&str]) -> Result<(), AppError> {
+        let mut aliases = HashSet::new();
+
+        for (name, alias) in &self.aliases {
+            if field_names.contains(&alias.as_str()) {
+                return Err(AppError::Generic(format!(
+                    "Alias `{alias}` of response field `{name}` collides with a response field name"
+                )));
+            }
+            if !aliases.insert(alias) {
+                return Err(AppError::Generic(format!(
+                    "Alias `{alias}` is configured for multiple response fields"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serializes a verification response with fields renamed or aliased as configured
+    pub fn apply(&self, res: &impl Serialize) -> serde_json::Value {
+        let mut value = serde_json::to_value(res).expect("Verification response is serializable");
+
+        if let serde_json::Value::Object(fields) = &mut value {
+            for (name, alias) in &self.aliases {
+                let field = if self.keep_default_names {
+                    fields.get(name).cloned()
+                } else {
+                    fields.remove(name)
+                };
+                if let Some(field) = field {
+                    fields.insert(alias.clone(), field);
+                }
+            }
+        }
+
+        value
+    }
+}
+
+/// Permanent restriction of issuing a token once per external account
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
+pub struct OneTimeClaimsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// File to persist claimed external accounts to, kept in memory only if not set
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// Limit of tokens issued per external account within a time window
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
+pub struct ExtAccountRateLimitConfig {
+    pub max_tokens: u32,
+    /// Window in seconds
+    pub window: u64,
+}
+
+/// Compression of responses negotiated with `Accept-Encoding` header
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
+pub struct CompressionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Minimum size of a response body in bytes to be compressed
+    #[serde(default = "default_compression_min_size")]
+    pub min_size: u16,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_size: default_compression_min_size(),
+        }
+    }
+}
+
+/// Level of per-request access log lines
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(crate = "near_sdk::serde", rename_all = "lowercase")]
+pub enum AccessLogLevel {
+    Off,
+    Trace,
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+/// Response for an authenticated user without any verification case
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(crate = "near_sdk::serde", rename_all = "lowercase")]
+pub enum UnverifiedUserResponse {
+    /// Fail as missed face verification
+    #[default]
+    Error,
+    /// Respond with unverified status
+    Status,
+}
+
+/// Handling of requests when the captcha provider is unavailable, user failures are always refused
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum CaptchaFailureMode {
+    /// Accept requests as if captcha was passed
+    FailOpen,
+    /// Refuse requests with captcha error
+    #[default]
+    FailClosed,
+}
+
+pub fn load_config() -> Result<AppConfig, AppError> {
+    let config = config::Config::builder()
         // Load default set of configuration
         .add_source(config::File::with_name("config/default"))
         // Overlay configuration with local configuration
         .add_source(config::File::with_name("config/local").required(false))
         .build()
-        .and_then(|config| config.try_deserialize())
+        .map_err(|e| AppError::Generic(format!("Configuration loading failure. Error: {e}")))?;
+
+    // Signing key is loaded ahead to fail with a typed error instead of a generic deserialization one
+    let credentials = config
+        .get::<HashMap<String, String>>("signer.credentials")
+        .unwrap_or_default();
+    SignerCredentials::load(&credentials)?;
+
+    config
+        .try_deserialize()
+        .map_err(|e| AppError::Generic(format!("Configuration loading failure. Error: {e}")))
+}
+
+fn default_compression_min_size() -> u16 {
+    1024
+}
+
+fn default_normalize_claimer() -> bool {
+    true
 }
 
 fn default_allow_named_sub_accounts() -> bool {
     false
 }
+
+fn default_max_batch_concurrency() -> usize {
+    8
+}
+
+fn default_max_batch_size() -> usize {
+    100
+}
+
+fn default_error_rate_window() -> u64 {
+    60
+}
+
+fn default_error_rate_min_requests() -> u32 {
+    10
+}
@@ -1,16 +1,25 @@
 use axum::{http::StatusCode, response::IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use near_sdk::serde::Serialize;
 use near_sdk::serde_json::json;
 
+/// Version of the `reason` values set, bumped on any rename or removal of a value
+pub const REJECT_REASON_VERSION: u8 = 1;
+
 #[derive(thiserror::Error, Debug)]
 pub enum AppError {
     #[error("Signing error")]
     SigningError,
+    #[error("Signing key loading failure: {0}")]
+    KeyLoad(String),
     #[error("User face verification were rejected")]
     FaceVerificationRejected,
     #[error("User face verification is missed")]
     FaceVerificationMissed,
     #[error("Http request timed out: {0}")]
     TimeoutError(String),
+    #[error("Request budget exceeded: {0}")]
+    DeadlineExceeded(String),
     #[error("Http request failed: {0}")]
     ReqwestError(reqwest::Error),
     #[error("JSON parse failure: {0}")]
@@ -23,12 +32,203 @@ pub enum AppError {
     NotAllowedNamedSubAccount(near_sdk::AccountId),
     #[error("Captcha error: {0}")]
     CaptchaError(#[from] crate::captcha::CaptchaError),
+    #[error("Malformed request: {0}")]
+    MalformedRequest(String),
+    #[error("Signing rate limit exceeded")]
+    SigningRateLimited,
+    #[error("Claimer {0} was recently verified with another external account")]
+    ClaimerIdentityConflict(near_sdk::AccountId),
+    #[error("Service is in maintenance mode")]
+    Maintenance,
+    #[error("User account is too new")]
+    AccountTooNew,
+    #[error("User token lacks required scopes")]
+    InsufficientScope,
+    #[error("Claimer account control isn't proven")]
+    ChallengeFailed,
+    #[error("User KYC was rejected")]
+    KycRejected,
+    #[error("Too many concurrent requests from a client")]
+    TooManyRequests,
+    #[error("External account {0:?} was already issued a token")]
+    AlreadyClaimed(crate::ExternalAccountId),
+    #[error("External account {0:?} exceeded tokens rate limit")]
+    ExtAccountRateLimited(crate::ExternalAccountId),
+    #[error("User authorization is expired or revoked")]
+    AuthorizationExpired,
+    #[error("OAuth token refresh failed: {0}")]
+    TokenRefreshFailed(String),
+    #[error("Upstream response is truncated: {0}")]
+    UpstreamTruncated(String),
+    #[error("Upstream response exceeds {0} bytes")]
+    UpstreamResponseTooLarge(usize),
+    #[error("Captcha token was already used")]
+    CaptchaReused,
+    #[error("Challenge nonce was already used")]
+    NonceReused,
+    #[error("Unexpected request fields: {0:?}")]
+    UnknownFields(Vec<String>),
+    #[error("Unexpected upstream response content type `{0}`")]
+    UnexpectedContentType(String),
+    #[error("Unexpected provider API version `{0}`")]
+    UnexpectedApiVersion(String),
+    #[error("Not allowed token audience {0}")]
+    NotAllowedAudience(near_sdk::AccountId),
+    #[error("Claimer {0} isn't bound to the verified user")]
+    ClaimerNotBound(near_sdk::AccountId),
+    #[error("Aggregated tokens belong to different users")]
+    TokenUserMismatch,
+    #[error("Time of the approved user verification is unknown")]
+    UnknownVerificationTime,
+    #[error("Request is outside of the campaign window")]
+    OutsideCampaignWindow {
+        accept_from: Option<DateTime<Utc>>,
+        accept_until: Option<DateTime<Utc>>,
+    },
+}
+
+/// Machine readable error code attached to error responses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorCode(pub &'static str);
+
+/// Client facing error message attached to error responses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorMessage(pub &'static str);
+
+/// Stable client facing reason of a failed request, a coarser grouping of error codes
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum RejectReason {
+    /// Malformed request body or parameters
+    InvalidRequest,
+    /// Claimer account isn't allowed, bound or controlled by the requester
+    AccountNotAllowed,
+    /// Verified account is younger than required
+    AccountTooNew,
+    /// Captcha verification failed or was reused
+    CaptchaFailed,
+    /// User didn't complete face verification
+    UniquenessMissing,
+    /// User face verification was rejected
+    UniquenessRejected,
+    /// User KYC was rejected
+    KycRejected,
+    /// Identity or claimer was already verified and is blocked from repeated claims
+    Blocked,
+    /// User authorization is expired, revoked or lacks scopes
+    AuthorizationFailed,
+    /// Too many requests, could be retried later
+    RateLimited,
+    /// Service doesn't accept requests right now
+    Unavailable,
+    /// Verification provider or captcha service failed
+    UpstreamFailure,
+    /// Request or upstream service took too long
+    Timeout,
+    /// User verification failed for other reasons
+    VerificationFailed,
+    /// Internal server failure
+    Internal,
+}
+
+impl AppError {
+    pub fn reason(&self) -> RejectReason {
+        match self {
+            Self::MalformedRequest(_) | Self::UnknownFields(_) | Self::NotAllowedAudience(_) => {
+                RejectReason::InvalidRequest
+            }
+            Self::NotAllowedNamedSubAccount(_)
+            | Self::ChallengeFailed
+            | Self::NonceReused
+            | Self::ClaimerNotBound(_) => RejectReason::AccountNotAllowed,
+            Self::AccountTooNew => RejectReason::AccountTooNew,
+            Self::SuspiciousUser | Self::CaptchaError(_) | Self::CaptchaReused => {
+                RejectReason::CaptchaFailed
+            }
+            Self::FaceVerificationMissed => RejectReason::UniquenessMissing,
+            Self::FaceVerificationRejected => RejectReason::UniquenessRejected,
+            Self::KycRejected => RejectReason::KycRejected,
+            Self::AlreadyClaimed(_) | Self::ClaimerIdentityConflict(_) => RejectReason::Blocked,
+            Self::AuthorizationExpired | Self::TokenRefreshFailed(_) | Self::InsufficientScope => {
+                RejectReason::AuthorizationFailed
+            }
+            Self::SigningRateLimited | Self::TooManyRequests | Self::ExtAccountRateLimited(_) => {
+                RejectReason::RateLimited
+            }
+            Self::Maintenance | Self::OutsideCampaignWindow { .. } => RejectReason::Unavailable,
+            Self::ReqwestError(_)
+            | Self::UpstreamTruncated(_)
+            | Self::UpstreamResponseTooLarge(_)
+            | Self::UnexpectedContentType(_)
+            | Self::UnexpectedApiVersion(_) => RejectReason::UpstreamFailure,
+            Self::TimeoutError(_) | Self::DeadlineExceeded(_) => RejectReason::Timeout,
+            Self::Generic(_) | Self::TokenUserMismatch | Self::UnknownVerificationTime => {
+                RejectReason::VerificationFailed
+            }
+            Self::SigningError | Self::KeyLoad(_) | Self::ParseError(_) => RejectReason::Internal,
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        ErrorCode(match self {
+            Self::SigningError => "signing_error",
+            Self::KeyLoad(_) => "key_load_failure",
+            Self::FaceVerificationRejected => "face_verification_rejected",
+            Self::FaceVerificationMissed => "face_verification_missed",
+            Self::TimeoutError(_) => "upstream_timeout",
+            Self::DeadlineExceeded(_) => "deadline_exceeded",
+            Self::ReqwestError(_) => "upstream_failure",
+            Self::ParseError(_) => "parse_error",
+            Self::Generic(_) => "verification_failure",
+            Self::SuspiciousUser => "suspicious_user",
+            Self::NotAllowedNamedSubAccount(_) => "not_allowed_account",
+            Self::CaptchaError(_) => "captcha_error",
+            Self::MalformedRequest(_) => "malformed_request",
+            Self::SigningRateLimited => "signing_rate_limited",
+            Self::ClaimerIdentityConflict(_) => "claimer_identity_conflict",
+            Self::Maintenance => "maintenance",
+            Self::AccountTooNew => "account_too_new",
+            Self::InsufficientScope => "insufficient_scope",
+            Self::ChallengeFailed => "challenge_failed",
+            Self::NonceReused => "nonce_reused",
+            Self::UnknownFields(_) => "unknown_fields",
+            Self::KycRejected => "kyc_rejected",
+            Self::TooManyRequests => "too_many_requests",
+            Self::AlreadyClaimed(_) => "already_claimed",
+            Self::ExtAccountRateLimited(_) => "ext_account_rate_limited",
+            Self::AuthorizationExpired => "authorization_expired",
+            Self::TokenRefreshFailed(_) => "token_refresh_failed",
+            Self::UpstreamTruncated(_) => "upstream_truncated",
+            Self::UpstreamResponseTooLarge(_) => "upstream_response_too_large",
+            Self::CaptchaReused => "captcha_reused",
+            Self::UnexpectedContentType(_) => "unexpected_content_type",
+            Self::UnexpectedApiVersion(_) => "unexpected_api_version",
+            Self::NotAllowedAudience(_) => "not_allowed_audience",
+            Self::ClaimerNotBound(_) => "claimer_not_bound",
+            Self::TokenUserMismatch => "token_user_mismatch",
+            Self::UnknownVerificationTime => "unknown_verification_time",
+            Self::OutsideCampaignWindow { .. } => "outside_campaign_window",
+        })
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
+        let code = self.code();
+        let reason = self.reason();
+        let window = match &self {
+            Self::OutsideCampaignWindow {
+                accept_from,
+                accept_until,
+            } => Some(json!({ "accept_from": accept_from, "accept_until": accept_until })),
+            _ => None,
+        };
+        let fields = match &self {
+            Self::UnknownFields(fields) => Some(fields.clone()),
+            _ => None,
+        };
         let (status, err_msg) = match self {
-            Self::SigningError | Self::ParseError(_) => {
+            Self::SigningError | Self::KeyLoad(_) | Self::ParseError(_) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
             }
             Self::FaceVerificationRejected => {
@@ -38,17 +238,116 @@ impl IntoResponse for AppError {
                 StatusCode::UNAUTHORIZED,
                 "Face verification weren't completed",
             ),
-            Self::ReqwestError(_) | Self::Generic(_) | Self::TimeoutError(_) => {
-                (StatusCode::UNAUTHORIZED, "User verification failure")
-            }
-            Self::CaptchaError(_) => (StatusCode::UNAUTHORIZED, "Captcha error"),
-            Self::SuspiciousUser => (StatusCode::UNAUTHORIZED, "Suspicious user"),
+            Self::TimeoutError(_) => (
+                StatusCode::GATEWAY_TIMEOUT,
+                "Upstream service timed out, please retry",
+            ),
+            Self::DeadlineExceeded(_) => (
+                StatusCode::GATEWAY_TIMEOUT,
+                "Request took too long, please retry",
+            ),
+            Self::ReqwestError(_) => (
+                StatusCode::BAD_GATEWAY,
+                "Upstream service failure, please retry",
+            ),
+            Self::Generic(_) => (StatusCode::UNAUTHORIZED, "User verification failure"),
+            Self::CaptchaError(_) => (StatusCode::FORBIDDEN, "Captcha error"),
+            Self::CaptchaReused => (StatusCode::FORBIDDEN, "Captcha was already used"),
+            Self::SuspiciousUser => (StatusCode::FORBIDDEN, "Suspicious user"),
             Self::NotAllowedNamedSubAccount(_) => (
-                StatusCode::UNAUTHORIZED,
+                StatusCode::FORBIDDEN,
                 "Allowed only implicit account id or named sub-account from .near root account",
             ),
+            Self::MalformedRequest(_) => (StatusCode::BAD_REQUEST, "Malformed request"),
+            Self::UnknownFields(_) => (StatusCode::BAD_REQUEST, "Request has unexpected fields"),
+            Self::SigningRateLimited => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Service is busy, please retry later",
+            ),
+            Self::ClaimerIdentityConflict(_) => (
+                StatusCode::CONFLICT,
+                "Account was recently verified with another identity",
+            ),
+            Self::Maintenance => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Service is under maintenance, please retry later",
+            ),
+            Self::AccountTooNew => (StatusCode::FORBIDDEN, "Verified account is too new"),
+            Self::InsufficientScope => {
+                (StatusCode::FORBIDDEN, "Authorization lacks required scopes")
+            }
+            Self::ChallengeFailed => (
+                StatusCode::UNAUTHORIZED,
+                "Failed to prove claimer account control",
+            ),
+            Self::NonceReused => (
+                StatusCode::UNAUTHORIZED,
+                "Challenge was already used, please request a new one",
+            ),
+            Self::KycRejected => (StatusCode::UNAUTHORIZED, "KYC was rejected"),
+            Self::TooManyRequests => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many requests, please retry later",
+            ),
+            Self::AlreadyClaimed(_) => (StatusCode::CONFLICT, "Identity was already verified"),
+            Self::ExtAccountRateLimited(_) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many verifications of the identity, please retry later",
+            ),
+            Self::AuthorizationExpired => (
+                StatusCode::UNAUTHORIZED,
+                "Authorization expired, please retry",
+            ),
+            Self::TokenRefreshFailed(_) => (
+                StatusCode::BAD_GATEWAY,
+                "Authorization refresh failed, please retry",
+            ),
+            Self::UpstreamTruncated(_) => (
+                StatusCode::BAD_GATEWAY,
+                "Verification provider response was interrupted, please retry",
+            ),
+            Self::UpstreamResponseTooLarge(_) => (
+                StatusCode::BAD_GATEWAY,
+                "Unexpected verification provider response",
+            ),
+            Self::UnexpectedContentType(_) => (
+                StatusCode::BAD_GATEWAY,
+                "Unexpected verification provider response",
+            ),
+            Self::UnexpectedApiVersion(_) => (
+                StatusCode::BAD_GATEWAY,
+                "Unexpected verification provider response",
+            ),
+            Self::NotAllowedAudience(_) => {
+                (StatusCode::BAD_REQUEST, "Token audience isn't allowed")
+            }
+            Self::ClaimerNotBound(_) => (
+                StatusCode::FORBIDDEN,
+                "Claimer account isn't linked to the verified identity",
+            ),
+            Self::TokenUserMismatch => (
+                StatusCode::FORBIDDEN,
+                "Tokens belong to different verified identities",
+            ),
+            Self::UnknownVerificationTime => {
+                (StatusCode::UNAUTHORIZED, "Verification time is unknown")
+            }
+            Self::OutsideCampaignWindow { .. } => {
+                (StatusCode::FORBIDDEN, "Verification campaign is not active")
+            }
         };
-        (status, Json(json!({ "error": err_msg }))).into_response()
+        let mut body = json!({ "error": err_msg, "code": code.0, "reason": reason });
+        if let Some(window) = window {
+            body["window"] = window;
+        }
+        if let Some(fields) = fields {
+            body["fields"] = json!(fields);
+        }
+        let mut res = (status, Json(body)).into_response();
+        res.extensions_mut().insert(code);
+        res.extensions_mut().insert(reason);
+        res.extensions_mut().insert(ErrorMessage(err_msg));
+        res
     }
 }
 
@@ -67,3 +366,158 @@ impl From<reqwest::Error> for AppError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{AppError, ErrorCode, RejectReason};
+    use axum::{http::StatusCode, response::IntoResponse};
+    use near_sdk::serde_json::{self, json};
+
+    #[test]
+    fn test_reject_reason() {
+        let cases = [
+            (
+                AppError::MalformedRequest("bad".to_owned()),
+                "invalid_request",
+            ),
+            (
+                AppError::NotAllowedNamedSubAccount("test.testnet".parse().unwrap()),
+                "account_not_allowed",
+            ),
+            (AppError::ChallengeFailed, "account_not_allowed"),
+            (AppError::SuspiciousUser, "captcha_failed"),
+            (AppError::CaptchaReused, "captcha_failed"),
+            (AppError::FaceVerificationMissed, "uniqueness_missing"),
+            (AppError::FaceVerificationRejected, "uniqueness_rejected"),
+            (AppError::KycRejected, "kyc_rejected"),
+            (
+                AppError::ClaimerIdentityConflict("test.near".parse().unwrap()),
+                "blocked",
+            ),
+            (AppError::AuthorizationExpired, "authorization_failed"),
+            (AppError::TooManyRequests, "rate_limited"),
+            (AppError::SigningRateLimited, "rate_limited"),
+            (AppError::Maintenance, "unavailable"),
+            (
+                AppError::UnexpectedContentType("text/html".to_owned()),
+                "upstream_failure",
+            ),
+            (AppError::DeadlineExceeded("budget".to_owned()), "timeout"),
+            (
+                AppError::Generic("failure".to_owned()),
+                "verification_failed",
+            ),
+            (AppError::UnknownVerificationTime, "verification_failed"),
+            (AppError::SigningError, "internal"),
+        ];
+
+        for (error, expected) in cases {
+            assert_eq!(serde_json::to_value(error.reason()).unwrap(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_and_code() {
+        let cases = [
+            (
+                AppError::SuspiciousUser,
+                StatusCode::FORBIDDEN,
+                "suspicious_user",
+            ),
+            (
+                AppError::NotAllowedNamedSubAccount("test.testnet".parse().unwrap()),
+                StatusCode::FORBIDDEN,
+                "not_allowed_account",
+            ),
+            (
+                AppError::FaceVerificationMissed,
+                StatusCode::UNAUTHORIZED,
+                "face_verification_missed",
+            ),
+            (
+                AppError::FaceVerificationRejected,
+                StatusCode::UNAUTHORIZED,
+                "face_verification_rejected",
+            ),
+            (
+                AppError::KycRejected,
+                StatusCode::UNAUTHORIZED,
+                "kyc_rejected",
+            ),
+            (
+                AppError::CaptchaError(crate::captcha::CaptchaError::InvalidAction),
+                StatusCode::FORBIDDEN,
+                "captcha_error",
+            ),
+            (
+                AppError::CaptchaReused,
+                StatusCode::FORBIDDEN,
+                "captcha_reused",
+            ),
+            (
+                AppError::TimeoutError("timeout".to_owned()),
+                StatusCode::GATEWAY_TIMEOUT,
+                "upstream_timeout",
+            ),
+            (
+                AppError::Generic("failure".to_owned()),
+                StatusCode::UNAUTHORIZED,
+                "verification_failure",
+            ),
+            (
+                AppError::UnknownVerificationTime,
+                StatusCode::UNAUTHORIZED,
+                "unknown_verification_time",
+            ),
+            (
+                AppError::SigningError,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "signing_error",
+            ),
+            (
+                AppError::TokenUserMismatch,
+                StatusCode::FORBIDDEN,
+                "token_user_mismatch",
+            ),
+        ];
+
+        for (error, status, code) in cases {
+            let res = error.into_response();
+            assert_eq!(res.status(), status);
+            assert_eq!(res.extensions().get::<ErrorCode>(), Some(&ErrorCode(code)));
+
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+            assert_eq!(body["code"], code);
+        }
+
+        // Connection failures are upstream problems
+        let error = reqwest::Client::new()
+            .get("http://127.0.0.1:1")
+            .send()
+            .await
+            .unwrap_err();
+        let res = AppError::from(error).into_response();
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            res.extensions().get::<ErrorCode>(),
+            Some(&ErrorCode("upstream_failure"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_reason_in_body() {
+        let res = AppError::KycRejected.into_response();
+        assert_eq!(
+            res.extensions().get::<RejectReason>(),
+            Some(&RejectReason::KycRejected)
+        );
+
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({ "error": "KYC was rejected", "code": "kyc_rejected", "reason": "kyc_rejected" })
+        );
+    }
+}
@@ -0,0 +1,20 @@
+use crate::error::AppError;
+use crate::verification_provider::FractalUser;
+use futures_util::future::BoxFuture;
+
+/// Extension point for custom policy applied to a fetched user before token creation,
+/// e.g. cross-referencing an internal denylist or adjusting KYC status
+pub trait VerifiedUserHook: Send + Sync {
+    /// Transforms the user in place, or refuses the verification with an error
+    fn apply<'a>(&'a self, user: &'a mut FractalUser) -> BoxFuture<'a, Result<(), AppError>>;
+}
+
+/// Hook leaving users unchanged
+#[derive(Debug, Default)]
+pub struct NoopUserHook;
+
+impl VerifiedUserHook for NoopUserHook {
+    fn apply<'a>(&'a self, _user: &'a mut FractalUser) -> BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async { Ok(()) })
+    }
+}
@@ -0,0 +1,97 @@
+use crate::error::AppError;
+use base64::{engine::general_purpose, Engine};
+use near_crypto::{SecretKey, Signature};
+use near_sdk::{
+    serde::{Deserialize, Serialize},
+    serde_json, AccountId,
+};
+
+/// JWT header for EdDSA (ed25519) signed tokens
+static JWT_HEADER: &str = r#"{"alg":"EdDSA","typ":"JWT"}"#;
+
+/// JWT claims mirroring verified account token
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct JwtClaims {
+    pub sub: AccountId,
+    pub ext_account: String,
+    pub kyc: bool,
+    /// Time of the user verification by the provider
+    pub verified_at: u64,
+    pub iat: u64,
+    pub exp: u64,
+    /// Time the token becomes valid at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+    /// Contract account id the token is intended for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<AccountId>,
+}
+
+/// Creates compact serialized JWT signed with ed25519 signing key
+pub fn encode(claims: &JwtClaims, signing_key: &SecretKey) -> Result<String, AppError> {
+    let header = general_purpose::URL_SAFE_NO_PAD.encode(JWT_HEADER);
+    let payload = general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?);
+    let signing_input = format!("{header}.{payload}");
+
+    let signature = match signing_key.sign(signing_input.as_bytes()) {
+        Signature::ED25519(signature) => signature.to_bytes(),
+        _ => return Err(AppError::SigningError),
+    };
+
+    Ok(format!(
+        "{signing_input}.{}",
+        general_purpose::URL_SAFE_NO_PAD.encode(signature)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, JwtClaims};
+    use crate::signer::decode_signature;
+    use base64::{engine::general_purpose, Engine};
+    use near_crypto::{KeyType, SecretKey};
+    use near_sdk::{serde_json, AccountId};
+
+    #[test]
+    fn test_jwt_encode() {
+        let signing_key = SecretKey::from_random(KeyType::ED25519);
+        let claims = JwtClaims {
+            sub: AccountId::new_unchecked("test.near".to_owned()),
+            ext_account: "f20181bafc0c11edbe560242ac120002".to_owned(),
+            kyc: true,
+            verified_at: 500,
+            iat: 1_000,
+            exp: 2_000,
+            nbf: None,
+            aud: None,
+        };
+
+        let jwt = encode(&claims, &signing_key).unwrap();
+        let parts = jwt.split('.').collect::<Vec<_>>();
+        assert_eq!(parts.len(), 3);
+
+        let header = general_purpose::URL_SAFE_NO_PAD.decode(parts[0]).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&header).unwrap(),
+            serde_json::json!({ "alg": "EdDSA", "typ": "JWT" })
+        );
+
+        let payload = general_purpose::URL_SAFE_NO_PAD.decode(parts[1]).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<JwtClaims>(&payload).unwrap(),
+            claims
+        );
+
+        let signature = decode_signature(
+            KeyType::ED25519,
+            &general_purpose::URL_SAFE_NO_PAD.decode(parts[2]).unwrap(),
+        )
+        .unwrap();
+        let signing_input = format!("{}.{}", parts[0], parts[1]);
+        assert!(signature.verify(signing_input.as_bytes(), &signing_key.public_key()));
+
+        let other_key = SecretKey::from_random(KeyType::ED25519);
+        assert!(!signature.verify(signing_input.as_bytes(), &other_key.public_key()));
+    }
+}
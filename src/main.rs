@@ -1,28 +1,94 @@
 mod captcha;
+mod challenge;
 mod config;
 mod error;
+mod hook;
+mod jwt;
+mod middleware;
 mod signer;
+mod store;
 mod utils;
 mod verification_provider;
 
-use axum::{extract::State, routing::post, Json, Router};
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::{get, post},
+    Json, Router,
+};
 use base64::{engine::general_purpose, Engine};
 use captcha::CaptchaClient;
-use chrono::Utc;
-use error::AppError;
-use near_crypto::Signature;
+use challenge::{ChallengeReq, ChallengeResponse, ChallengeSolution};
+use chrono::{DateTime, Utc};
+use error::{AppError, ErrorCode, ErrorMessage, RejectReason, REJECT_REASON_VERSION};
+use futures_util::{Stream, StreamExt};
+use hook::{NoopUserHook, VerifiedUserHook};
+use jwt::JwtClaims;
+use near_crypto::{KeyType, SecretKey, Signature};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::AccountId;
+use sha2::{Digest, Sha256, Sha512_256};
+use tower_http::compression::{
+    predicate::{NotForContentType, Predicate, SizeAbove},
+    CompressionLayer,
+};
 use tower_http::cors::CorsLayer;
 use uuid::Uuid;
 
-use crate::config::AppConfig;
-use utils::{enable_logging, is_allowed_named_sub_account, set_heavy_panic};
+use crate::config::{AppConfig, CaptchaFailureMode, OneTimeClaimsConfig, UnverifiedUserResponse};
+use middleware::IpConcurrencyLimiter;
+use signer::{decode_signature, verify_signing_key, SigningRateLimiter, ED25519_SIGNATURE_LENGTH};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use store::{
+    ClaimedAccounts, ErrorRateWindow, ExpiringStore, FileClaimedAccounts, KeyedRateLimiter,
+    MemoryClaimedAccounts,
+};
+use utils::{
+    enable_logging, is_allowed_named_sub_account, is_implicit_account, parse_account_id,
+    set_heavy_panic,
+};
 use verification_provider::{
-    FractalClient, FractalTokenKind, FractalUser, OAuthToken, VerificationStatus,
+    ExtraClaim, FractalClient, FractalTokenKind, FractalUser, OAuthToken, PendingStep, ProviderId,
+    VerificationStatus,
 };
 
+/// Domain tag prefixing merkle leaf encoded tokens
+const LEAF_DOMAIN_TAG: &[u8; 16] = b"VERIF_ORACLE_V1\0";
+/// Length of merkle leaf encoded tokens in bytes
+const LEAF_LENGTH: usize = 16 + 32 + 32 + 8 + 8 + 1 + 1 + 1;
+
+/// Type of the typed data domain separator
+const TYPED_DATA_DOMAIN_TYPE: &str = "Domain(string name,string version)";
+/// Name of the typed data domain of signed tokens
+const TYPED_DATA_DOMAIN_NAME: &str = "verification-oracle";
+/// Version of the typed data domain of signed tokens
+const TYPED_DATA_DOMAIN_VERSION: &str = "1";
+/// Type of typed data encoded tokens
+const TYPED_DATA_TOKEN_TYPE: &str = "VerifiedAccountToken(string claimer,string ext_account,uint64 timestamp,uint64 verified_at,bool verified_kyc,uint8 kyc_code,uint8 provider_id)";
+
+/// Version of the signed token format, bumped on breaking changes of core fields
+const TOKEN_FORMAT_VERSION: u8 = 2;
+
+/// Number of leading and trailing chars of external account ids kept in logs
+const EXT_ACCOUNT_UNMASKED_CHARS: usize = 4;
+
+/// Whether external account ids are logged unmasked, enabled only in dev mode
+static LOG_UNMASKED_EXT_ACCOUNT: AtomicBool = AtomicBool::new(false);
+
+/// Header with API key of a caller
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// Maximum number of additional tokens of the same user aggregated in a verification request
+const MAX_ADDITIONAL_TOKENS: usize = 4;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Exit on any panic in any async task
@@ -32,7 +98,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv::dotenv().ok();
 
     enable_logging();
-    let config = config::load_config()?;
+    let config = match config::load_config() {
+        Ok(config) => config,
+        Err(e) => exit_on_startup_failure(e),
+    };
 
     // Log a base64 encoded ed25519 public key to be used in smart contract for signature verification
     tracing::info!(
@@ -48,50 +117,410 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         )
     );
 
+    // Log the verification policy hash for contracts to pin the expected policy
+    if config.signer.policy_hash {
+        tracing::info!(
+            "Verification policy hash (base64 encoded): {}",
+            general_purpose::STANDARD.encode(config.policy_hash())
+        );
+    }
+
     let addr = config
         .listen_address
         .parse()
         .expect("Can't parse socket address");
 
-    let state = AppState::new(config.clone())?;
-
-    let app = Router::new()
-        .route("/verify", post(verify))
-        .layer(CorsLayer::permissive())
-        .with_state(state);
+    let state = match AppState::new(config.clone()) {
+        Ok(state) => state,
+        Err(e) => exit_on_startup_failure(e),
+    };
+    reload_on_hangup(state.clone())?;
+    let app = create_router(state);
 
     tracing::debug!("Server listening on {}", addr);
 
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
 
     Ok(())
 }
 
+pub fn create_router(state: AppState) -> Router {
+    let router = Router::new()
+        // Streamed and batch verifications record their outcomes per verification
+        .route(
+            "/verify",
+            post(verify).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                middleware::track_error_rate,
+            )),
+        )
+        .route("/verify/stream", post(verify_stream))
+        .route("/verify/batch", post(verify_batch))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::reject_unknown_fields,
+        ))
+        .route("/challenge", get(challenge))
+        .route("/decode", post(decode))
+        .route("/pubkey", get(public_key))
+        .route("/ready", get(ready))
+        .route("/health", get(health))
+        .route("/version", get(version));
+
+    // Debug endpoint is served only if explicitly enabled
+    let router = if state.config.verify_signature_endpoint {
+        router.route("/verify_signature", post(verify_signature))
+    } else {
+        router
+    };
+
+    // Error format is negotiated outside of the IP limit, so its rejections are negotiated too
+    let router = router
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::limit_per_ip,
+        ))
+        .layer(axum::middleware::from_fn(
+            middleware::negotiate_error_format,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::access_log,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::instance_header,
+        ));
+
+    let compression = &state.config.compression;
+    let router = if compression.enabled {
+        // Event streams aren't compressed to not buffer progress events
+        router.layer(
+            CompressionLayer::new().compress_when(
+                SizeAbove::new(compression.min_size)
+                    .and(NotForContentType::const_new("text/event-stream")),
+            ),
+        )
+    } else {
+        router
+    };
+
+    router.layer(CorsLayer::permissive()).with_state(state)
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub config: AppConfig,
     pub client: FractalClient,
     pub captcha: CaptchaClient,
+    pub signing_limiter: SigningRateLimiter,
+    pub claimer_identities: Option<ExpiringStore<AccountId, ExternalAccountId>>,
+    /// Tokens issued per external account
+    pub ext_account_limiter: Option<KeyedRateLimiter<String>>,
+    pub maintenance_mode: Arc<AtomicBool>,
+    pub skip_captcha: bool,
+    pub ip_limiter: Option<IpConcurrencyLimiter>,
+    pub instance_id: String,
+    pub claimed_accounts: Option<Arc<dyn ClaimedAccounts>>,
+    /// Custom policy applied to fetched users before token creation
+    pub user_hook: Arc<dyn VerifiedUserHook>,
+    /// Challenge nonces tokens were signed for, if challenges are single use
+    pub used_nonces: Option<ExpiringStore<String, ()>>,
+    /// Recent verification requests and their failures, if readiness depends on the error rate
+    pub error_rate: Option<ErrorRateWindow>,
 }
 
 impl AppState {
     pub fn new(config: AppConfig) -> Result<Self, AppError> {
+        LOG_UNMASKED_EXT_ACCOUNT.store(
+            should_log_unmasked_ext_account(
+                config.dev_log_ext_account,
+                std::env::var_os("DEV_MODE").is_some(),
+            ),
+            Ordering::Relaxed,
+        );
+        config.signer.check_expected_public_key()?;
+        config.signer.check_typed_data()?;
+        config.signer.check_leaf_format()?;
+        config.signer.check_not_before(Utc::now())?;
+        config.check_campaign_window()?;
+        config.response_fields.check_aliases(RESPONSE_FIELD_NAMES)?;
+
         Ok(Self {
             captcha: CaptchaClient::new(config.captcha.clone())?,
             client: FractalClient::create(config.verification_provider.clone())?,
+            signing_limiter: SigningRateLimiter::new(config.signer.max_signatures_per_second),
+            claimer_identities: config
+                .claimer_identity_window
+                .map(|window| ExpiringStore::new(Duration::from_secs(window))),
+            ext_account_limiter: config.ext_account_rate_limit.as_ref().map(|limit| {
+                KeyedRateLimiter::new(limit.max_tokens, Duration::from_secs(limit.window))
+            }),
+            maintenance_mode: Arc::new(AtomicBool::new(config.maintenance_mode)),
+            ip_limiter: config.max_requests_per_ip.map(IpConcurrencyLimiter::new),
+            claimed_accounts: create_claimed_accounts(&config.one_time_claims)?,
+            user_hook: Arc::new(NoopUserHook),
+            error_rate: config
+                .error_rate_threshold
+                .as_ref()
+                .map(|threshold| ErrorRateWindow::new(Duration::from_secs(threshold.window))),
+            used_nonces: config
+                .challenge
+                .single_use
+                .then(|| ExpiringStore::new(Duration::from_secs(config.challenge.lifetime))),
+            instance_id: config
+                .instance_id
+                .clone()
+                .or_else(utils::hostname)
+                .unwrap_or_else(|| "unknown".to_owned()),
+            skip_captcha: should_skip_captcha(
+                config.dev_skip_captcha,
+                std::env::var_os("DEV_MODE").is_some(),
+            ),
             config,
         })
     }
+
+    pub fn is_maintenance_mode(&self) -> bool {
+        self.maintenance_mode.load(Ordering::Relaxed)
+    }
+
+    pub fn set_maintenance_mode(&self, enabled: bool) {
+        self.maintenance_mode.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Records a verification outcome for the error rate, if readiness depends on it
+    pub fn record_verification(&self, failed: bool) {
+        if let Some(error_rate) = &self.error_rate {
+            error_rate.record(failed);
+        }
+    }
+
+    /// Checks if the recent error rate of verification requests is within the threshold, if configured
+    pub fn is_error_rate_ok(&self) -> bool {
+        match (&self.error_rate, &self.config.error_rate_threshold) {
+            (Some(error_rate), Some(threshold)) => {
+                let (requests, rate) = error_rate.error_rate();
+                requests < threshold.min_requests || rate <= threshold.max_error_rate
+            }
+            _ => true,
+        }
+    }
+
+    /// Marks a challenge nonce as used, rejecting nonces already used within the challenge lifetime
+    pub fn consume_nonce(&self, nonce: &str) -> Result<(), AppError> {
+        let used_nonces = match &self.used_nonces {
+            Some(used_nonces) => used_nonces,
+            None => return Ok(()),
+        };
+
+        if used_nonces.try_insert(nonce.to_owned(), ()) {
+            Ok(())
+        } else {
+            tracing::warn!("Challenge nonce `{nonce}` is reused");
+            Err(AppError::NonceReused)
+        }
+    }
+}
+
+/// Reports a startup failure and exits with non-zero code
+fn exit_on_startup_failure(e: AppError) -> ! {
+    tracing::error!("Startup failure. {e}");
+    std::process::exit(1)
+}
+
+/// Creates a store of claimed external accounts if one-time claims are enabled
+fn create_claimed_accounts(
+    config: &OneTimeClaimsConfig,
+) -> Result<Option<Arc<dyn ClaimedAccounts>>, AppError> {
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    Ok(Some(match &config.path {
+        Some(path) => Arc::new(FileClaimedAccounts::open(path)?),
+        None => {
+            tracing::warn!("One-time claims aren't persisted and reset on restart");
+            Arc::new(MemoryClaimedAccounts::default())
+        }
+    }))
+}
+
+/// Checks if captcha verification should be skipped for local development
+fn should_skip_captcha(dev_skip_captcha: bool, dev_mode: bool) -> bool {
+    match (dev_skip_captcha, dev_mode) {
+        (true, true) => {
+            tracing::warn!("DEV MODE: captcha verification is skipped, never use it in production");
+            true
+        }
+        (true, false) => {
+            tracing::error!("Captcha skip is refused to activate without `DEV_MODE` env variable");
+            false
+        }
+        _ => false,
+    }
+}
+
+/// Checks if external account ids should be logged unmasked for local development
+fn should_log_unmasked_ext_account(dev_log_ext_account: bool, dev_mode: bool) -> bool {
+    match (dev_log_ext_account, dev_mode) {
+        (true, true) => {
+            tracing::warn!(
+                "DEV MODE: external account ids are logged unmasked, never use it in production"
+            );
+            true
+        }
+        (true, false) => {
+            tracing::error!(
+                "Unmasked external account logging is refused to activate without `DEV_MODE` env variable"
+            );
+            false
+        }
+        _ => false,
+    }
+}
+
+/// Reloads hot-reloadable configuration on SIGHUP
+fn reload_on_hangup(state: AppState) -> Result<(), std::io::Error> {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match config::load_config() {
+                Ok(config) => {
+                    tracing::warn!("Maintenance mode is set to {}", config.maintenance_mode);
+                    state.set_maintenance_mode(config.maintenance_mode);
+
+                    let ignored_case_ids = config.verification_provider.ignored_case_ids;
+                    tracing::warn!("Ignored verification cases: {ignored_case_ids:?}");
+                    state.client.set_ignored_case_ids(ignored_case_ids);
+                }
+                Err(e) => tracing::error!("Configuration reload failure. Error: {e:?}"),
+            }
+        }
+    });
+
+    Ok(())
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(crate = "near_sdk::serde")]
 pub struct VerificationReq {
-    pub claimer: AccountId,
+    /// Claimer account id, validated after optional normalization
+    pub claimer: String,
     #[serde(flatten)]
     pub fractal_token: FractalTokenKind,
+    /// Whether to include JWT attestation into the response
+    #[serde(default)]
+    pub jwt: bool,
+    /// Solved challenge proving the claimer account control
+    #[serde(default)]
+    pub challenge: Option<ChallengeSolution>,
+    /// Tenant to sign the token with its derived signing key
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Whether to only check if verification would succeed, without signing
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Contract account id the token is intended for, must be allowed by config
+    #[serde(default)]
+    pub audience: Option<AccountId>,
+    /// Other tokens of the same user, e.g. of other sessions, aggregated for the highest assurance
+    #[serde(default)]
+    pub additional_tokens: Vec<FractalTokenKind>,
+}
+
+impl VerificationReq {
+    /// Fields of any request
+    const COMMON_FIELDS: &'static [&'static str] = &[
+        "claimer",
+        "jwt",
+        "challenge",
+        "tenant",
+        "dry_run",
+        "audience",
+        "additional_tokens",
+    ];
+    /// Fields of a request with authorization code
+    const AUTHORIZATION_CODE_FIELDS: &'static [&'static str] =
+        &["code", "captcha", "redirect_uri", "redirect_hint"];
+    /// Fields of a request with client provided token
+    const TOKEN_FIELDS: &'static [&'static str] = &["token", "redirect_uri"];
+    /// Fields of a raw Fractal token
+    const RAW_TOKEN_FIELDS: &'static [&'static str] = &[
+        "access_token",
+        "refresh_token",
+        "token_type",
+        "expires_in",
+        "created_at",
+        "scope",
+    ];
+    /// Fields of an oracle encoded token in JSON form
+    const OAUTH_TOKEN_FIELDS: &'static [&'static str] =
+        &["access_token", "refresh_token", "expires_at", "scopes"];
+
+    /// Lists fields of a JSON request unknown to the token variant it parses as,
+    /// including fields of additional tokens
+    pub fn unknown_fields(value: &near_sdk::serde_json::Value) -> Vec<String> {
+        let fields = match value.as_object() {
+            Some(fields) => fields,
+            None => return vec![],
+        };
+
+        let mut unknown = Self::unknown_token_fields(fields, Self::COMMON_FIELDS);
+        if let Some(tokens) = fields
+            .get("additional_tokens")
+            .and_then(|tokens| tokens.as_array())
+        {
+            for (idx, token) in tokens.iter().enumerate() {
+                if let Some(token) = token.as_object() {
+                    unknown.extend(
+                        Self::unknown_token_fields(token, &[])
+                            .into_iter()
+                            .map(|name| format!("additional_tokens[{idx}].{name}")),
+                    );
+                }
+            }
+        }
+        unknown
+    }
+
+    /// Lists fields unknown to the token variant an object parses as, besides the other known fields.
+    /// Token object fields are checked against the raw Fractal or oracle encoded token by its shape
+    fn unknown_token_fields(
+        fields: &near_sdk::serde_json::Map<String, near_sdk::serde_json::Value>,
+        known_fields: &[&str],
+    ) -> Vec<String> {
+        let variant_fields = if fields.contains_key("code") {
+            Self::AUTHORIZATION_CODE_FIELDS
+        } else {
+            Self::TOKEN_FIELDS
+        };
+
+        let mut unknown = fields
+            .keys()
+            .filter(|name| {
+                !known_fields.contains(&name.as_str()) && !variant_fields.contains(&name.as_str())
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if let Some(token) = fields.get("token").and_then(|token| token.as_object()) {
+            let token_fields = if token.contains_key("expires_at") {
+                Self::OAUTH_TOKEN_FIELDS
+            } else {
+                Self::RAW_TOKEN_FIELDS
+            };
+            unknown.extend(
+                token
+                    .keys()
+                    .filter(|name| !token_fields.contains(&name.as_str()))
+                    .map(|name| format!("token.{name}")),
+            );
+        }
+        unknown
+    }
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
@@ -100,12 +529,131 @@ pub struct VerifiedAccountToken {
     pub ext_account: ExternalAccountId,
     pub timestamp: u64,
     pub verified_kyc: bool,
+    pub provider_id: ProviderId,
+    /// Unix timestamp (seconds) of the user verification by the provider, after the fields consumers already decode
+    pub verified_at: u64,
+    /// Numeric KYC status code, see `VerificationStatus::code`
+    pub kyc_code: u8,
+    /// Contract account id the token is intended for, appended after other fields if audiences are configured
+    #[borsh_skip]
+    pub audience: Option<AccountId>,
+}
+
+impl VerifiedAccountToken {
+    /// Encodes the token as a fixed-width merkle leaf:
+    /// domain tag, sha256 of claimer, sha256 of external account id, little-endian timestamps and single byte flags
+    pub fn to_leaf(&self) -> Vec<u8> {
+        let mut leaf = Vec::with_capacity(LEAF_LENGTH);
+        leaf.extend_from_slice(LEAF_DOMAIN_TAG);
+        leaf.extend_from_slice(&Sha256::digest(self.claimer.as_bytes()));
+        leaf.extend_from_slice(&Sha256::digest(self.ext_account.as_ref().as_bytes()));
+        leaf.extend_from_slice(&self.timestamp.to_le_bytes());
+        leaf.extend_from_slice(&self.verified_at.to_le_bytes());
+        leaf.push(self.verified_kyc as u8);
+        leaf.push(self.kyc_code);
+        leaf.push(self.provider_id as u8);
+        leaf
+    }
+
+    /// Hashes the token as EIP-712 like typed data, with SHA-256 instead of keccak256:
+    /// `sha256(0x19 0x01 || domain separator || sha256(type hash || encoded fields))`,
+    /// strings are encoded as their hashes and numbers as 32 bytes big-endian words
+    pub fn typed_data_digest(&self) -> [u8; 32] {
+        let word = |value: u64| {
+            let mut word = [0u8; 32];
+            word[24..].copy_from_slice(&value.to_be_bytes());
+            word
+        };
+
+        let domain_separator = Sha256::new()
+            .chain_update(Sha256::digest(TYPED_DATA_DOMAIN_TYPE))
+            .chain_update(Sha256::digest(TYPED_DATA_DOMAIN_NAME))
+            .chain_update(Sha256::digest(TYPED_DATA_DOMAIN_VERSION))
+            .finalize();
+        let struct_hash = Sha256::new()
+            .chain_update(Sha256::digest(TYPED_DATA_TOKEN_TYPE))
+            .chain_update(Sha256::digest(self.claimer.as_bytes()))
+            .chain_update(Sha256::digest(self.ext_account.as_ref().as_bytes()))
+            .chain_update(word(self.timestamp))
+            .chain_update(word(self.verified_at))
+            .chain_update(word(self.verified_kyc as u64))
+            .chain_update(word(self.kyc_code as u64))
+            .chain_update(word(self.provider_id as u64))
+            .finalize();
+
+        Sha256::new()
+            .chain_update([0x19u8, 0x01])
+            .chain_update(domain_separator)
+            .chain_update(struct_hash)
+            .finalize()
+            .into()
+    }
+
+    /// Encodes the token fields as a CBOR map for non-Rust tooling
+    pub fn to_cbor(&self) -> Result<Vec<u8>, AppError> {
+        let token = CborToken {
+            claimer: self.claimer.to_string(),
+            ext_account: self.ext_account.to_string(),
+            timestamp: self.timestamp,
+            verified_at: self.verified_at,
+            verified_kyc: self.verified_kyc,
+            kyc_code: self.kyc_code,
+            provider_id: self.provider_id as u8,
+            audience: self.audience.as_ref().map(ToString::to_string),
+        };
+
+        let mut cbor = vec![];
+        ciborium::ser::into_writer(&token, &mut cbor).map_err(|e| {
+            AppError::Generic(format!("Failed to encode token as CBOR. Error: {e}"))
+        })?;
+        Ok(cbor)
+    }
+}
+
+/// CBOR representation of the token fields, with provider id as a number like in borsh
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CborToken {
+    pub claimer: String,
+    pub ext_account: String,
+    pub timestamp: u64,
+    pub verified_at: u64,
+    pub verified_kyc: bool,
+    pub kyc_code: u8,
+    pub provider_id: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
 }
 
 /// External account id represented as hexadecimal string
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[derive(BorshSerialize, BorshDeserialize, Clone, PartialEq)]
 pub struct ExternalAccountId(String);
 
+/// Masks all but leading and trailing chars to not leak user identities into logs
+impl std::fmt::Debug for ExternalAccountId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let chars = self.0.chars().count();
+
+        if LOG_UNMASKED_EXT_ACCOUNT.load(Ordering::Relaxed) {
+            write!(f, "ExternalAccountId({:?})", self.0)
+        } else if chars <= 2 * EXT_ACCOUNT_UNMASKED_CHARS {
+            write!(f, "ExternalAccountId(\"***\")")
+        } else {
+            let head = self
+                .0
+                .chars()
+                .take(EXT_ACCOUNT_UNMASKED_CHARS)
+                .collect::<String>();
+            let tail = self
+                .0
+                .chars()
+                .skip(chars - EXT_ACCOUNT_UNMASKED_CHARS)
+                .collect::<String>();
+            write!(f, "ExternalAccountId(\"{head}***{tail}\")")
+        }
+    }
+}
+
 impl std::fmt::Display for ExternalAccountId {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -118,6 +666,18 @@ impl AsRef<String> for ExternalAccountId {
     }
 }
 
+impl ExternalAccountId {
+    /// Derives a stable pseudonymous id as hex encoded `sha256(salt || id)`
+    pub fn pseudonymize(&self, salt: &str) -> Self {
+        let digest = Sha256::new()
+            .chain_update(salt.as_bytes())
+            .chain_update(self.0.as_bytes())
+            .finalize();
+
+        Self(digest.iter().map(|byte| format!("{byte:02x}")).collect())
+    }
+}
+
 impl From<Uuid> for ExternalAccountId {
     fn from(value: Uuid) -> Self {
         let mut buf = [0u8; uuid::fmt::Simple::LENGTH];
@@ -125,11 +685,28 @@ impl From<Uuid> for ExternalAccountId {
     }
 }
 
+/// Serialized field names of all verification responses, which response field aliases can't take
+const RESPONSE_FIELD_NAMES: &[&str] = &[
+    "m",
+    "sig",
+    "sigs",
+    "kyc",
+    "jwt",
+    "cbor",
+    "token",
+    "additional_tokens",
+    "pending_steps",
+    "uniqueness",
+    "would_succeed",
+];
+
 #[derive(Serialize, Debug)]
 #[serde(crate = "near_sdk::serde", untagged)]
 pub enum VerificationResponse {
     Approved(ApprovedResponse),
     Pending(PendingResponse),
+    Unverified(UnverifiedResponse),
+    DryRun(DryRunResponse),
 }
 
 /// Signed response for a fractal user with approved face verification
@@ -140,8 +717,34 @@ pub struct ApprovedResponse {
     pub message: String,
     #[serde(rename = "sig")]
     pub signature_ed25519: String,
-    #[serde(rename = "kyc")]
-    pub kyc_status: VerificationStatus,
+    /// Signatures of the signing key and co-signing keys, omitted if co-signing isn't configured
+    #[serde(rename = "sigs", skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<KeySignature>,
+    /// Omitted for callers not authorized to get KYC details
+    #[serde(rename = "kyc", skip_serializing_if = "Option::is_none")]
+    pub kyc_status: Option<VerificationStatus>,
+    /// EdDSA signed JWT with the same claims, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwt: Option<String>,
+    /// Base64 encoded CBOR of the token fields, if configured. Not signed, the signature covers `m` only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cbor: Option<String>,
+    /// Client provided OAuth token refreshed during verification, for the client to store it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<OAuthToken>,
+    /// Additional tokens by their request positions, set for the refreshed ones. Omitted if none was refreshed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub additional_tokens: Vec<Option<OAuthToken>>,
+}
+
+/// Signature over the signed message along with its key
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct KeySignature {
+    /// Base64 encoded ed25519 public key
+    pub key_id: String,
+    #[serde(rename = "sig")]
+    pub signature_ed25519: String,
 }
 
 /// Response for a fractal user whos face verification is pending for final decision
@@ -149,188 +752,4969 @@ pub struct ApprovedResponse {
 #[serde(crate = "near_sdk::serde")]
 pub struct PendingResponse {
     pub token: OAuthToken,
+    /// Remaining verification steps, not covered by any signature
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pending_steps: Vec<PendingStep>,
+}
+
+/// Response for an authenticated user who hasn't started any verification yet
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UnverifiedResponse {
+    pub uniqueness: bool,
+    #[serde(rename = "kyc")]
+    pub kyc_status: VerificationStatus,
+}
+
+/// Response for a dry run verification, nothing is signed
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DryRunResponse {
+    pub would_succeed: bool,
+    /// Omitted for callers not authorized to get KYC details
+    #[serde(rename = "kyc", skip_serializing_if = "Option::is_none")]
+    pub kyc_status: Option<VerificationStatus>,
+}
+
+/// Request to check a signed message against the oracle public key
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VerifySignatureReq {
+    #[serde(rename = "m")]
+    pub message: String,
+    #[serde(rename = "sig")]
+    pub signature_ed25519: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VerifySignatureResponse {
+    pub valid: bool,
+}
+
+/// Request to decode a signed message
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DecodeReq {
+    #[serde(rename = "m", alias = "message")]
+    pub message: String,
+}
+
+/// Token fields of a decoded message, with appended fields as configured for the oracle
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DecodeResponse {
+    pub claimer: AccountId,
+    pub ext_account: String,
+    pub timestamp: u64,
+    pub verified_at: u64,
+    pub verified_kyc: bool,
+    pub kyc_code: u8,
+    pub provider_id: u8,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_claims: Vec<DecodedExtraClaim>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audience: Option<AccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<u64>,
+    /// Base64 encoded hash of the verification policy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_hash: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DecodedExtraClaim {
+    pub claim: ExtraClaim,
+    pub value: u8,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PublicKeyReq {
+    pub tenant: Option<String>,
+}
+
+/// Base64 encoded ed25519 public key to verify signatures
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PublicKeyResponse {
+    pub public_key: String,
+}
+
+/// Readiness of the service components
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReadinessResponse {
+    pub signer_ok: bool,
+    pub provider_ok: bool,
+    /// Whether the recent error rate of verification requests is within the threshold
+    pub error_rate_ok: bool,
+}
+
+/// Build info of the deployed service
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VersionResponse {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    /// Unix timestamp (seconds) of the build
+    pub build_timestamp: u64,
+    pub token_format: &'static str,
+    pub token_format_version: u8,
+    /// Version of the error `reason` values set
+    pub reject_reason_version: u8,
 }
 
 pub async fn verify(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(req): Json<VerificationReq>,
-) -> Result<Json<VerificationResponse>, AppError> {
-    tracing::debug!("Request: {req:?}");
+) -> Result<Json<near_sdk::serde_json::Value>, AppError> {
+    let min_duration = state
+        .config
+        .min_verify_duration_ms
+        .map(Duration::from_millis);
+    let response_fields = state.config.response_fields.clone();
+
+    let Json(res) = with_min_duration(
+        min_duration,
+        process_verification(state, headers, req, &|_| ()),
+    )
+    .await?;
+
+    Ok(Json(response_fields.apply(&res)))
+}
+
+/// Result of a batch item, either the verification response or the same error details as error responses
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde", untagged)]
+pub enum BatchVerifyResult {
+    Verified(near_sdk::serde_json::Value),
+    Failed(near_sdk::serde_json::Value),
+}
 
-    if !state.config.allow_named_sub_accounts && !is_allowed_named_sub_account(&req.claimer) {
-        return Err(AppError::NotAllowedNamedSubAccount(req.claimer));
+/// Verifies a batch of requests concurrently, responding with results in the order of requests.
+/// Failed items don't affect the rest of the batch
+pub async fn verify_batch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(reqs): Json<Vec<VerificationReq>>,
+) -> Result<Json<Vec<BatchVerifyResult>>, AppError> {
+    if reqs.len() > state.config.max_batch_size {
+        return Err(AppError::MalformedRequest(format!(
+            "Batch of {} items exceeds the limit of {}",
+            reqs.len(),
+            state.config.max_batch_size
+        )));
     }
 
-    if let Some(captcha_token) = req.fractal_token.captcha() {
-        match state.captcha.verify(captcha_token).await {
-            Ok(true) => (),
-            Ok(false) => return Err(AppError::SuspiciousUser),
+    let concurrency = state.config.max_batch_concurrency.max(1);
+    let min_duration = state
+        .config
+        .min_verify_duration_ms
+        .map(Duration::from_millis);
+
+    let mut results = futures_util::stream::iter(reqs.into_iter().enumerate())
+        .map(|(idx, req)| {
+            let state = state.clone();
+            let headers = headers.clone();
+            async move {
+                let response_fields = state.config.response_fields.clone();
+                let res = match with_min_duration(
+                    min_duration,
+                    process_verification(state.clone(), headers, req, &|_| ()),
+                )
+                .await
+                {
+                    Ok(Json(res)) => {
+                        state.record_verification(false);
+                        BatchVerifyResult::Verified(response_fields.apply(&res))
+                    }
+                    Err(e) => {
+                        let (status, details) = error_details(e);
+                        state.record_verification(status.is_server_error());
+                        BatchVerifyResult::Failed(details)
+                    }
+                };
+                (idx, res)
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect::<Vec<_>>()
+        .await;
+    results.sort_unstable_by_key(|(idx, _)| *idx);
+
+    Ok(Json(results.into_iter().map(|(_, res)| res).collect()))
+}
+
+/// Stage of a verification reported to streaming clients
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum VerificationStage {
+    ExchangingToken,
+    FetchingUser,
+    Signing,
+}
+
+/// Streams verification progress as server-sent `progress` events,
+/// finished with either `done` event carrying the verification response or `error` event
+pub async fn verify_stream(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<VerificationReq>,
+) -> Sse<impl Stream<Item = Result<Event, std::convert::Infallible>>> {
+    let (events_tx, events_rx) = tokio::sync::mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let report = |stage: VerificationStage| {
+            let event = Event::default()
+                .event("progress")
+                .json_data(stage)
+                .expect("Verification stage is serializable");
+            let _ = events_tx.send(event);
+        };
+
+        let response_fields = state.config.response_fields.clone();
+        let min_duration = state
+            .config
+            .min_verify_duration_ms
+            .map(Duration::from_millis);
+        let event = match with_min_duration(
+            min_duration,
+            process_verification(state.clone(), headers, req, &report),
+        )
+        .await
+        {
+            Ok(Json(res)) => {
+                state.record_verification(false);
+                Event::default()
+                    .event("done")
+                    .json_data(response_fields.apply(&res))
+                    .expect("Verification response is serializable")
+            }
             Err(e) => {
-                tracing::error!(
-                    "Captcha verification failure for an account `{:?}`. Error: {e:?}",
-                    req.claimer
-                );
-                return Err(AppError::from(e));
+                let (status, details) = error_details(e);
+                state.record_verification(status.is_server_error());
+                error_event(details)
             }
         };
-    }
+        let _ = events_tx.send(event);
+    });
 
-    let user = state.client.fetch_user(req.fractal_token).await?;
+    let events = futures_util::stream::unfold(events_rx, |mut events_rx| async move {
+        events_rx.recv().await.map(|event| (Ok(event), events_rx))
+    });
 
-    let res = match user.fv_status {
-        VerificationStatus::Approved => create_approved_response(&state.config, req.claimer, user),
-        VerificationStatus::Pending => Ok(VerificationResponse::Pending(PendingResponse {
-            token: user.token,
-        })),
-        VerificationStatus::Rejected => Err(AppError::FaceVerificationRejected),
-        VerificationStatus::Unavailable => Err(AppError::FaceVerificationMissed),
-    };
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
 
-    tracing::debug!("Response: {res:?}");
+/// Creates `error` event with the client facing details of an error
+fn error_event(details: near_sdk::serde_json::Value) -> Event {
+    Event::default()
+        .event("error")
+        .json_data(details)
+        .expect("Error details are serializable")
+}
 
-    res.map(Json)
+/// Status and client facing details of an error, as in error responses
+fn error_details(e: AppError) -> (StatusCode, near_sdk::serde_json::Value) {
+    let res = e.into_response();
+    let code = res
+        .extensions()
+        .get::<ErrorCode>()
+        .map_or("", |code| code.0);
+    let message = res
+        .extensions()
+        .get::<ErrorMessage>()
+        .map_or("", |message| message.0);
+    let reason = res.extensions().get::<RejectReason>();
+
+    (
+        res.status(),
+        near_sdk::serde_json::json!({ "error": message, "code": code, "reason": reason }),
+    )
 }
 
-/// Creates signed json response for fractal user with approved face verification
-fn create_approved_response(
-    config: &AppConfig,
-    claimer: AccountId,
-    user: FractalUser,
-) -> Result<VerificationResponse, AppError> {
-    let credentials = &config.signer.credentials;
-    let raw_message = VerifiedAccountToken {
-        claimer,
-        ext_account: user.user_id,
-        timestamp: Utc::now().timestamp() as u64,
-        verified_kyc: user.kyc_status == VerificationStatus::Approved,
+async fn process_verification(
+    state: AppState,
+    headers: HeaderMap,
+    req: VerificationReq,
+    report: &(dyn Fn(VerificationStage) + Send + Sync),
+) -> Result<Json<VerificationResponse>, AppError> {
+    tracing::debug!("Request: {req:?}");
+
+    if state.is_maintenance_mode() {
+        return Err(AppError::Maintenance);
     }
-    .try_to_vec()
-    .map_err(|_| AppError::SigningError)?;
-    let signature = credentials.signing_key.sign(&raw_message);
 
-    if !signature.verify(&raw_message, &credentials.signing_key.public_key()) {
-        return Err(AppError::SigningError);
+    check_campaign_window(&state.config, Utc::now())?;
+
+    let claimer = parse_account_id(&req.claimer, state.config.normalize_claimer)?;
+
+    if !state.config.allow_named_sub_accounts && !is_allowed_named_sub_account(&claimer) {
+        return Err(AppError::NotAllowedNamedSubAccount(claimer));
     }
 
-    let raw_signature_ed25519 = match signature {
-        Signature::ED25519(signature) => signature.to_bytes(),
-        _ => return Err(AppError::SigningError),
+    let deadline = state
+        .config
+        .request_budget_ms
+        .map(|budget| tokio::time::Instant::now() + Duration::from_millis(budget));
+
+    let challenge = match &req.challenge {
+        Some(solution) => Some(solution.verify(
+            &claimer,
+            &state.config.signer.credentials.signing_key,
+            state.config.challenge.lifetime,
+        )?),
+        None if state.config.challenge.required => return Err(AppError::ChallengeFailed),
+        None => None,
     };
 
-    let message = general_purpose::STANDARD.encode(&raw_message);
-    let signature_ed25519 = general_purpose::STANDARD.encode(raw_signature_ed25519);
+    if state.skip_captcha {
+        tracing::warn!(
+            "DEV MODE: captcha verification is skipped for an account `{}`",
+            claimer
+        );
+    } else if let Some(captcha_token) = req.fractal_token.captcha() {
+        state.captcha.check_not_consumed(captcha_token)?;
 
-    Ok(VerificationResponse::Approved(ApprovedResponse {
-        message,
-        signature_ed25519,
-        kyc_status: user.kyc_status,
-    }))
-}
+        let captcha_res = within_deadline(deadline, "captcha verification", async {
+            state
+                .captcha
+                .verify(captcha_token)
+                .await
+                .map_err(AppError::from)
+        })
+        .await;
 
-#[cfg(test)]
-mod tests {
-    use crate::signer::{SignerConfig, SignerCredentials};
-    use crate::*;
-    use assert_matches::assert_matches;
-    use base64::{engine::general_purpose, Engine};
-    use chrono::Utc;
-    use near_crypto::{KeyType, Signature};
-    use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
-    use near_sdk::AccountId;
-    use std::str::FromStr;
-    use uuid::Uuid;
+        // Consumed only once the token is definitively passed or failed, so a provider outage
+        // or an exhausted deadline doesn't burn a token the user could retry with.
+        // Dry runs change no state, so the same token could be used for the following request
+        if !req.dry_run && is_definitive_captcha_result(&captcha_res) {
+            state.captcha.consume(captcha_token)?;
+        }
 
-    #[test]
-    fn test_approved_account_response_no_kyc() {
-        let config = gen_app_config(false);
+        check_captcha_result(&state.config, &claimer, captcha_res)?;
+    }
 
-        let claimer = AccountId::new_unchecked("test.near".to_owned());
-        let verified_user = FractalUser {
-            user_id: Uuid::default().into(),
+    let signing_key = state.config.signer.signing_key(req.tenant.as_deref())?;
+
+    let audience = req.audience;
+    check_audience(&state.config, audience.as_ref())?;
+
+    if req.additional_tokens.len() > MAX_ADDITIONAL_TOKENS {
+        return Err(AppError::MalformedRequest(format!(
+            "At most {MAX_ADDITIONAL_TOKENS} additional tokens are allowed"
+        )));
+    }
+
+    let fractal_token = req.fractal_token;
+    let provided_access_token = fractal_token.access_token().map(ToOwned::to_owned);
+    let mut user = within_deadline(deadline, "user fetching", async {
+        report(VerificationStage::ExchangingToken);
+        let oauth_token = state.client.acquire_user_token(fractal_token).await?;
+
+        report(VerificationStage::FetchingUser);
+        state.client.fetch_user(oauth_token).await
+    })
+    .await?;
+
+    state.user_hook.apply(&mut user).await?;
+
+    let mut refreshed_additional_tokens = vec![None; req.additional_tokens.len()];
+    for (idx, fractal_token) in req.additional_tokens.into_iter().enumerate() {
+        if user.is_fully_verified() {
+            tracing::debug!("User is fully verified, remaining additional tokens are skipped");
+            break;
+        }
+
+        let provided_access_token = fractal_token.access_token().map(ToOwned::to_owned);
+        let mut other_user = within_deadline(deadline, "user fetching", async {
+            let oauth_token = state.client.acquire_user_token(fractal_token).await?;
+            state.client.fetch_user(oauth_token).await
+        })
+        .await?;
+        state.user_hook.apply(&mut other_user).await?;
+
+        refreshed_additional_tokens[idx] = provided_access_token
+            .filter(|access_token| *access_token != other_user.token.access_token)
+            .map(|_| other_user.token.clone());
+        user = user.merge(other_user)?;
+    }
+    if refreshed_additional_tokens.iter().all(Option::is_none) {
+        refreshed_additional_tokens.clear();
+    }
+
+    // Client provided tokens refreshed on the way are echoed back, so the client could store them
+    let refreshed_token = provided_access_token
+        .filter(|access_token| *access_token != user.token.access_token)
+        .map(|_| user.token.clone());
+
+    if state.config.require_claimer_binding
+        && req.challenge.is_none()
+        && user.fv_status == VerificationStatus::Approved
+    {
+        check_claimer_binding(&claimer, &user)?;
+    }
+
+    let mut res = if req.dry_run {
+        Ok(create_dry_run_response(&state, &claimer, user))
+    } else {
+        report(VerificationStage::Signing);
+        let nonce = challenge.as_ref().map(|challenge| challenge.nonce.as_str());
+        create_verification_response(
+            &state,
+            &signing_key,
+            claimer,
+            user,
+            req.jwt,
+            audience,
+            nonce,
+        )
+    };
+
+    if let Ok(VerificationResponse::Approved(ApprovedResponse {
+        token,
+        additional_tokens,
+        ..
+    })) = &mut res
+    {
+        *token = refreshed_token;
+        *additional_tokens = refreshed_additional_tokens;
+    }
+
+    if !is_kyc_authorized(&state.config, &headers) {
+        match &mut res {
+            Ok(VerificationResponse::Approved(ApprovedResponse { kyc_status, .. }))
+            | Ok(VerificationResponse::DryRun(DryRunResponse { kyc_status, .. })) => {
+                *kyc_status = None
+            }
+            _ => (),
+        }
+    }
+
+    tracing::debug!("Response: {res:?}");
+
+    res.map(Json)
+}
+
+/// Runs a verification phase within the remaining request budget, if any
+async fn within_deadline<T>(
+    deadline: Option<tokio::time::Instant>,
+    phase: &str,
+    fut: impl std::future::Future<Output = Result<T, AppError>>,
+) -> Result<T, AppError> {
+    let deadline = match deadline {
+        Some(deadline) => deadline,
+        None => return fut.await,
+    };
+
+    tokio::time::timeout_at(deadline, fut)
+        .await
+        .unwrap_or_else(|_| {
+            tracing::warn!("Request budget is exhausted during {phase}");
+            Err(AppError::DeadlineExceeded(format!(
+                "Request budget is exhausted during {phase}"
+            )))
+        })
+}
+
+/// Pads completion to the minimum duration, if any, so outcomes can't be told apart by timing
+async fn with_min_duration<T>(
+    min_duration: Option<Duration>,
+    fut: impl std::future::Future<Output = T>,
+) -> T {
+    let padded_until = min_duration.map(|min_duration| tokio::time::Instant::now() + min_duration);
+    let res = fut.await;
+
+    if let Some(padded_until) = padded_until {
+        tokio::time::sleep_until(padded_until).await;
+    }
+
+    res
+}
+
+/// Checks if a caller presented an API key required to get KYC details, if any is configured
+fn is_kyc_authorized(config: &AppConfig, headers: &HeaderMap) -> bool {
+    match &config.kyc_api_key {
+        // Fixed size digests are compared, so the timing doesn't reveal the matching key prefix
+        Some(api_key) => headers.get(API_KEY_HEADER).map_or(false, |value| {
+            Sha256::digest(value.as_bytes()) == Sha256::digest(api_key.expose().as_bytes())
+        }),
+        None => true,
+    }
+}
+
+/// Creates response depending on user face verification status
+fn create_verification_response(
+    state: &AppState,
+    signing_key: &SecretKey,
+    claimer: AccountId,
+    user: FractalUser,
+    with_jwt: bool,
+    audience: Option<AccountId>,
+    nonce: Option<&str>,
+) -> Result<VerificationResponse, AppError> {
+    match user.fv_status {
+        VerificationStatus::Approved => {
+            sign_approved_user(state, signing_key, claimer, user, with_jwt, audience, nonce)
+        }
+        VerificationStatus::Pending => Ok(VerificationResponse::Pending(PendingResponse {
+            token: user.token,
+            pending_steps: if state.config.pending_steps {
+                user.pending_steps
+            } else {
+                vec![]
+            },
+        })),
+        VerificationStatus::Rejected => Err(AppError::FaceVerificationRejected),
+        VerificationStatus::Unavailable
+            if !user.has_verification_cases
+                && state.config.unverified_user_response == UnverifiedUserResponse::Status =>
+        {
+            Ok(VerificationResponse::Unverified(UnverifiedResponse {
+                uniqueness: false,
+                kyc_status: VerificationStatus::Unavailable,
+            }))
+        }
+        VerificationStatus::Unavailable | VerificationStatus::Expired => {
+            Err(AppError::FaceVerificationMissed)
+        }
+    }
+}
+
+/// Issues a challenge to be signed by the claimer to prove account control
+pub async fn challenge(
+    State(state): State<AppState>,
+    Query(req): Query<ChallengeReq>,
+) -> Result<Json<ChallengeResponse>, AppError> {
+    let claimer = parse_account_id(&req.claimer, state.config.normalize_claimer)?;
+
+    challenge::issue(claimer, &state.config.signer.credentials.signing_key).map(Json)
+}
+
+/// Reports that the service is up
+pub async fn health() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Reports which build is deployed
+pub async fn version(State(state): State<AppState>) -> Json<VersionResponse> {
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("GIT_COMMIT"),
+        build_timestamp: env!("BUILD_TIMESTAMP").parse().unwrap_or_default(),
+        token_format: if state.config.signer.leaf_format {
+            "leaf"
+        } else if state.config.signer.typed_data {
+            "typed_data"
+        } else {
+            "borsh"
+        },
+        token_format_version: TOKEN_FORMAT_VERSION,
+        reject_reason_version: REJECT_REASON_VERSION,
+    })
+}
+
+/// Reports readiness to serve verification requests
+pub async fn ready(State(state): State<AppState>) -> (StatusCode, Json<ReadinessResponse>) {
+    let res = ReadinessResponse {
+        signer_ok: verify_signing_key(&state.config.signer.credentials.signing_key),
+        provider_ok: state.client.check_health().await,
+        error_rate_ok: state.is_error_rate_ok(),
+    };
+
+    let status = if res.signer_ok && res.provider_ok && res.error_rate_ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(res))
+}
+
+/// Debug endpoint to check if a message was signed by the oracle
+pub async fn verify_signature(
+    State(state): State<AppState>,
+    Json(req): Json<VerifySignatureReq>,
+) -> Result<Json<VerifySignatureResponse>, AppError> {
+    let message = general_purpose::STANDARD
+        .decode(&req.message)
+        .map_err(|e| AppError::MalformedRequest(format!("Invalid message encoding. Error: {e}")))?;
+    let raw_signature = general_purpose::STANDARD
+        .decode(&req.signature_ed25519)
+        .map_err(|e| {
+            AppError::MalformedRequest(format!("Invalid signature encoding. Error: {e}"))
+        })?;
+
+    let signature = decode_signature(KeyType::ED25519, &raw_signature)?;
+    let public_key = state.config.signer.credentials.signing_key.public_key();
+    let signed_payload = if state.config.signer.sign_hash {
+        Sha512_256::digest(&message).to_vec()
+    } else {
+        message
+    };
+
+    Ok(Json(VerifySignatureResponse {
+        valid: signature.verify(&signed_payload, &public_key),
+    }))
+}
+
+/// Decodes token fields of a signed message without verifying its signature, for support tooling
+pub async fn decode(
+    State(state): State<AppState>,
+    Json(req): Json<DecodeReq>,
+) -> Result<Json<DecodeResponse>, AppError> {
+    let message = general_purpose::STANDARD
+        .decode(&req.message)
+        .map_err(|e| AppError::MalformedRequest(format!("Invalid message encoding. Error: {e}")))?;
+
+    decode_token(&state.config, &message).map(Json)
+}
+
+/// Decodes borsh token followed by the fields appended according to the config
+fn decode_token(config: &AppConfig, message: &[u8]) -> Result<DecodeResponse, AppError> {
+    if config.signer.leaf_format {
+        return Err(AppError::MalformedRequest(
+            "Leaf format tokens can't be decoded".to_owned(),
+        ));
+    }
+    let malformed =
+        |e: std::io::Error| AppError::MalformedRequest(format!("Invalid token. Error: {e}"));
+
+    let mut buf = message;
+    let token: VerifiedAccountToken = BorshDeserialize::deserialize(&mut buf).map_err(malformed)?;
+    let mut extra_claims = Vec::with_capacity(config.signer.extra_claims.len());
+    for claim in &config.signer.extra_claims {
+        let (value, rest) = buf
+            .split_first()
+            .ok_or_else(|| AppError::MalformedRequest("Missing extra claims".to_owned()))?;
+        extra_claims.push(DecodedExtraClaim {
+            claim: *claim,
+            value: *value,
+        });
+        buf = rest;
+    }
+    let raw_level = match config.signer.raw_level_max_length {
+        Some(_) => Some(BorshDeserialize::deserialize(&mut buf).map_err(malformed)?),
+        None => None,
+    };
+    let audience = if config.signer.allowed_audiences.is_empty() {
+        None
+    } else {
+        BorshDeserialize::deserialize(&mut buf).map_err(malformed)?
+    };
+    let not_before = match config.signer.not_before {
+        Some(_) => Some(BorshDeserialize::deserialize(&mut buf).map_err(malformed)?),
+        None => None,
+    };
+    let policy_hash = if config.signer.policy_hash {
+        let policy_hash: [u8; 32] = BorshDeserialize::deserialize(&mut buf).map_err(malformed)?;
+        Some(general_purpose::STANDARD.encode(policy_hash))
+    } else {
+        None
+    };
+    if !buf.is_empty() {
+        return Err(AppError::MalformedRequest(format!(
+            "Unexpected {} trailing bytes",
+            buf.len()
+        )));
+    }
+
+    Ok(DecodeResponse {
+        claimer: token.claimer,
+        ext_account: token.ext_account.to_string(),
+        timestamp: token.timestamp,
+        verified_at: token.verified_at,
+        verified_kyc: token.verified_kyc,
+        kyc_code: token.kyc_code,
+        provider_id: token.provider_id as u8,
+        extra_claims,
+        raw_level,
+        audience,
+        not_before,
+        policy_hash,
+    })
+}
+
+/// Returns base64 encoded ed25519 public key of the oracle or a tenant
+pub async fn public_key(
+    State(state): State<AppState>,
+    Query(req): Query<PublicKeyReq>,
+) -> Result<Json<PublicKeyResponse>, AppError> {
+    let public_key = state
+        .config
+        .signer
+        .signing_key(req.tenant.as_deref())?
+        .public_key();
+
+    Ok(Json(PublicKeyResponse {
+        public_key: general_purpose::STANDARD.encode(public_key.key_data()),
+    }))
+}
+
+/// Checks if a token would be issued for a user without signing or mutating any store
+fn create_dry_run_response(
+    state: &AppState,
+    claimer: &AccountId,
+    user: FractalUser,
+) -> VerificationResponse {
+    let would_succeed = user.fv_status == VerificationStatus::Approved
+        && check_issuance_policies(state, claimer, &user).is_ok();
+
+    VerificationResponse::DryRun(DryRunResponse {
+        would_succeed,
+        kyc_status: Some(user.kyc_status),
+    })
+}
+
+/// Rejects requests outside of the configured campaign window
+fn check_campaign_window(config: &AppConfig, now: DateTime<Utc>) -> Result<(), AppError> {
+    let before_start = config.accept_from.map_or(false, |from| now < from);
+    let after_end = config.accept_until.map_or(false, |until| now > until);
+
+    if before_start || after_end {
+        return Err(AppError::OutsideCampaignWindow {
+            accept_from: config.accept_from,
+            accept_until: config.accept_until,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks that an implicit claimer without a solved challenge is linked as a NEAR wallet of the user
+fn check_claimer_binding(claimer: &AccountId, user: &FractalUser) -> Result<(), AppError> {
+    if !is_implicit_account(claimer) || user.near_wallets.iter().any(|w| w == claimer.as_str()) {
+        return Ok(());
+    }
+
+    tracing::debug!("Implicit claimer `{claimer}` isn't linked to the verified user");
+    Err(AppError::ClaimerNotBound(claimer.clone()))
+}
+
+/// Checks configured policies of issuing a token for a user with approved face verification
+fn check_issuance_policies(
+    state: &AppState,
+    claimer: &AccountId,
+    user: &FractalUser,
+) -> Result<(), AppError> {
+    if state.config.refuse_on_kyc_rejected && user.kyc_status == VerificationStatus::Rejected {
+        return Err(AppError::KycRejected);
+    }
+
+    if let Some(min_account_age) = state.config.min_account_age {
+        check_account_age(user, min_account_age)?;
+    }
+
+    if let Some(identities) = &state.claimer_identities {
+        check_claimer_identity(identities, claimer, &user.user_id)?;
+    }
+
+    if let Some(claimed_accounts) = &state.claimed_accounts {
+        if claimed_accounts.is_claimed(&user.user_id) {
+            return Err(AppError::AlreadyClaimed(user.user_id.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies issuance policies and creates signed response for a user with approved face verification.
+/// Challenge nonce, if any, is consumed right before signing, so only issued tokens use it up
+fn sign_approved_user(
+    state: &AppState,
+    signing_key: &SecretKey,
+    claimer: AccountId,
+    user: FractalUser,
+    with_jwt: bool,
+    audience: Option<AccountId>,
+    nonce: Option<&str>,
+) -> Result<VerificationResponse, AppError> {
+    check_issuance_policies(state, &claimer, &user)?;
+
+    // Keyed by the raw external account, so one identity can't farm tokens across many claimers.
+    // Acquired after the policies, so otherwise refused requests don't burn the quota
+    if let Some(limiter) = &state.ext_account_limiter {
+        if !limiter.try_acquire(user.user_id.to_string()) {
+            tracing::warn!(
+                "Tokens rate limit of external account {:?} exceeded",
+                user.user_id
+            );
+            return Err(AppError::ExtAccountRateLimited(user.user_id));
+        }
+    }
+
+    if let Some(nonce) = nonce {
+        state.consume_nonce(nonce)?;
+    }
+
+    // Global quota is acquired right before signing, after all refusal checks.
+    // A shed request returns the external account quota it acquired
+    if !state.signing_limiter.try_acquire() {
+        tracing::warn!("Signing rate limit exceeded, request is shed");
+        if let Some(limiter) = &state.ext_account_limiter {
+            limiter.release(&user.user_id.to_string());
+        }
+        return Err(AppError::SigningRateLimited);
+    }
+
+    let ext_account = user.user_id.clone();
+    let res = create_approved_response(
+        &state.config,
+        signing_key,
+        claimer.clone(),
+        user,
+        with_jwt,
+        audience,
+    )?;
+
+    // Claimed atomically after signing, so a signing failure doesn't burn the claim,
+    // while a token signed for a concurrent request of the same identity is discarded
+    if let Some(claimed_accounts) = &state.claimed_accounts {
+        if !claimed_accounts.try_claim(&ext_account)? {
+            return Err(AppError::AlreadyClaimed(ext_account));
+        }
+    }
+
+    if let Some(identities) = &state.claimer_identities {
+        identities.insert(claimer, ext_account);
+    }
+
+    Ok(res)
+}
+
+/// Checks that the earliest user verification case is older than required minimum age in seconds
+fn check_account_age(user: &FractalUser, min_account_age: u64) -> Result<(), AppError> {
+    let min_created_at = Utc::now() - chrono::Duration::seconds(min_account_age as i64);
+
+    match user.created_at {
+        Some(created_at) if created_at <= min_created_at => Ok(()),
+        _ => Err(AppError::AccountTooNew),
+    }
+}
+
+/// Checks that a claimer didn't switch external account within configured time window
+fn check_claimer_identity(
+    identities: &ExpiringStore<AccountId, ExternalAccountId>,
+    claimer: &AccountId,
+    ext_account: &ExternalAccountId,
+) -> Result<(), AppError> {
+    match identities.get(claimer) {
+        Some(prev_ext_account) if &prev_ext_account != ext_account => {
+            tracing::warn!(
+                "Claimer `{claimer}` switched external account from {prev_ext_account:?} to {ext_account:?}"
+            );
+            Err(AppError::ClaimerIdentityConflict(claimer.clone()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Truncates the raw provider level string to at most `max_length` bytes on a char boundary
+fn truncate_raw_level(raw_level: &str, max_length: usize) -> String {
+    let mut end = raw_level.len().min(max_length);
+    while !raw_level.is_char_boundary(end) {
+        end -= 1;
+    }
+    raw_level[..end].to_owned()
+}
+
+/// Checks if captcha verification result is a pass or fail of the token itself,
+/// rather than a failure to get the result
+fn is_definitive_captcha_result(captcha_res: &Result<bool, AppError>) -> bool {
+    match captcha_res {
+        Ok(_) => true,
+        Err(AppError::CaptchaError(e)) => !e.is_provider_failure(),
+        Err(_) => false,
+    }
+}
+
+/// Checks captcha verification result, accepting captcha provider failures if configured to fail open
+fn check_captcha_result(
+    config: &AppConfig,
+    claimer: &AccountId,
+    captcha_res: Result<bool, AppError>,
+) -> Result<(), AppError> {
+    match captcha_res {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(AppError::SuspiciousUser),
+        Err(AppError::CaptchaError(e))
+            if e.is_provider_failure()
+                && config.captcha_failure_mode == CaptchaFailureMode::FailOpen =>
+        {
+            tracing::error!(
+                "Captcha provider failure, FAILING OPEN and accepting an account `{:?}` without captcha. Error: {e:?}",
+                claimer
+            );
+            Ok(())
+        }
+        Err(e) => {
+            tracing::error!(
+                "Captcha verification failure for an account `{:?}`. Error: {e:?}",
+                claimer
+            );
+            Err(e)
+        }
+    }
+}
+
+/// Checks if the requested token audience is an allowed contract account
+fn check_audience(config: &AppConfig, audience: Option<&AccountId>) -> Result<(), AppError> {
+    match audience {
+        Some(audience) if !config.signer.allowed_audiences.contains(audience) => {
+            tracing::debug!("Requested audience `{audience}` isn't allowed");
+            Err(AppError::NotAllowedAudience(audience.clone()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Creates signed json response for fractal user with approved face verification
+fn create_approved_response(
+    config: &AppConfig,
+    signing_key: &SecretKey,
+    claimer: AccountId,
+    user: FractalUser,
+    with_jwt: bool,
+    audience: Option<AccountId>,
+) -> Result<VerificationResponse, AppError> {
+    let verified_at = user.verified_at.ok_or(AppError::UnknownVerificationTime)?;
+    let extra_claims = config
+        .signer
+        .extra_claims
+        .iter()
+        .map(|claim| claim.encode(&user))
+        .collect::<Vec<_>>();
+    let ext_account = match &config.signer.ext_account_salt {
+        Some(salt) => user.user_id.pseudonymize(salt.expose()),
+        None => user.user_id,
+    };
+    let token = VerifiedAccountToken {
+        claimer,
+        ext_account,
+        timestamp: Utc::now().timestamp() as u64,
+        verified_at: verified_at.timestamp() as u64,
+        verified_kyc: user.kyc_status == VerificationStatus::Approved,
+        kyc_code: user.kyc_status.code(),
+        provider_id: user.provider_id,
+        audience,
+    };
+    let mut raw_message = if config.signer.leaf_format {
+        token.to_leaf()
+    } else {
+        token.try_to_vec().map_err(|_| AppError::SigningError)?
+    };
+    raw_message.extend(extra_claims);
+    if let Some(max_length) = config.signer.raw_level_max_length {
+        let raw_level =
+            truncate_raw_level(user.raw_level.as_deref().unwrap_or_default(), max_length);
+        BorshSerialize::serialize(&raw_level, &mut raw_message)
+            .map_err(|_| AppError::SigningError)?;
+    }
+    if !config.signer.allowed_audiences.is_empty() {
+        BorshSerialize::serialize(&token.audience, &mut raw_message)
+            .map_err(|_| AppError::SigningError)?;
+    }
+    let not_before = config
+        .signer
+        .not_before
+        .map(|not_before| not_before.timestamp().max(0) as u64);
+    if let Some(not_before) = not_before {
+        BorshSerialize::serialize(&not_before, &mut raw_message)
+            .map_err(|_| AppError::SigningError)?;
+    }
+    if config.signer.policy_hash {
+        raw_message.extend(config.policy_hash());
+    }
+
+    // Typed data or message digest is signed instead of the message, which still carries the token fields
+    let signed_payload = if config.signer.typed_data {
+        token.typed_data_digest().to_vec()
+    } else if config.signer.sign_hash {
+        Sha512_256::digest(&raw_message).to_vec()
+    } else {
+        raw_message.clone()
+    };
+
+    let verify_own_signature = config.signer.verify_own_signature;
+    let raw_signature_ed25519 = sign_ed25519(signing_key, &signed_payload, verify_own_signature)?;
+
+    // Signatures of all oracle keys over the same message, if co-signing is configured
+    let signatures = if config.signer.cosigning_keys.is_empty() {
+        vec![]
+    } else {
+        let key_signature = |key: &SecretKey, signature: &[u8]| KeySignature {
+            key_id: general_purpose::STANDARD.encode(key.public_key().key_data()),
+            signature_ed25519: general_purpose::STANDARD.encode(signature),
+        };
+
+        let mut signatures = vec![key_signature(signing_key, &raw_signature_ed25519)];
+        for key in &config.signer.cosigning_keys {
+            let signature = sign_ed25519(key, &signed_payload, verify_own_signature)?;
+            signatures.push(key_signature(key, &signature));
+        }
+        signatures
+    };
+
+    let message = general_purpose::STANDARD.encode(&raw_message);
+    let signature_ed25519 = general_purpose::STANDARD.encode(raw_signature_ed25519);
+    let cbor = if config.signer.cbor_token {
+        Some(general_purpose::STANDARD.encode(token.to_cbor()?))
+    } else {
+        None
+    };
+
+    let jwt = if with_jwt {
+        let claims = JwtClaims {
+            sub: token.claimer,
+            ext_account: token.ext_account.to_string(),
+            kyc: token.verified_kyc,
+            verified_at: token.verified_at,
+            iat: token.timestamp,
+            exp: token.timestamp + config.signer.jwt_lifetime(token.verified_kyc),
+            nbf: not_before,
+            aud: token.audience.clone(),
+        };
+        Some(jwt::encode(&claims, signing_key)?)
+    } else {
+        None
+    };
+
+    Ok(VerificationResponse::Approved(ApprovedResponse {
+        message,
+        signature_ed25519,
+        signatures,
+        kyc_status: Some(user.kyc_status),
+        jwt,
+        cbor,
+        token: None,
+        additional_tokens: vec![],
+    }))
+}
+
+/// Signs a message with an ed25519 key, optionally verifying the signature
+fn sign_ed25519(
+    signing_key: &SecretKey,
+    raw_message: &[u8],
+    verify: bool,
+) -> Result<[u8; ED25519_SIGNATURE_LENGTH], AppError> {
+    let signature = signing_key.sign(raw_message);
+
+    if verify && !signature.verify(raw_message, &signing_key.public_key()) {
+        return Err(AppError::SigningError);
+    }
+
+    match signature {
+        Signature::ED25519(signature) => Ok(signature.to_bytes()),
+        _ => Err(AppError::SigningError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::signer::{SignerConfig, SignerCredentials};
+    use crate::utils::{test_utils::spawn_mock_server, Secret};
+    use crate::verification_provider::{ExtraClaim, VerificationProviderConfig};
+    use crate::*;
+    use assert_matches::assert_matches;
+    use base64::{engine::general_purpose, Engine};
+    use chrono::{TimeZone, Utc};
+    use near_crypto::{KeyType, Signature};
+    use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+    use near_sdk::AccountId;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_approved_account_response_no_kyc() {
+        let config = gen_app_config(false);
+
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+        let verified_user =
+            gen_verified_user(Uuid::default().into(), VerificationStatus::Unavailable);
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            claimer.clone(),
+            verified_user.clone(),
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+
+        let credentials = &config.signer.credentials;
+
+        let decoded_bytes = general_purpose::STANDARD
+            .decode(&approved_res.message)
+            .unwrap();
+
+        assert!(Signature::from_parts(
+            KeyType::ED25519,
+            &general_purpose::STANDARD
+                .decode(&approved_res.signature_ed25519)
+                .unwrap()
+        )
+        .unwrap()
+        .verify(&decoded_bytes, &credentials.signing_key.public_key()));
+
+        let decoded_msg = VerifiedAccountToken::try_from_slice(&decoded_bytes).unwrap();
+
+        assert_matches!(decoded_msg, VerifiedAccountToken {
+            claimer: claimer_res,
+            ext_account: ext_account_res,
+            timestamp: _,
+            verified_at: _,
+            verified_kyc: false,
+            kyc_code: 0,
+            provider_id: ProviderId::Fractal,
+            audience: None,
+        } if claimer_res == claimer && ext_account_res == verified_user.user_id);
+    }
+
+    #[test]
+    fn test_approved_account_response_with_kyc() {
+        let config = gen_app_config(false);
+
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            claimer.clone(),
+            verified_user.clone(),
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+
+        let credentials = &config.signer.credentials;
+
+        let decoded_bytes = general_purpose::STANDARD
+            .decode(&approved_res.message)
+            .unwrap();
+
+        assert!(Signature::from_parts(
+            KeyType::ED25519,
+            &general_purpose::STANDARD
+                .decode(&approved_res.signature_ed25519)
+                .unwrap()
+        )
+        .unwrap()
+        .verify(&decoded_bytes, &credentials.signing_key.public_key()));
+
+        let decoded_msg = VerifiedAccountToken::try_from_slice(&decoded_bytes).unwrap();
+
+        assert_matches!(decoded_msg, VerifiedAccountToken {
+            claimer: claimer_res,
+            ext_account: ext_account_res,
+            timestamp: _,
+            verified_at: _,
+            verified_kyc: true,
+            kyc_code: 2,
+            provider_id: ProviderId::Fractal,
+            audience: None,
+        } if claimer_res == claimer && ext_account_res == verified_user.user_id);
+    }
+
+    #[test]
+    fn test_approved_account_response_jwt() {
+        let config = gen_app_config(false);
+
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            claimer.clone(),
+            verified_user.clone(),
+            true,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+
+        let jwt = approved_res.jwt.unwrap();
+        let parts = jwt.split('.').collect::<Vec<_>>();
+        let signature = Signature::from_parts(
+            KeyType::ED25519,
+            &general_purpose::URL_SAFE_NO_PAD.decode(parts[2]).unwrap(),
+        )
+        .unwrap();
+        assert!(signature.verify(
+            format!("{}.{}", parts[0], parts[1]).as_bytes(),
+            &config.signer.credentials.signing_key.public_key()
+        ));
+
+        let claims = near_sdk::serde_json::from_slice::<JwtClaims>(
+            &general_purpose::URL_SAFE_NO_PAD.decode(parts[1]).unwrap(),
+        )
+        .unwrap();
+        let decoded_msg = VerifiedAccountToken::try_from_slice(
+            &general_purpose::STANDARD
+                .decode(&approved_res.message)
+                .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(claims.sub, claimer);
+        assert_eq!(claims.ext_account, verified_user.user_id.to_string());
+        assert!(claims.kyc);
+        assert_eq!(claims.verified_at, decoded_msg.verified_at);
+        assert_eq!(claims.iat, decoded_msg.timestamp);
+        assert_eq!(claims.exp, decoded_msg.timestamp + 3600);
+
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            claimer,
+            verified_user,
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+        assert!(approved_res.jwt.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_verify_signature() {
+        let config = gen_app_config(false);
+        let state = AppState::new(config.clone()).unwrap();
+
+        let verified_user =
+            gen_verified_user(Uuid::default().into(), VerificationStatus::Unavailable);
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user,
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+
+        let res = verify_signature(
+            State(state.clone()),
+            Json(VerifySignatureReq {
+                message: approved_res.message.clone(),
+                signature_ed25519: approved_res.signature_ed25519.clone(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.0, VerifySignatureResponse { valid: true });
+
+        let res = verify_signature(
+            State(state.clone()),
+            Json(VerifySignatureReq {
+                message: general_purpose::STANDARD.encode("other message"),
+                signature_ed25519: approved_res.signature_ed25519.clone(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.0, VerifySignatureResponse { valid: false });
+
+        let res = verify_signature(
+            State(state),
+            Json(VerifySignatureReq {
+                message: approved_res.message.clone(),
+                signature_ed25519: general_purpose::STANDARD.encode([0u8; 32]),
+            }),
+        )
+        .await;
+        assert_matches!(res, Err(AppError::MalformedRequest(_)));
+
+        // Endpoint is served only if enabled
+        let verify_signature_req = || {
+            axum::http::Request::post("/verify_signature")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(
+                    near_sdk::serde_json::json!({
+                        "m": approved_res.message,
+                        "sig": approved_res.signature_ed25519,
+                    })
+                    .to_string(),
+                ))
+                .unwrap()
+        };
+        let app = create_router(AppState::new(config.clone()).unwrap());
+        let res = app.oneshot(verify_signature_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let mut config = config;
+        config.verify_signature_endpoint = true;
+        let app = create_router(AppState::new(config).unwrap());
+        let res = app.oneshot(verify_signature_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_version() {
+        let app = create_router(AppState::new(gen_app_config(false)).unwrap());
+
+        let res = app
+            .oneshot(
+                axum::http::Request::get("/version")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        let mut keys = body
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            [
+                "build_timestamp",
+                "git_commit",
+                "reject_reason_version",
+                "token_format",
+                "token_format_version",
+                "version"
+            ]
+        );
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["token_format"], "borsh");
+        assert_eq!(body["token_format_version"], TOKEN_FORMAT_VERSION);
+        assert_eq!(body["reject_reason_version"], REJECT_REASON_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_ready_provider_health() {
+        let up_url = spawn_mock_server(Router::new().route(
+            "/oauth/token",
+            post(|| async { (StatusCode::UNAUTHORIZED, "invalid_client") }),
+        ));
+        let down_url = spawn_mock_server(Router::new().route(
+            "/oauth/token",
+            post(|| async { StatusCode::SERVICE_UNAVAILABLE }),
+        ));
+
+        let mut config = gen_app_config(false);
+        config.verification_provider.request_token_url = format!("{up_url}/oauth/token");
+        let (status, res) = ready(State(AppState::new(config).unwrap())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            res.0,
+            ReadinessResponse {
+                signer_ok: true,
+                provider_ok: true,
+                error_rate_ok: true,
+            }
+        );
+
+        let mut config = gen_app_config(false);
+        config.verification_provider.request_token_url = format!("{down_url}/oauth/token");
+        let (status, res) = ready(State(AppState::new(config).unwrap())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            res.0,
+            ReadinessResponse {
+                signer_ok: true,
+                provider_ok: false,
+                error_rate_ok: true,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ready_error_rate() {
+        let provider_url = spawn_mock_server(Router::new().route(
+            "/oauth/token",
+            post(|| async { (StatusCode::UNAUTHORIZED, "invalid_client") }),
+        ));
+
+        let mut config = gen_app_config(false);
+        config.verification_provider.request_token_url = format!("{provider_url}/oauth/token");
+        // Nothing listens there, so user fetching fails with HTTP 502
+        config.verification_provider.request_user_url = "http://127.0.0.1:1/users/me".to_owned();
+        config.error_rate_threshold = Some(crate::config::ErrorRateThresholdConfig {
+            max_error_rate: 0.5,
+            window: 60,
+            min_requests: 4,
+        });
+        let state = AppState::new(config.clone()).unwrap();
+        let app = create_router(state.clone());
+        let verify_body = || {
+            let token = verification_provider::FractalTokenKind::OAuth {
+                token: OAuthToken {
+                    access_token: "some_auth_token".to_owned(),
+                    refresh_token: "some_refresh_token".to_owned(),
+                    expires_at: Utc::now() + chrono::Duration::days(1),
+                    scopes: None,
+                },
+                redirect_uri: "https://some_url".to_owned(),
+            };
+            let mut body = near_sdk::serde_json::to_value(token).unwrap();
+            body["claimer"] = "test.near".into();
+            body
+        };
+        let post_req = |uri: &str, body: near_sdk::serde_json::Value| {
+            axum::http::Request::post(uri)
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap()
+        };
+        let verify_req = || post_req("/verify", verify_body());
+
+        // Too few requests to judge the error rate by
+        for _ in 0..3 {
+            let res = app.clone().oneshot(verify_req()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        }
+        let (status, res) = ready(State(state.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(res.error_rate_ok);
+
+        let res = app.oneshot(verify_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        let (status, res) = ready(State(state)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            res.0,
+            ReadinessResponse {
+                signer_ok: true,
+                provider_ok: true,
+                error_rate_ok: false,
+            }
+        );
+
+        // Failed batch items are tracked one by one
+        let state = AppState::new(config.clone()).unwrap();
+        let batch = (0..4).map(|_| verify_body()).collect();
+        let res = create_router(state.clone())
+            .oneshot(post_req(
+                "/verify/batch",
+                near_sdk::serde_json::Value::Array(batch),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!state.is_error_rate_ok());
+
+        // As well as streamed verifications failed with error events
+        let state = AppState::new(config).unwrap();
+        let app = create_router(state.clone());
+        for _ in 0..4 {
+            let res = app
+                .clone()
+                .oneshot(post_req("/verify/stream", verify_body()))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            hyper::body::to_bytes(res.into_body()).await.unwrap();
+        }
+        assert!(!state.is_error_rate_ok());
+    }
+
+    #[tokio::test]
+    async fn test_provider_health_cached() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let url = spawn_mock_server(Router::new().route(
+            "/oauth/token",
+            post(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { StatusCode::BAD_REQUEST }
+            }),
+        ));
+
+        let mut config = gen_app_config(false);
+        config.verification_provider.request_token_url = format!("{url}/oauth/token");
+        let state = AppState::new(config).unwrap();
+
+        assert!(state.client.check_health().await);
+        assert!(state.client.check_health().await);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_claimer_identity_conflict() {
+        let mut config = gen_app_config(false);
+        config.claimer_identity_window = Some(60);
+        let state = AppState::new(config).unwrap();
+
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+        let gen_user = |user_id: ExternalAccountId| {
+            gen_verified_user(user_id, VerificationStatus::Unavailable)
+        };
+        let ext_account: ExternalAccountId = Uuid::new_v4().into();
+        let other_ext_account: ExternalAccountId = Uuid::new_v4().into();
+
+        // Same claimer with the same external account
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                claimer.clone(),
+                gen_user(ext_account.clone()),
+                false,
+                None,
+                None
+            ),
+            Ok(VerificationResponse::Approved(_))
+        );
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                claimer.clone(),
+                gen_user(ext_account.clone()),
+                false,
+                None,
+                None
+            ),
+            Ok(VerificationResponse::Approved(_))
+        );
+
+        // Same claimer with different external account
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                claimer.clone(),
+                gen_user(other_ext_account.clone()),
+                false,
+                None,
+                None
+            ),
+            Err(AppError::ClaimerIdentityConflict(account)) if account == claimer
+        );
+
+        // Another claimer isn't affected
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                AccountId::new_unchecked("other.near".to_owned()),
+                gen_user(other_ext_account),
+                false,
+                None,
+                None
+            ),
+            Ok(VerificationResponse::Approved(_))
+        );
+    }
+
+    #[test]
+    fn test_ext_account_rate_limit() {
+        let mut config = gen_app_config(false);
+        config.ext_account_rate_limit = Some(config::ExtAccountRateLimitConfig {
+            max_tokens: 2,
+            window: 60,
+        });
+        let state = AppState::new(config).unwrap();
+
+        let ext_account: ExternalAccountId = Uuid::new_v4().into();
+        let sign = |claimer: &str, ext_account: &ExternalAccountId| {
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                AccountId::new_unchecked(claimer.to_owned()),
+                gen_verified_user(ext_account.clone(), VerificationStatus::Unavailable),
+                false,
+                None,
+                None,
+            )
+        };
+
+        assert_matches!(
+            sign("first.near", &ext_account),
+            Ok(VerificationResponse::Approved(_))
+        );
+        assert_matches!(
+            sign("second.near", &ext_account),
+            Ok(VerificationResponse::Approved(_))
+        );
+
+        // Blocked even with distinct claimers
+        assert_matches!(
+            sign("third.near", &ext_account),
+            Err(AppError::ExtAccountRateLimited(account)) if account == ext_account
+        );
+        assert_matches!(
+            sign("first.near", &ext_account),
+            Err(AppError::ExtAccountRateLimited(_))
+        );
+
+        // Another external account isn't affected
+        assert_matches!(
+            sign("third.near", &Uuid::new_v4().into()),
+            Ok(VerificationResponse::Approved(_))
+        );
+    }
+
+    #[test]
+    fn test_ext_account_rate_limit_not_burned() {
+        let mut config = gen_app_config(false);
+        config.signer.max_signatures_per_second = Some(1);
+        config.ext_account_rate_limit = Some(config::ExtAccountRateLimitConfig {
+            max_tokens: 2,
+            window: 60,
+        });
+        let state = AppState::new(config).unwrap();
+
+        let ext_account: ExternalAccountId = Uuid::new_v4().into();
+        let sign = || {
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                AccountId::new_unchecked("test.near".to_owned()),
+                gen_verified_user(ext_account.clone(), VerificationStatus::Unavailable),
+                false,
+                None,
+                None,
+            )
+        };
+
+        assert_matches!(sign(), Ok(VerificationResponse::Approved(_)));
+        // Shed request doesn't count towards the external account quota
+        assert_matches!(sign(), Err(AppError::SigningRateLimited));
+        std::thread::sleep(Duration::from_millis(1100));
+        assert_matches!(sign(), Ok(VerificationResponse::Approved(_)));
+    }
+
+    #[test]
+    fn test_signing_rate_limit_not_burned() {
+        let mut config = gen_app_config(false);
+        config.signer.max_signatures_per_second = Some(1);
+        config.ext_account_rate_limit = Some(config::ExtAccountRateLimitConfig {
+            max_tokens: 1,
+            window: 60,
+        });
+        config.challenge.single_use = true;
+        let state = AppState::new(config).unwrap();
+
+        let limited_account: ExternalAccountId = Uuid::new_v4().into();
+        assert!(state
+            .ext_account_limiter
+            .as_ref()
+            .unwrap()
+            .try_acquire(limited_account.to_string()));
+        state.consume_nonce("some_nonce").unwrap();
+
+        let sign = |ext_account: &ExternalAccountId, nonce| {
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                AccountId::new_unchecked("test.near".to_owned()),
+                gen_verified_user(ext_account.clone(), VerificationStatus::Unavailable),
+                false,
+                None,
+                nonce,
+            )
+        };
+
+        // Requests refused by other checks don't count towards the global quota
+        assert_matches!(
+            sign(&limited_account, None),
+            Err(AppError::ExtAccountRateLimited(_))
+        );
+        assert_matches!(
+            sign(&Uuid::new_v4().into(), Some("some_nonce")),
+            Err(AppError::NonceReused)
+        );
+        assert_matches!(
+            sign(&Uuid::new_v4().into(), None),
+            Ok(VerificationResponse::Approved(_))
+        );
+        assert_matches!(
+            sign(&Uuid::new_v4().into(), None),
+            Err(AppError::SigningRateLimited)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout_vs_deadline() {
+        let error_details = |e: AppError| async move {
+            let res = axum::response::IntoResponse::into_response(e);
+            let status = res.status();
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body =
+                near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+            (status, body["code"].as_str().unwrap().to_owned())
+        };
+
+        // Network timeout of an upstream request
+        let url = spawn_mock_server(Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                "slow"
+            }),
+        ));
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let timeout = AppError::from(client.get(format!("{url}/slow")).send().await.unwrap_err());
+        assert_matches!(timeout, AppError::TimeoutError(_));
+        assert_eq!(
+            error_details(timeout).await,
+            (StatusCode::GATEWAY_TIMEOUT, "upstream_timeout".to_owned())
+        );
+
+        // Request budget cancellation of a phase
+        let deadline = Some(tokio::time::Instant::now() + Duration::from_millis(50));
+        let deadline_exceeded = within_deadline(deadline, "user fetching", async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            Ok::<_, AppError>(())
+        })
+        .await
+        .unwrap_err();
+        assert_matches!(deadline_exceeded, AppError::DeadlineExceeded(_));
+        assert_eq!(
+            error_details(deadline_exceeded).await,
+            (StatusCode::GATEWAY_TIMEOUT, "deadline_exceeded".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_budget() {
+        let phase = |delay_ms| async move {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            Ok::<_, AppError>(())
+        };
+
+        // Slow first phase leaves insufficient budget for the next one
+        let deadline = Some(tokio::time::Instant::now() + Duration::from_millis(200));
+        assert_matches!(within_deadline(deadline, "first", phase(150)).await, Ok(()));
+        assert_matches!(
+            within_deadline(deadline, "second", phase(100)).await,
+            Err(AppError::DeadlineExceeded(_))
+        );
+
+        // Phases fitting the budget together succeed
+        let deadline = Some(tokio::time::Instant::now() + Duration::from_millis(200));
+        assert_matches!(within_deadline(deadline, "first", phase(50)).await, Ok(()));
+        assert_matches!(within_deadline(deadline, "second", phase(50)).await, Ok(()));
+
+        // Unlimited without a budget
+        assert_matches!(within_deadline(None, "first", phase(50)).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_min_duration() {
+        let min_duration = Some(Duration::from_millis(200));
+
+        // Both success and failure are padded
+        let started = tokio::time::Instant::now();
+        assert_matches!(
+            with_min_duration(min_duration, async { Ok::<_, AppError>(()) }).await,
+            Ok(())
+        );
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        let started = tokio::time::Instant::now();
+        assert_matches!(
+            with_min_duration(min_duration, async {
+                Err::<(), _>(AppError::FaceVerificationMissed)
+            })
+            .await,
+            Err(AppError::FaceVerificationMissed)
+        );
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        // Slower outcome isn't delayed any further
+        let started = tokio::time::Instant::now();
+        with_min_duration(min_duration, tokio::time::sleep(Duration::from_millis(300))).await;
+        assert!(started.elapsed() < Duration::from_millis(450));
+
+        // Not padded by default
+        let started = tokio::time::Instant::now();
+        with_min_duration(None, async {}).await;
+        assert!(started.elapsed() < Duration::from_millis(200));
+
+        // Early rejected requests are padded as well
+        let mut config = gen_app_config(false);
+        config.min_verify_duration_ms = Some(200);
+        let app = create_router(AppState::new(config).unwrap());
+
+        let started = tokio::time::Instant::now();
+        let res = app
+            .clone()
+            .oneshot(
+                axum::http::Request::post("/verify")
+                    .header("content-type", "application/json")
+                    .body(axum::body::Body::from(
+                        r#"{
+                            "claimer": "test1.test.near",
+                            "code": "some_code",
+                            "captcha": "some_captcha",
+                            "redirect_uri": "https://some_url"
+                        }"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        // Final event of streamed verifications is padded as well
+        let started = tokio::time::Instant::now();
+        let res = app
+            .oneshot(
+                axum::http::Request::post("/verify/stream")
+                    .header("content-type", "application/json")
+                    .body(axum::body::Body::from(
+                        r#"{
+                            "claimer": "test1.test.near",
+                            "code": "some_code",
+                            "captcha": "some_captcha",
+                            "redirect_uri": "https://some_url"
+                        }"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("not_allowed_account"));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_one_time_claims() {
+        let mut config = gen_app_config(false);
+        config.one_time_claims.enabled = true;
+        let state = AppState::new(config).unwrap();
+
+        let sign = |claimer: &str, user_id: &ExternalAccountId| {
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                AccountId::new_unchecked(claimer.to_owned()),
+                gen_verified_user(user_id.clone(), VerificationStatus::Unavailable),
+                false,
+                None,
+                None,
+            )
+        };
+        let ext_account: ExternalAccountId = Uuid::new_v4().into();
+
+        // Signing failure doesn't burn the claim
+        let mut unsignable_user =
+            gen_verified_user(ext_account.clone(), VerificationStatus::Unavailable);
+        unsignable_user.verified_at = None;
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                AccountId::new_unchecked("test.near".to_owned()),
+                unsignable_user,
+                false,
+                None,
+                None,
+            ),
+            Err(AppError::Generic(_))
+        );
+
+        // First claim is allowed
+        assert_matches!(
+            sign("test.near", &ext_account),
+            Ok(VerificationResponse::Approved(_))
+        );
+
+        // Repeated claims are blocked regardless of the claimer
+        assert_matches!(
+            sign("test.near", &ext_account),
+            Err(AppError::AlreadyClaimed(account)) if account == ext_account
+        );
+        assert_matches!(
+            sign("other.near", &ext_account),
+            Err(AppError::AlreadyClaimed(_))
+        );
+
+        // Another external account isn't affected
+        assert_matches!(
+            sign("other.near", &Uuid::new_v4().into()),
+            Ok(VerificationResponse::Approved(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tenant_public_key() {
+        let mut config = gen_app_config(false);
+        config.signer.tenant_keys = true;
+        let master_key = config.signer.credentials.signing_key.clone();
+        let app = create_router(AppState::new(config).unwrap());
+
+        let public_key_req = |uri: &str| {
+            axum::http::Request::get(uri)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+        let fetch_public_key = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let res = app.oneshot(public_key_req(uri)).await.unwrap();
+                assert_eq!(res.status(), StatusCode::OK);
+                let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                let body =
+                    near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+                body["public_key"].as_str().unwrap().to_owned()
+            }
+        };
+
+        let tenant_key = crate::signer::derive_tenant_key(&master_key, "tenant_a").unwrap();
+        assert_eq!(
+            fetch_public_key("/pubkey?tenant=tenant_a").await,
+            general_purpose::STANDARD.encode(tenant_key.public_key().key_data())
+        );
+        assert_eq!(
+            fetch_public_key("/pubkey").await,
+            general_purpose::STANDARD.encode(master_key.public_key().key_data())
+        );
+        assert_ne!(
+            fetch_public_key("/pubkey?tenant=tenant_a").await,
+            fetch_public_key("/pubkey?tenant=tenant_b").await
+        );
+
+        // Tenant keys are disabled
+        let app = create_router(AppState::new(gen_app_config(false)).unwrap());
+        let res = app
+            .oneshot(public_key_req("/pubkey?tenant=tenant_a"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_expected_public_key() {
+        let mut config = gen_app_config(false);
+        config.signer.expected_public_key = Some(
+            config
+                .signer
+                .credentials
+                .signing_key
+                .public_key()
+                .to_string(),
+        );
+        assert!(AppState::new(config).is_ok());
+
+        let mut config = gen_app_config(false);
+        config.signer.expected_public_key = Some(
+            near_crypto::SecretKey::from_random(KeyType::ED25519)
+                .public_key()
+                .to_string(),
+        );
+        assert!(matches!(AppState::new(config), Err(AppError::Generic(_))));
+    }
+
+    #[tokio::test]
+    async fn test_instance_header() {
+        let health_req = || {
+            axum::http::Request::get("/health")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let mut config = gen_app_config(false);
+        config.instance_id = Some("oracle-1".to_owned());
+        let app = create_router(AppState::new(config).unwrap());
+        let res = app.oneshot(health_req()).await.unwrap();
+        assert_eq!(res.headers()[middleware::INSTANCE_HEADER], "oracle-1");
+
+        // Falls back to the hostname
+        let app = create_router(AppState::new(gen_app_config(false)).unwrap());
+        let res = app.oneshot(health_req()).await.unwrap();
+        assert!(res.headers().contains_key(middleware::INSTANCE_HEADER));
+    }
+
+    #[tokio::test]
+    async fn test_response_compression() {
+        // Version endpoint is served locally without upstream requests
+        let version_req = |encoding: &str| {
+            axum::http::Request::get("/version")
+                .header("accept-encoding", encoding)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        // Response is larger than the minimum size
+        let mut config = gen_app_config(false);
+        config.compression.enabled = true;
+        config.compression.min_size = 16;
+        let app = create_router(AppState::new(config).unwrap());
+        for encoding in ["gzip", "br"] {
+            let res = app.clone().oneshot(version_req(encoding)).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()["content-encoding"], encoding);
+        }
+
+        // Response is smaller than the minimum size
+        let mut config = gen_app_config(false);
+        config.compression.enabled = true;
+        let app = create_router(AppState::new(config).unwrap());
+        let res = app.oneshot(version_req("gzip, br")).await.unwrap();
+        assert!(res.headers().get("content-encoding").is_none());
+
+        // Compression is disabled
+        let mut config = gen_app_config(false);
+        config.compression.min_size = 16;
+        let app = create_router(AppState::new(config).unwrap());
+        let res = app.oneshot(version_req("gzip, br")).await.unwrap();
+        assert!(res.headers().get("content-encoding").is_none());
+    }
+
+    #[test]
+    fn test_kyc_api_key() {
+        let gen_headers = |api_key: Option<&'static str>| {
+            let mut headers = axum::http::HeaderMap::new();
+            if let Some(api_key) = api_key {
+                headers.insert("X-Api-Key", api_key.parse().unwrap());
+            }
+            headers
+        };
+
+        let mut config = gen_app_config(false);
+        assert!(is_kyc_authorized(&config, &gen_headers(None)));
+        assert!(is_kyc_authorized(&config, &gen_headers(Some("any_key"))));
+
+        config.kyc_api_key = Some(Secret::from("some_api_key"));
+        assert!(is_kyc_authorized(
+            &config,
+            &gen_headers(Some("some_api_key"))
+        ));
+        assert!(!is_kyc_authorized(
+            &config,
+            &gen_headers(Some("other_api_key"))
+        ));
+        assert!(!is_kyc_authorized(&config, &gen_headers(None)));
+
+        let verified_user = gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Approved);
+        let mut approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user,
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+
+        let authorized = near_sdk::serde_json::to_value(&approved_res).unwrap();
+        assert_eq!(authorized["kyc"], "approved");
+
+        approved_res.kyc_status = None;
+        let unauthorized = near_sdk::serde_json::to_value(&approved_res).unwrap();
+        assert!(unauthorized.get("kyc").is_none());
+        assert_eq!(unauthorized["m"], authorized["m"]);
+    }
+
+    #[tokio::test]
+    async fn test_max_requests_per_ip() {
+        let mut config = gen_app_config(false);
+        config.max_requests_per_ip = Some(1);
+        let state = AppState::new(config).unwrap();
+        let app = create_router(state.clone());
+
+        let ip: std::net::SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let other_ip: std::net::SocketAddr = "10.0.0.2:1000".parse().unwrap();
+        let health_req = |addr: std::net::SocketAddr| {
+            let mut req = axum::http::Request::get("/health")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            req.extensions_mut()
+                .insert(axum::extract::ConnectInfo(addr));
+            req
+        };
+
+        let res = app.clone().oneshot(health_req(ip)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // Request from the same IP is in flight
+        let _permit = state.ip_limiter.as_ref().unwrap().try_acquire(ip.ip());
+
+        let res = app.clone().oneshot(health_req(ip)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["code"], "too_many_requests");
+
+        // Rejections are responded in the negotiated format
+        let mut req = health_req(ip);
+        req.headers_mut()
+            .insert("accept", "text/plain".parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["content-type"], "text/plain; charset=utf-8");
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(body.starts_with(b"too_many_requests: "));
+
+        let res = app.oneshot(health_req(other_ip)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_max_requests_per_ip_stream() {
+        let token_notify = Arc::new(tokio::sync::Notify::new());
+        let provider_url = spawn_mock_server(Router::new().route(
+            "/oauth/token",
+            post({
+                let token_notify = token_notify.clone();
+                move || async move {
+                    token_notify.notified().await;
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            }),
+        ));
+
+        let mut config = gen_app_config(false);
+        config.max_requests_per_ip = Some(1);
+        config.verification_provider.request_token_url = format!("{provider_url}/oauth/token");
+        let app = create_router(AppState::new(config).unwrap());
+
+        let ip: std::net::SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let stream_req = || {
+            let mut req = axum::http::Request::post("/verify/stream")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(
+                    near_sdk::serde_json::json!({
+                        "claimer": "test.near",
+                        "code": "some_code",
+                        "redirect_uri": "https://some_url",
+                    })
+                    .to_string(),
+                ))
+                .unwrap();
+            req.extensions_mut().insert(axum::extract::ConnectInfo(ip));
+            req
+        };
+
+        // Stream responds with headers right away, while events are still pending
+        let res = app.clone().oneshot(stream_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let rejected_res = app.clone().oneshot(stream_req()).await.unwrap();
+        assert_eq!(rejected_res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Permit is released once the stream is finished
+        token_notify.notify_one();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("event: error"));
+
+        let res = app.oneshot(stream_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut config = gen_app_config(false);
+        config.claimer_identity_window = Some(3600);
+        config.signer.max_signatures_per_second = Some(1);
+        config.min_account_age = Some(30 * 24 * 60 * 60);
+        let state = AppState::new(config).unwrap();
+        let signing_key = &state.config.signer.credentials.signing_key;
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+        let user = gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Approved);
+
+        // Dry runs neither consume signatures nor record claimer identity
+        for _ in 0..3 {
+            assert_eq!(
+                near_sdk::serde_json::to_value(create_dry_run_response(
+                    &state,
+                    &claimer,
+                    user.clone()
+                ))
+                .unwrap(),
+                near_sdk::serde_json::json!({ "would_succeed": true, "kyc": "approved" })
+            );
+        }
+        assert!(state
+            .claimer_identities
+            .as_ref()
+            .unwrap()
+            .get(&claimer)
+            .is_none());
+
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                signing_key,
+                claimer.clone(),
+                user,
+                false,
+                None,
+                None
+            ),
+            Ok(VerificationResponse::Approved(_))
+        );
+
+        // Same policies apply for dry runs and real verifications
+        let other_user = gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Approved);
+        assert_matches!(
+            create_dry_run_response(&state, &claimer, other_user.clone()),
+            VerificationResponse::DryRun(DryRunResponse {
+                would_succeed: false,
+                ..
+            })
+        );
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                signing_key,
+                claimer.clone(),
+                other_user,
+                false,
+                None,
+                None
+            ),
+            Err(AppError::ClaimerIdentityConflict(_))
+        );
+
+        let mut new_user = gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Approved);
+        new_user.created_at = Some(Utc::now());
+        let new_claimer = AccountId::new_unchecked("new.near".to_owned());
+        assert_matches!(
+            create_dry_run_response(&state, &new_claimer, new_user.clone()),
+            VerificationResponse::DryRun(DryRunResponse {
+                would_succeed: false,
+                ..
+            })
+        );
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                signing_key,
+                new_claimer.clone(),
+                new_user,
+                false,
+                None,
+                None
+            ),
+            Err(AppError::AccountTooNew)
+        );
+
+        let mut pending_user =
+            gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Unavailable);
+        pending_user.fv_status = VerificationStatus::Pending;
+        assert_matches!(
+            create_dry_run_response(&state, &new_claimer, pending_user),
+            VerificationResponse::DryRun(DryRunResponse {
+                would_succeed: false,
+                kyc_status: Some(VerificationStatus::Unavailable),
+            })
+        );
+    }
+
+    #[test]
+    fn test_ext_account_masked_debug() {
+        let ext_account = ExternalAccountId::from(
+            Uuid::parse_str("de223722-fe21-11ed-be56-0242ac120002").unwrap(),
+        );
+        assert_eq!(
+            format!("{ext_account:?}"),
+            r#"ExternalAccountId("de22***0002")"#
+        );
+        // Display is kept as is for tokens
+        assert_eq!(ext_account.to_string(), "de223722fe2111edbe560242ac120002");
+
+        let mut user = gen_verified_user(ext_account, VerificationStatus::Approved);
+        let debug = format!("{user:?}");
+        assert!(debug.contains("de22***0002"));
+        assert!(!debug.contains("de223722fe2111edbe560242ac120002"));
+
+        user.user_id = ExternalAccountId("short".to_owned());
+        assert!(format!("{user:?}").contains(r#"ExternalAccountId("***")"#));
+    }
+
+    #[test]
+    fn test_should_log_unmasked_ext_account() {
+        assert!(should_log_unmasked_ext_account(true, true));
+        assert!(!should_log_unmasked_ext_account(true, false));
+        assert!(!should_log_unmasked_ext_account(false, true));
+        assert!(!should_log_unmasked_ext_account(false, false));
+    }
+
+    #[test]
+    fn test_should_skip_captcha() {
+        assert!(should_skip_captcha(true, true));
+        assert!(!should_skip_captcha(true, false));
+        assert!(!should_skip_captcha(false, true));
+        assert!(!should_skip_captcha(false, false));
+    }
+
+    #[test]
+    fn test_refuse_on_kyc_rejected() {
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+        let rejected_user = gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Rejected);
+
+        let state = AppState::new(gen_app_config(false)).unwrap();
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                claimer.clone(),
+                rejected_user.clone(),
+                false,
+                None,
+                None
+            ),
+            Ok(VerificationResponse::Approved(ApprovedResponse {
+                kyc_status: Some(VerificationStatus::Rejected),
+                ..
+            }))
+        );
+
+        let mut config = gen_app_config(false);
+        config.refuse_on_kyc_rejected = true;
+        let state = AppState::new(config).unwrap();
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                claimer.clone(),
+                rejected_user,
+                false,
+                None,
+                None
+            ),
+            Err(AppError::KycRejected)
+        );
+
+        let approved_user = gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Approved);
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                claimer,
+                approved_user,
+                false,
+                None,
+                None
+            ),
+            Ok(VerificationResponse::Approved(_))
+        );
+    }
+
+    #[test]
+    fn test_verified_at() {
+        let config = gen_app_config(false);
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+        let mut verified_user =
+            gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        let verified_at = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        verified_user.verified_at = Some(verified_at);
+
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            claimer.clone(),
+            verified_user.clone(),
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+        let decoded_msg = VerifiedAccountToken::try_from_slice(
+            &general_purpose::STANDARD
+                .decode(&approved_res.message)
+                .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(decoded_msg.verified_at, verified_at.timestamp() as u64);
+        assert_ne!(decoded_msg.verified_at, decoded_msg.timestamp);
+
+        verified_user.verified_at = None;
+        assert_matches!(
+            create_approved_response(
+                &config,
+                &config.signer.credentials.signing_key,
+                claimer,
+                verified_user,
+                false,
+                None
+            ),
+            Err(AppError::Generic(_))
+        );
+    }
+
+    #[test]
+    fn test_kyc_code() {
+        let config = gen_app_config(false);
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+
+        for (kyc_status, expected) in [
+            (VerificationStatus::Unavailable, 0),
+            (VerificationStatus::Pending, 1),
+            (VerificationStatus::Approved, 2),
+            (VerificationStatus::Rejected, 3),
+            (VerificationStatus::Expired, 4),
+        ] {
+            let verified_user = gen_verified_user(Uuid::default().into(), kyc_status);
+            let approved_res = match create_approved_response(
+                &config,
+                &config.signer.credentials.signing_key,
+                claimer.clone(),
+                verified_user,
+                false,
+                None,
+            ) {
+                Ok(VerificationResponse::Approved(res)) => res,
+                _ => panic!("Not an approved verification"),
+            };
+            let decoded_msg = VerifiedAccountToken::try_from_slice(
+                &general_purpose::STANDARD
+                    .decode(&approved_res.message)
+                    .unwrap(),
+            )
+            .unwrap();
+
+            assert_eq!(decoded_msg.kyc_code, expected, "KYC status {kyc_status:?}");
+            assert_eq!(
+                decoded_msg.verified_kyc,
+                kyc_status == VerificationStatus::Approved
+            );
+        }
+    }
+
+    #[test]
+    fn test_audience() {
+        let mut config = gen_app_config(false);
+        let contract_a = AccountId::new_unchecked("contract-a.near".to_owned());
+        let contract_b = AccountId::new_unchecked("contract-b.near".to_owned());
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        let sign = |config: &AppConfig, audience: Option<AccountId>| match create_approved_response(
+            config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user.clone(),
+            false,
+            audience,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => {
+                general_purpose::STANDARD.decode(&res.message).unwrap()
+            }
+            _ => panic!("Not an approved verification"),
+        };
+
+        // Nothing is allowed nor appended by default
+        assert_matches!(
+            check_audience(&config, Some(&contract_a)),
+            Err(AppError::NotAllowedAudience(audience)) if audience == contract_a
+        );
+        assert_matches!(check_audience(&config, None), Ok(()));
+        assert_matches!(
+            VerifiedAccountToken::try_from_slice(&sign(&config, None)),
+            Ok(_)
+        );
+
+        config.signer.allowed_audiences = vec![contract_a.clone(), contract_b.clone()];
+        assert_matches!(check_audience(&config, Some(&contract_a)), Ok(()));
+        assert_matches!(check_audience(&config, Some(&contract_b)), Ok(()));
+        assert_matches!(
+            check_audience(
+                &config,
+                Some(&AccountId::new_unchecked("other.near".to_owned()))
+            ),
+            Err(AppError::NotAllowedAudience(_))
+        );
+
+        let message_a = sign(&config, Some(contract_a.clone()));
+        let message_b = sign(&config, Some(contract_b));
+        assert_ne!(message_a, message_b);
+
+        let mut buf = message_a.as_slice();
+        VerifiedAccountToken::deserialize(&mut buf).unwrap();
+        assert_eq!(
+            <Option<AccountId> as BorshDeserialize>::deserialize(&mut buf).unwrap(),
+            Some(contract_a)
+        );
+        assert!(buf.is_empty());
+
+        // Tokens without audience are still distinguishable
+        let mut message = sign(&config, None);
+        assert_eq!(message.pop(), Some(0));
+        assert_matches!(VerifiedAccountToken::try_from_slice(&message), Ok(_));
+    }
+
+    #[test]
+    fn test_cbor_token() {
+        let mut config = gen_app_config(false);
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        let sign = |config: &AppConfig| match create_approved_response(
+            config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user.clone(),
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+
+        // Not included by default
+        assert_eq!(sign(&config).cbor, None);
+
+        config.signer.cbor_token = true;
+        let approved_res = sign(&config);
+        let message = general_purpose::STANDARD
+            .decode(&approved_res.message)
+            .unwrap();
+        let token = VerifiedAccountToken::try_from_slice(&message).unwrap();
+
+        let cbor = general_purpose::STANDARD
+            .decode(approved_res.cbor.unwrap())
+            .unwrap();
+        let cbor_token = ciborium::de::from_reader::<CborToken, _>(cbor.as_slice()).unwrap();
+        assert_eq!(
+            cbor_token,
+            CborToken {
+                claimer: token.claimer.to_string(),
+                ext_account: token.ext_account.to_string(),
+                timestamp: token.timestamp,
+                verified_at: token.verified_at,
+                verified_kyc: token.verified_kyc,
+                kyc_code: token.kyc_code,
+                provider_id: token.provider_id as u8,
+                audience: None,
+            }
+        );
+        assert_eq!(cbor_token.claimer, "test.near");
+        assert_eq!(cbor_token.ext_account, verified_user.user_id.to_string());
+
+        // Signature still covers the borsh message
+        let signature = general_purpose::STANDARD
+            .decode(&approved_res.signature_ed25519)
+            .unwrap();
+        assert!(Signature::from_parts(KeyType::ED25519, &signature)
+            .unwrap()
+            .verify(
+                &message,
+                &config.signer.credentials.signing_key.public_key()
+            ));
+    }
+
+    #[test]
+    fn test_leaf_format() {
+        let gen_token = |claimer: &str| VerifiedAccountToken {
+            claimer: AccountId::new_unchecked(claimer.to_owned()),
+            ext_account: Uuid::from_str("f20181ba-fc0c-11ed-be56-0242ac120002")
+                .unwrap()
+                .into(),
+            timestamp: 1_700_000_000,
+            verified_at: 1_600_000_000,
+            verified_kyc: true,
+            kyc_code: 2,
+            provider_id: ProviderId::Fractal,
+            audience: None,
+        };
+
+        let leaf = gen_token("test.near").to_leaf();
+        assert_eq!(leaf, gen_token("test.near").to_leaf());
+        assert_eq!(leaf.len(), LEAF_LENGTH);
+        assert_eq!(
+            gen_token("28cda90838b6fa11b629747cf8173edc2d5bc010d1300d544f39cc19d4d69edb")
+                .to_leaf()
+                .len(),
+            LEAF_LENGTH
+        );
+        assert_ne!(leaf, gen_token("other.near").to_leaf());
+
+        assert_eq!(&leaf[..16], LEAF_DOMAIN_TAG);
+        assert_eq!(&leaf[16..48], Sha256::digest(b"test.near").as_slice());
+        assert_eq!(&leaf[80..88], &1_700_000_000u64.to_le_bytes());
+        assert_eq!(&leaf[88..96], &1_600_000_000u64.to_le_bytes());
+        assert_eq!(&leaf[96..], &[1, 2, 0]);
+
+        let mut config = gen_app_config(false);
+        config.signer.leaf_format = true;
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user,
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+        let message = general_purpose::STANDARD
+            .decode(&approved_res.message)
+            .unwrap();
+        assert_eq!(message.len(), LEAF_LENGTH);
+        assert_eq!(&message[..16], LEAF_DOMAIN_TAG);
+
+        // Fields appended to tokens would break the fixed width
+        assert_matches!(config.signer.check_leaf_format(), Ok(()));
+        config.signer.extra_claims = vec![ExtraClaim::HasPhone];
+        assert_matches!(config.signer.check_leaf_format(), Err(AppError::Generic(_)));
+        config.signer.extra_claims.clear();
+        config.signer.policy_hash = true;
+        assert_matches!(config.signer.check_leaf_format(), Err(AppError::Generic(_)));
+        config.signer.policy_hash = false;
+        config.signer.not_before = Some(Utc::now());
+        assert_matches!(config.signer.check_leaf_format(), Err(AppError::Generic(_)));
+    }
+
+    #[test]
+    fn test_not_before() {
+        let mut config = gen_app_config(false);
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        let sign = |config: &AppConfig| match create_approved_response(
+            config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user.clone(),
+            true,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+        let jwt_claims = |jwt: &str| {
+            near_sdk::serde_json::from_slice::<JwtClaims>(
+                &general_purpose::URL_SAFE_NO_PAD
+                    .decode(jwt.split('.').nth(1).unwrap())
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+
+        // Not included by default
+        let approved_res = sign(&config);
+        let message = general_purpose::STANDARD
+            .decode(&approved_res.message)
+            .unwrap();
+        assert_matches!(VerifiedAccountToken::try_from_slice(&message), Ok(_));
+        assert_eq!(jwt_claims(&approved_res.jwt.unwrap()).nbf, None);
+
+        let not_before = Utc::now() + chrono::Duration::minutes(10);
+        config.signer.not_before = Some(not_before);
+        let approved_res = sign(&config);
+        let message = general_purpose::STANDARD
+            .decode(&approved_res.message)
+            .unwrap();
+        let mut buf = message.as_slice();
+        let token = VerifiedAccountToken::deserialize(&mut buf).unwrap();
+        assert_eq!(
+            <u64 as BorshDeserialize>::deserialize(&mut buf).unwrap(),
+            not_before.timestamp() as u64
+        );
+        assert!(buf.is_empty());
+
+        let claims = jwt_claims(&approved_res.jwt.unwrap());
+        assert_eq!(claims.nbf, Some(not_before.timestamp() as u64));
+        assert_eq!(claims.iat, token.timestamp);
+        assert!(claims.nbf.unwrap() < claims.exp);
+    }
+
+    #[test]
+    fn test_not_before_config() {
+        let mut config = gen_app_config(false);
+        let now = Utc::now();
+        assert_matches!(config.signer.check_not_before(now), Ok(()));
+
+        config.signer.jwt_lifetime = 3600;
+        config.signer.not_before = Some(now + chrono::Duration::seconds(3599));
+        assert_matches!(config.signer.check_not_before(now), Ok(()));
+
+        config.signer.not_before = Some(now + chrono::Duration::seconds(3600));
+        assert_matches!(
+            config.signer.check_not_before(now),
+            Err(AppError::Generic(_))
+        );
+
+        // The shortest lifetime of KYC and uniqueness tiers applies
+        config.signer.not_before = Some(now + chrono::Duration::seconds(1800));
+        assert_matches!(config.signer.check_not_before(now), Ok(()));
+        config.signer.kyc_jwt_lifetime = Some(600);
+        assert_matches!(
+            config.signer.check_not_before(now),
+            Err(AppError::Generic(_))
+        );
+    }
+
+    #[test]
+    fn test_policy_hash() {
+        let mut config = gen_app_config(false);
+        let policy_hash = config.policy_hash();
+
+        // Stable for the same policy, regardless of unrelated settings
+        assert_eq!(gen_app_config(false).policy_hash(), policy_hash);
+        config.listen_address = "127.0.0.1:8081".to_owned();
+        config.signer.policy_hash = true;
+        assert_eq!(config.policy_hash(), policy_hash);
+
+        // Changed by required levels, TTL and uniqueness requirement
+        let mut other_config = gen_app_config(false);
+        other_config.verification_provider.strict_kyc_liveness = true;
+        assert_ne!(other_config.policy_hash(), policy_hash);
+        let mut other_config = gen_app_config(false);
+        other_config.signer.jwt_lifetime += 1;
+        assert_ne!(other_config.policy_hash(), policy_hash);
+        let mut other_config = gen_app_config(false);
+        other_config.one_time_claims.enabled = true;
+        assert_ne!(other_config.policy_hash(), policy_hash);
+        let mut other_config = gen_app_config(false);
+        other_config.verification_provider.required_scopes.kyc =
+            vec!["verification.basic:read".to_owned()];
+        assert_ne!(other_config.policy_hash(), policy_hash);
+        let mut other_config = gen_app_config(false);
+        other_config.accept_until = Some(Utc::now());
+        assert_ne!(other_config.policy_hash(), policy_hash);
+
+        // Not covered by typed data digest
+        assert_matches!(config.signer.check_typed_data(), Ok(()));
+        let mut typed_data_config = config.clone();
+        typed_data_config.signer.typed_data = true;
+        assert_matches!(
+            typed_data_config.signer.check_typed_data(),
+            Err(AppError::Generic(_))
+        );
+
+        // Appended after other fields when enabled
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user,
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+        let message = general_purpose::STANDARD
+            .decode(&approved_res.message)
+            .unwrap();
+        let mut buf = message.as_slice();
+        VerifiedAccountToken::deserialize(&mut buf).unwrap();
+        assert_eq!(buf, policy_hash);
+    }
+
+    #[test]
+    fn test_typed_data_digest() {
+        let gen_token = |claimer: &str| VerifiedAccountToken {
+            claimer: AccountId::new_unchecked(claimer.to_owned()),
+            ext_account: Uuid::from_str("f20181ba-fc0c-11ed-be56-0242ac120002")
+                .unwrap()
+                .into(),
+            timestamp: 1_700_000_000,
+            verified_at: 1_600_000_000,
+            verified_kyc: true,
+            kyc_code: 2,
+            provider_id: ProviderId::Fractal,
+            audience: None,
+        };
+
+        let digest = gen_token("test.near").typed_data_digest();
+        assert_eq!(
+            digest
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>(),
+            "f838fa6b9f0b67f9229ed1d7329ad4cb5e166753361b3e868ea6860cd60e735e"
+        );
+        assert_ne!(digest, gen_token("other.near").typed_data_digest());
+
+        let mut config = gen_app_config(false);
+        config.signer.typed_data = true;
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user,
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+
+        // Message still carries borsh token, while the signature covers its typed data digest
+        let message = general_purpose::STANDARD
+            .decode(&approved_res.message)
+            .unwrap();
+        let token = VerifiedAccountToken::try_from_slice(&message).unwrap();
+        let signature = Signature::from_parts(
+            KeyType::ED25519,
+            &general_purpose::STANDARD
+                .decode(&approved_res.signature_ed25519)
+                .unwrap(),
+        )
+        .unwrap();
+        let public_key = config.signer.credentials.signing_key.public_key();
+        assert!(signature.verify(&token.typed_data_digest(), &public_key));
+        assert!(!signature.verify(&message, &public_key));
+
+        // Appended fields aren't covered by the digest
+        assert_matches!(config.signer.check_typed_data(), Ok(()));
+        config.signer.raw_level_max_length = Some(32);
+        assert_matches!(config.signer.check_typed_data(), Err(AppError::Generic(_)));
+    }
+
+    #[test]
+    fn test_sign_hash() {
+        let mut config = gen_app_config(false);
+        config.signer.sign_hash = true;
+        config.signer.extra_claims = vec![ExtraClaim::HasEmail];
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user,
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+
+        // Message carries the full token, while the signature covers its digest
+        let message = general_purpose::STANDARD
+            .decode(&approved_res.message)
+            .unwrap();
+        let signature = Signature::from_parts(
+            KeyType::ED25519,
+            &general_purpose::STANDARD
+                .decode(&approved_res.signature_ed25519)
+                .unwrap(),
+        )
+        .unwrap();
+        let public_key = config.signer.credentials.signing_key.public_key();
+        assert!(signature.verify(&Sha512_256::digest(&message), &public_key));
+        assert!(!signature.verify(&message, &public_key));
+
+        // Typed data already signs a digest
+        assert_matches!(config.signer.check_typed_data(), Ok(()));
+        config.signer.extra_claims = vec![];
+        config.signer.typed_data = true;
+        assert_matches!(config.signer.check_typed_data(), Err(AppError::Generic(_)));
+    }
+
+    #[tokio::test]
+    async fn test_verify_signature_sign_hash() {
+        let mut config = gen_app_config(false);
+        config.signer.sign_hash = true;
+        let signing_key = config.signer.credentials.signing_key.clone();
+        let state = AppState::new(config).unwrap();
+        let message = b"some message".to_vec();
+
+        let verify = |signed_payload: Vec<u8>| {
+            let state = state.clone();
+            let message = message.clone();
+            let signature = sign_ed25519(&signing_key, &signed_payload, true).unwrap();
+            async move {
+                let req = VerifySignatureReq {
+                    message: general_purpose::STANDARD.encode(message),
+                    signature_ed25519: general_purpose::STANDARD.encode(signature),
+                };
+                verify_signature(State(state), Json(req))
+                    .await
+                    .unwrap()
+                    .0
+                    .valid
+            }
+        };
+
+        assert!(verify(Sha512_256::digest(&message).to_vec()).await);
+        assert!(!verify(message.clone()).await);
+    }
+
+    #[test]
+    fn test_tier_jwt_lifetime() {
+        let mut config = gen_app_config(false);
+        config.signer.kyc_jwt_lifetime = Some(600);
+        let jwt_claims = |kyc_status| {
+            let approved_res = match create_approved_response(
+                &config,
+                &config.signer.credentials.signing_key,
+                AccountId::new_unchecked("test.near".to_owned()),
+                gen_verified_user(Uuid::default().into(), kyc_status),
+                true,
+                None,
+            ) {
+                Ok(VerificationResponse::Approved(res)) => res,
+                _ => panic!("Not an approved verification"),
+            };
+            near_sdk::serde_json::from_slice::<JwtClaims>(
+                &general_purpose::URL_SAFE_NO_PAD
+                    .decode(approved_res.jwt.unwrap().split('.').nth(1).unwrap())
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+
+        let claims = jwt_claims(VerificationStatus::Approved);
+        assert!(claims.kyc);
+        assert_eq!(claims.exp, claims.iat + 600);
+
+        let claims = jwt_claims(VerificationStatus::Pending);
+        assert!(!claims.kyc);
+        assert_eq!(claims.exp, claims.iat + 3600);
+    }
+
+    #[test]
+    fn test_decode_token() {
+        let mut config = gen_app_config(false);
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        let audience = AccountId::new_unchecked("contract.near".to_owned());
+        let sign = |config: &AppConfig, audience: Option<AccountId>| {
+            let approved_res = match create_approved_response(
+                config,
+                &config.signer.credentials.signing_key,
+                AccountId::new_unchecked("test.near".to_owned()),
+                verified_user.clone(),
+                false,
+                audience,
+            ) {
+                Ok(VerificationResponse::Approved(res)) => res,
+                _ => panic!("Not an approved verification"),
+            };
+            general_purpose::STANDARD
+                .decode(&approved_res.message)
+                .unwrap()
+        };
+
+        let message = sign(&config, None);
+        let token = VerifiedAccountToken::try_from_slice(&message).unwrap();
+        assert_eq!(
+            decode_token(&config, &message).unwrap(),
+            DecodeResponse {
+                claimer: token.claimer.clone(),
+                ext_account: token.ext_account.to_string(),
+                timestamp: token.timestamp,
+                verified_at: token.verified_at,
+                verified_kyc: true,
+                kyc_code: token.kyc_code,
+                provider_id: token.provider_id as u8,
+                extra_claims: vec![],
+                raw_level: None,
+                audience: None,
+                not_before: None,
+                policy_hash: None,
+            }
+        );
+
+        // Appended fields are decoded as configured
+        config.signer.extra_claims = vec![ExtraClaim::HasEmail, ExtraClaim::WalletCount];
+        config.signer.raw_level_max_length = Some(32);
+        config.signer.allowed_audiences = vec![audience.clone()];
+        config.signer.not_before = Some(Utc.timestamp_opt(1_700_000_000, 0).unwrap());
+        config.signer.policy_hash = true;
+        let message = sign(&config, Some(audience.clone()));
+        let decoded = decode_token(&config, &message).unwrap();
+        assert_eq!(decoded.claimer.as_str(), "test.near");
+        assert_eq!(
+            decoded.extra_claims,
+            vec![
+                DecodedExtraClaim {
+                    claim: ExtraClaim::HasEmail,
+                    value: 1,
+                },
+                DecodedExtraClaim {
+                    claim: ExtraClaim::WalletCount,
+                    value: 2,
+                },
+            ]
+        );
+        assert_eq!(decoded.raw_level.as_deref(), Some("uniqueness"));
+        assert_eq!(decoded.audience, Some(audience));
+        assert_eq!(decoded.not_before, Some(1_700_000_000));
+        assert_eq!(
+            decoded.policy_hash,
+            Some(general_purpose::STANDARD.encode(config.policy_hash()))
+        );
+
+        // Fields not matching the config are malformed
+        config.signer.not_before = None;
+        assert_matches!(
+            decode_token(&config, &message),
+            Err(AppError::MalformedRequest(_))
+        );
+        assert_matches!(
+            decode_token(&config, &message[..10]),
+            Err(AppError::MalformedRequest(_))
+        );
+        config.signer.leaf_format = true;
+        assert_matches!(
+            decode_token(&config, &message),
+            Err(AppError::MalformedRequest(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode_endpoint() {
+        let config = gen_app_config(false);
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user,
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+        let app = create_router(AppState::new(config).unwrap());
+        let decode_req = |message: &str| {
+            axum::http::Request::post("/decode")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(
+                    near_sdk::serde_json::json!({ "message": message }).to_string(),
+                ))
+                .unwrap()
+        };
+
+        let res = app
+            .clone()
+            .oneshot(decode_req(&approved_res.message))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["claimer"], "test.near");
+        assert_eq!(body["verified_kyc"], true);
+        assert!(body["ext_account"].is_string());
+
+        for message in ["not base64!", "dGVzdA=="] {
+            let res = app.clone().oneshot(decode_req(message)).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn test_pseudonymized_ext_account() {
+        let ext_account: ExternalAccountId = Uuid::from_str("f20181ba-fc0c-11ed-be56-0242ac120002")
+            .unwrap()
+            .into();
+        let other_ext_account: ExternalAccountId = Uuid::new_v4().into();
+
+        let pseudonym = ext_account.pseudonymize("some_salt");
+        assert_eq!(pseudonym, ext_account.pseudonymize("some_salt"));
+        assert_eq!(pseudonym.as_ref().len(), 64);
+        assert_ne!(pseudonym, ext_account);
+        assert_ne!(pseudonym, ext_account.pseudonymize("other_salt"));
+        assert_ne!(pseudonym, other_ext_account.pseudonymize("some_salt"));
+
+        let mut config = gen_app_config(false);
+        config.signer.ext_account_salt = Some(Secret::from("some_salt"));
+        let verified_user = gen_verified_user(ext_account.clone(), VerificationStatus::Approved);
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user,
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+        let message = general_purpose::STANDARD
+            .decode(&approved_res.message)
+            .unwrap();
+        let token = VerifiedAccountToken::try_from_slice(&message).unwrap();
+        assert_eq!(token.ext_account, pseudonym);
+    }
+
+    #[test]
+    fn test_cosigning_keys() {
+        let mut config = gen_app_config(false);
+        config.signer.cosigning_keys = vec![
+            near_crypto::SecretKey::from_random(KeyType::ED25519),
+            near_crypto::SecretKey::from_random(KeyType::ED25519),
+        ];
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user.clone(),
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+        let message = general_purpose::STANDARD
+            .decode(&approved_res.message)
+            .unwrap();
+
+        assert_eq!(approved_res.signatures.len(), 3);
+        assert_eq!(
+            approved_res.signatures[0].signature_ed25519,
+            approved_res.signature_ed25519
+        );
+
+        let keys = std::iter::once(&config.signer.credentials.signing_key)
+            .chain(&config.signer.cosigning_keys);
+        for (key, key_signature) in keys.zip(&approved_res.signatures) {
+            assert_eq!(
+                key_signature.key_id,
+                general_purpose::STANDARD.encode(key.public_key().key_data())
+            );
+            let signature = Signature::from_parts(
+                KeyType::ED25519,
+                &general_purpose::STANDARD
+                    .decode(&key_signature.signature_ed25519)
+                    .unwrap(),
+            )
+            .unwrap();
+            assert!(signature.verify(&message, &key.public_key()));
+        }
+
+        // Omitted without co-signing keys
+        let config = gen_app_config(false);
+        let approved_res = match create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user,
+            false,
+            None,
+        ) {
+            Ok(VerificationResponse::Approved(res)) => res,
+            _ => panic!("Not an approved verification"),
+        };
+        assert!(approved_res.signatures.is_empty());
+    }
+
+    #[test]
+    fn test_verify_own_signature_toggle() {
+        let mut config = gen_app_config(false);
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+
+        for verify_own_signature in [true, false] {
+            config.signer.verify_own_signature = verify_own_signature;
+            let approved_res = match create_approved_response(
+                &config,
+                &config.signer.credentials.signing_key,
+                claimer.clone(),
+                verified_user.clone(),
+                false,
+                None,
+            ) {
+                Ok(VerificationResponse::Approved(res)) => res,
+                _ => panic!("Not an approved verification"),
+            };
+
+            let message = general_purpose::STANDARD
+                .decode(&approved_res.message)
+                .unwrap();
+            let signature = Signature::from_parts(
+                KeyType::ED25519,
+                &general_purpose::STANDARD
+                    .decode(&approved_res.signature_ed25519)
+                    .unwrap(),
+            )
+            .unwrap();
+            assert!(signature.verify(
+                &message,
+                &config.signer.credentials.signing_key.public_key()
+            ));
+
+            let token = VerifiedAccountToken::try_from_slice(&message).unwrap();
+            assert_eq!(token.claimer, claimer);
+            assert_eq!(token.ext_account, verified_user.user_id);
+            assert_eq!(approved_res.kyc_status, Some(VerificationStatus::Approved));
+        }
+    }
+
+    #[test]
+    fn test_raw_level() {
+        let mut config = gen_app_config(false);
+        config.signer.extra_claims = vec![ExtraClaim::HasEmail];
+        config.signer.raw_level_max_length = Some(32);
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+
+        let sign = |config: &AppConfig, user: FractalUser| {
+            let approved_res = match create_approved_response(
+                config,
+                &config.signer.credentials.signing_key,
+                claimer.clone(),
+                user,
+                false,
+                None,
+            ) {
+                Ok(VerificationResponse::Approved(res)) => res,
+                _ => panic!("Not an approved verification"),
+            };
+            general_purpose::STANDARD
+                .decode(&approved_res.message)
+                .unwrap()
+        };
+
+        let mut verified_user =
+            gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        verified_user.raw_level = Some("uniqueness+future_level".to_owned());
+        let message = sign(&config, verified_user.clone());
+        let mut buf = message.as_slice();
+        assert_matches!(VerifiedAccountToken::deserialize(&mut buf), Ok(_));
+        assert_eq!(<u8 as BorshDeserialize>::deserialize(&mut buf).unwrap(), 1);
+        assert_eq!(
+            <String as BorshDeserialize>::deserialize(&mut buf).unwrap(),
+            "uniqueness+future_level"
+        );
+        assert!(buf.is_empty());
+
+        // Length-limited
+        config.signer.raw_level_max_length = Some(10);
+        let message = sign(&config, verified_user.clone());
+        let mut buf = message.as_slice();
+        VerifiedAccountToken::deserialize(&mut buf).unwrap();
+        <u8 as BorshDeserialize>::deserialize(&mut buf).unwrap();
+        assert_eq!(
+            <String as BorshDeserialize>::deserialize(&mut buf).unwrap(),
+            "uniqueness"
+        );
+        assert!(buf.is_empty());
+
+        // Not appended by default
+        config.signer.raw_level_max_length = None;
+        let mut message = sign(&config, verified_user);
+        assert_eq!(message.pop(), Some(1));
+        assert_matches!(VerifiedAccountToken::try_from_slice(&message), Ok(_));
+
+        assert_eq!(truncate_raw_level("basic+liveness", 0), "");
+        assert_eq!(truncate_raw_level("uniquenéss", 8), "uniquen");
+    }
+
+    #[test]
+    fn test_extra_claims() {
+        let mut config = gen_app_config(false);
+        config.signer.extra_claims = vec![ExtraClaim::HasEmail];
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+
+        let sign = |config: &AppConfig, user: FractalUser| {
+            let approved_res = match create_approved_response(
+                config,
+                &config.signer.credentials.signing_key,
+                claimer.clone(),
+                user,
+                false,
+                None,
+            ) {
+                Ok(VerificationResponse::Approved(res)) => res,
+                _ => panic!("Not an approved verification"),
+            };
+            general_purpose::STANDARD
+                .decode(&approved_res.message)
+                .unwrap()
+        };
+
+        let mut verified_user =
+            gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        let mut message = sign(&config, verified_user.clone());
+        assert_eq!(message.pop(), Some(1));
+        // Core fields are kept as is
+        assert_matches!(VerifiedAccountToken::try_from_slice(&message), Ok(_));
+
+        verified_user.email_count = 0;
+        let mut message = sign(&config, verified_user.clone());
+        assert_eq!(message.pop(), Some(0));
+        assert_matches!(VerifiedAccountToken::try_from_slice(&message), Ok(_));
+
+        config.signer.extra_claims = vec![
+            ExtraClaim::WalletCount,
+            ExtraClaim::HasPhone,
+            ExtraClaim::HasWallet,
+        ];
+        let message = sign(&config, verified_user.clone());
+        assert_eq!(&message[message.len() - 3..], &[2, 0, 1]);
+
+        // Not appended to leaf format tokens
+        config.signer.leaf_format = true;
+        assert_matches!(config.signer.check_leaf_format(), Err(AppError::Generic(_)));
+    }
+
+    #[test]
+    fn test_provider_id_borsh_serde() {
+        let serialized = VerifiedAccountToken {
+            claimer: AccountId::new_unchecked("test.near".to_owned()),
+            ext_account: Uuid::default().into(),
+            timestamp: Utc::now().timestamp() as u64,
+            verified_at: Utc::now().timestamp() as u64,
+            verified_kyc: true,
+            kyc_code: 2,
+            provider_id: ProviderId::Fractal,
+            audience: None,
+        }
+        .try_to_vec()
+        .unwrap();
+
+        // Provider id is serialized as a single byte, followed by verification time and KYC code
+        assert_eq!(serialized[serialized.len() - 10], 0);
+        assert_eq!(
+            VerifiedAccountToken::try_from_slice(serialized.as_slice())
+                .unwrap()
+                .provider_id,
+            ProviderId::Fractal
+        );
+    }
+
+    #[tokio::test]
+    async fn test_access_log() {
+        #[derive(Clone)]
+        struct LogWriter(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for LogWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let logs = LogWriter(Default::default());
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_max_level(tracing::Level::INFO)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = create_router(AppState::new(gen_app_config(false)).unwrap());
+        let res = app
+            .oneshot(
+                axum::http::Request::get("/health")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let line = logs
+            .lines()
+            .find(|line| line.contains("Access log"))
+            .expect("Access log line is missing");
+
+        assert!(line.contains("INFO"), "Unexpected level: {line}");
+        assert!(line.contains("method=GET"), "Missing method: {line}");
+        assert!(line.contains("path=/health"), "Missing path: {line}");
+        assert!(line.contains("status=200"), "Missing status: {line}");
+        assert!(line.contains("latency_ms="), "Missing latency: {line}");
+        assert!(
+            line.contains("outcome=\"success\""),
+            "Missing outcome: {line}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_mode() {
+        let state = AppState::new(gen_app_config(false)).unwrap();
+        let app = create_router(state.clone());
+
+        let verify_req = || {
+            axum::http::Request::post("/verify")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(
+                    r#"{
+                        "claimer": "test1.test.near",
+                        "code": "some_code",
+                        "captcha": "some_captcha",
+                        "redirect_uri": "https://some_url"
+                    }"#,
+                ))
+                .unwrap()
+        };
+        let health_req = || {
+            axum::http::Request::get("/health")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        state.set_maintenance_mode(true);
+
+        let res = app.clone().oneshot(verify_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["code"], "maintenance");
+
+        let res = app.clone().oneshot(health_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        state.set_maintenance_mode(false);
+
+        // Request gets rejected for a not allowed account instead
+        let res = app.oneshot(verify_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_definitive_captcha_result() {
+        assert!(is_definitive_captcha_result(&Ok(true)));
+        assert!(is_definitive_captcha_result(&Ok(false)));
+        assert!(is_definitive_captcha_result(&Err(AppError::CaptchaError(
+            captcha::CaptchaError::InvalidAction
+        ))));
+        assert!(!is_definitive_captcha_result(&Err(AppError::CaptchaError(
+            captcha::CaptchaError::NoProviders
+        ))));
+        assert!(!is_definitive_captcha_result(&Err(
+            AppError::DeadlineExceeded("Request budget is exhausted".to_owned())
+        )));
+    }
+
+    #[test]
+    fn test_captcha_failure_mode() {
+        let mut config = gen_app_config(false);
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+        let provider_failure = || {
+            Err(AppError::CaptchaError(captcha::CaptchaError::ParseFailure(
+                near_sdk::serde_json::from_str::<()>("<html>").unwrap_err(),
+            )))
+        };
+        let user_failure = || Err(AppError::CaptchaError(captcha::CaptchaError::InvalidAction));
+
+        // Fails closed by default
+        assert_eq!(config.captcha_failure_mode, CaptchaFailureMode::FailClosed);
+        assert_matches!(
+            check_captcha_result(&config, &claimer, provider_failure()),
+            Err(AppError::CaptchaError(_))
+        );
+        assert_matches!(
+            check_captcha_result(&config, &claimer, user_failure()),
+            Err(AppError::CaptchaError(_))
+        );
+
+        // Only provider failures are accepted when failing open
+        config.captcha_failure_mode = CaptchaFailureMode::FailOpen;
+        assert_matches!(
+            check_captcha_result(&config, &claimer, provider_failure()),
+            Ok(())
+        );
+        assert_matches!(
+            check_captcha_result(&config, &claimer, user_failure()),
+            Err(AppError::CaptchaError(_))
+        );
+        assert_matches!(
+            check_captcha_result(&config, &claimer, Ok(false)),
+            Err(AppError::SuspiciousUser)
+        );
+        assert_matches!(
+            check_captcha_result(
+                &config,
+                &claimer,
+                Err(AppError::DeadlineExceeded(
+                    "captcha verification".to_owned()
+                ))
+            ),
+            Err(AppError::DeadlineExceeded(_))
+        );
+        assert_matches!(check_captcha_result(&config, &claimer, Ok(true)), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_campaign_window() {
+        let verify_req = || {
+            axum::http::Request::post("/verify")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(
+                    r#"{
+                        "claimer": "test1.test.near",
+                        "code": "some_code",
+                        "captcha": "some_captcha",
+                        "redirect_uri": "https://some_url"
+                    }"#,
+                ))
+                .unwrap()
+        };
+        let health_req = || {
+            axum::http::Request::get("/health")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        // Before the window
+        let mut config = gen_app_config(false);
+        config.accept_from = Some(Utc::now() + chrono::Duration::days(1));
+        let app = create_router(AppState::new(config).unwrap());
+
+        let res = app.clone().oneshot(verify_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["code"], "outside_campaign_window");
+        assert!(body["window"]["accept_from"].is_string());
+        assert!(body["window"]["accept_until"].is_null());
+
+        let res = app.oneshot(health_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // After the window
+        let mut config = gen_app_config(false);
+        config.accept_from = Some(Utc::now() - chrono::Duration::days(2));
+        config.accept_until = Some(Utc::now() - chrono::Duration::days(1));
+        let app = create_router(AppState::new(config).unwrap());
+
+        let res = app.clone().oneshot(verify_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["code"], "outside_campaign_window");
+        assert!(body["window"]["accept_until"].is_string());
+
+        let res = app.oneshot(health_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // Within the window request gets rejected for a not allowed account instead
+        let mut config = gen_app_config(false);
+        config.accept_from = Some(Utc::now() - chrono::Duration::days(1));
+        config.accept_until = Some(Utc::now() + chrono::Duration::days(1));
+        let app = create_router(AppState::new(config).unwrap());
+
+        let res = app.oneshot(verify_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // Inverted window is rejected at load
+        let mut config = gen_app_config(false);
+        config.accept_from = Some(Utc::now() + chrono::Duration::days(1));
+        config.accept_until = Some(Utc::now());
+        assert_matches!(AppState::new(config).err(), Some(AppError::Generic(_)));
+    }
+
+    #[test]
+    fn test_claimer_binding() {
+        let implicit_claimer = AccountId::new_unchecked(
+            "28cda90838b6fa11b629747cf8173edc2d5bc010d1300d544f39cc19d4d69edb".to_owned(),
+        );
+        let mut user = gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Approved);
+
+        // Implicit claimer not linked to the user
+        assert_matches!(
+            check_claimer_binding(&implicit_claimer, &user),
+            Err(AppError::ClaimerNotBound(claimer)) if claimer == implicit_claimer
+        );
+        user.near_wallets =
+            vec!["ff00000000000000000000000000000000000000000000000000000000000000".to_owned()];
+        assert_matches!(
+            check_claimer_binding(&implicit_claimer, &user),
+            Err(AppError::ClaimerNotBound(_))
+        );
+
+        // Implicit claimer linked as a NEAR wallet
+        user.near_wallets.push(implicit_claimer.to_string());
+        assert_matches!(check_claimer_binding(&implicit_claimer, &user), Ok(()));
+
+        // Named claimers aren't bound
+        user.near_wallets.clear();
+        assert_matches!(
+            check_claimer_binding(&AccountId::new_unchecked("test.near".to_owned()), &user),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_min_account_age() {
+        let mut config = gen_app_config(false);
+        // 30 days
+        config.min_account_age = Some(30 * 24 * 60 * 60);
+        let state = AppState::new(config).unwrap();
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+
+        let mut old_user =
+            gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Unavailable);
+        old_user.created_at = Some(Utc::now() - chrono::Duration::days(31));
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                claimer.clone(),
+                old_user,
+                false,
+                None,
+                None
+            ),
+            Ok(VerificationResponse::Approved(_))
+        );
+
+        let mut new_user =
+            gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Unavailable);
+        new_user.created_at = Some(Utc::now() - chrono::Duration::days(1));
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                claimer.clone(),
+                new_user,
+                false,
+                None,
+                None
+            ),
+            Err(AppError::AccountTooNew)
+        );
+
+        let mut unknown_user =
+            gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Unavailable);
+        unknown_user.created_at = None;
+        assert_matches!(
+            sign_approved_user(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                claimer,
+                unknown_user,
+                false,
+                None,
+                None
+            ),
+            Err(AppError::AccountTooNew)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_content_negotiation() {
+        let app = create_router(AppState::new(gen_app_config(false)).unwrap());
+        let verify_req = |accept: &str| {
+            axum::http::Request::post("/verify")
+                .header("content-type", "application/json")
+                .header("accept", accept)
+                .body(axum::body::Body::from(
+                    r#"{
+                        "claimer": "test1.test.near",
+                        "code": "some_code",
+                        "captcha": "some_captcha",
+                        "redirect_uri": "https://some_url"
+                    }"#,
+                ))
+                .unwrap()
+        };
+
+        let res = app.clone().oneshot(verify_req("text/plain")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(res.headers()["content-type"], "text/plain; charset=utf-8");
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(
+            body,
+            "not_allowed_account: Allowed only implicit account id or named sub-account from .near root account"
+        );
+
+        let res = app.oneshot(verify_req("application/json")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(res.headers()["content-type"], "application/json");
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["code"], "not_allowed_account");
+        assert_eq!(body["reason"], "account_not_allowed");
+    }
+
+    #[tokio::test]
+    async fn test_refreshed_token_echoed() {
+        let provider_url = spawn_mock_server(
+            Router::new()
+                .route(
+                    "/oauth/token",
+                    post(|| async {
+                        Json(near_sdk::serde_json::json!({
+                            "access_token": "refreshed_access_token",
+                            "refresh_token": "refreshed_refresh_token",
+                            "token_type": "Bearer",
+                            "expires_in": 7200,
+                            "created_at": Utc::now().timestamp(),
+                        }))
+                    }),
+                )
+                .route(
+                    "/users/me",
+                    get(|| async {
+                        Json(near_sdk::serde_json::json!({
+                            "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                            "emails": [],
+                            "phones": [],
+                            "wallets": [],
+                            "verification_cases": [{
+                                "id": "37c01d4e-fe22-11ed-be56-0242ac120002",
+                                "created_at": "2023-05-19 22:08:09 UTC",
+                                "updated_at": "2023-05-19 22:09:23 UTC",
+                                "level": "uniqueness",
+                                "status": "done",
+                                "credential": "approved",
+                                "details": { "liveness": true }
+                            }]
+                        }))
+                    }),
+                ),
+        );
+
+        let mut config = gen_app_config(false);
+        config.verification_provider.request_token_url = format!("{provider_url}/oauth/token");
+        config.verification_provider.request_user_url = format!("{provider_url}/users/me");
+        let state = AppState::new(config).unwrap();
+
+        let verify = |expires_at| {
+            let state = state.clone();
+            async move {
+                let token = verification_provider::FractalTokenKind::OAuth {
+                    token: OAuthToken {
+                        access_token: "some_auth_token".to_owned(),
+                        refresh_token: "some_refresh_token".to_owned(),
+                        expires_at,
+                        scopes: None,
+                    },
+                    redirect_uri: "https://some_url".to_owned(),
+                };
+                let mut body = near_sdk::serde_json::to_value(token).unwrap();
+                body["claimer"] = "test.near".into();
+                let req = near_sdk::serde_json::from_value::<VerificationReq>(body).unwrap();
+
+                match process_verification(state, HeaderMap::new(), req, &|_| ()).await {
+                    Ok(Json(VerificationResponse::Approved(res))) => res,
+                    _ => panic!("Not an approved verification"),
+                }
+            }
+        };
+
+        let approved_res = verify(Utc::now() - chrono::Duration::hours(1)).await;
+        assert_matches!(
+            approved_res.token,
+            Some(OAuthToken { access_token, refresh_token, .. })
+                if access_token == "refreshed_access_token" && refresh_token == "refreshed_refresh_token"
+        );
+
+        // Not refreshed token isn't echoed
+        let approved_res = verify(Utc::now() + chrono::Duration::days(1)).await;
+        assert_eq!(approved_res.token, None);
+    }
+
+    #[tokio::test]
+    async fn test_additional_tokens() {
+        let uniqueness_case = near_sdk::serde_json::json!({
+            "id": "37c01d4e-fe22-11ed-be56-0242ac120002",
+            "created_at": "2023-05-19 22:08:09 UTC",
+            "updated_at": "2023-05-19 22:09:23 UTC",
+            "level": "uniqueness",
+            "status": "done",
+            "credential": "approved",
+            "details": { "liveness": true }
+        });
+        let kyc_case = near_sdk::serde_json::json!({
+            "id": "07f10ea2-fe22-11ed-be56-0242ac120002",
+            "created_at": "2023-05-24 19:51:21 UTC",
+            "updated_at": "2023-05-24 19:59:19 UTC",
+            "level": "basic+liveness",
+            "status": "done",
+            "credential": "approved",
+            "details": { "liveness": true }
+        });
+        let provider_url = spawn_mock_server(
+            Router::new()
+                .route(
+                    "/oauth/token",
+                    post(|| async {
+                        Json(near_sdk::serde_json::json!({
+                            "access_token": "kyc_token",
+                            "refresh_token": "refreshed_refresh_token",
+                            "token_type": "Bearer",
+                            "expires_in": 7200,
+                            "created_at": Utc::now().timestamp(),
+                            "scope": "uid:read",
+                        }))
+                    }),
+                )
+                .route(
+                    "/users/me",
+                    get(|headers: HeaderMap| async move {
+                        let (uid, cases) = match headers
+                            .get("authorization")
+                            .and_then(|value| value.to_str().ok())
+                        {
+                            Some("Bearer uniqueness_token") => (
+                                "de223722-fe21-11ed-be56-0242ac120002",
+                                vec![uniqueness_case],
+                            ),
+                            Some("Bearer kyc_token") => {
+                                ("de223722-fe21-11ed-be56-0242ac120002", vec![kyc_case])
+                            }
+                            Some("Bearer full_token") => (
+                                "de223722-fe21-11ed-be56-0242ac120002",
+                                vec![uniqueness_case, kyc_case],
+                            ),
+                            _ => ("5d7c0b1e-fe21-11ed-be56-0242ac120002", vec![kyc_case]),
+                        };
+
+                        Json(near_sdk::serde_json::json!({
+                            "uid": uid,
+                            "emails": [],
+                            "phones": [],
+                            "wallets": [],
+                            "verification_cases": cases
+                        }))
+                    }),
+                ),
+        );
+
+        let mut config = gen_app_config(false);
+        config.verification_provider.request_token_url = format!("{provider_url}/oauth/token");
+        config.verification_provider.request_user_url = format!("{provider_url}/users/me");
+        let state = AppState::new(config).unwrap();
+
+        let gen_token = |access_token: &str| verification_provider::FractalTokenKind::OAuth {
             token: OAuthToken {
-                access_token: "some_auth_token".to_owned(),
+                access_token: access_token.to_owned(),
                 refresh_token: "some_refresh_token".to_owned(),
-                expires_at: Utc::now(),
+                expires_at: Utc::now() + chrono::Duration::days(1),
+                scopes: None,
             },
-            fv_status: VerificationStatus::Approved,
-            kyc_status: VerificationStatus::Unavailable,
+            redirect_uri: "https://some_url".to_owned(),
         };
-        let approved_res =
-            match create_approved_response(&config, claimer.clone(), verified_user.clone()) {
-                Ok(VerificationResponse::Approved(res)) => res,
-                _ => panic!("Not an approved verification"),
-            };
+        let verify = |access_token: &str, additional_tokens: &[&str]| {
+            let state = state.clone();
+            let mut body = near_sdk::serde_json::to_value(gen_token(access_token)).unwrap();
+            body["claimer"] = "test.near".into();
+            body["additional_tokens"] = additional_tokens
+                .iter()
+                .map(|access_token| {
+                    near_sdk::serde_json::to_value(gen_token(access_token)).unwrap()
+                })
+                .collect();
+            let req = near_sdk::serde_json::from_value::<VerificationReq>(body).unwrap();
 
-        let credentials = &config.signer.credentials;
+            async move { process_verification(state, HeaderMap::new(), req, &|_| ()).await }
+        };
+        let verified_kyc = |res: Result<Json<VerificationResponse>, AppError>| match res {
+            Ok(Json(VerificationResponse::Approved(res))) => {
+                let message = general_purpose::STANDARD.decode(&res.message).unwrap();
+                let token = VerifiedAccountToken::try_from_slice(&message).unwrap();
+                assert_eq!(
+                    res.kyc_status,
+                    Some(if token.verified_kyc {
+                        VerificationStatus::Approved
+                    } else {
+                        VerificationStatus::Unavailable
+                    })
+                );
+                token.verified_kyc
+            }
+            _ => panic!("Not an approved verification"),
+        };
 
-        let decoded_bytes = general_purpose::STANDARD
-            .decode(&approved_res.message)
-            .unwrap();
+        assert!(!verified_kyc(verify("uniqueness_token", &[]).await));
+        assert_matches!(
+            verify("kyc_token", &[]).await,
+            Err(AppError::FaceVerificationMissed)
+        );
 
-        assert!(Signature::from_parts(
-            KeyType::ED25519,
-            &general_purpose::STANDARD
-                .decode(&approved_res.signature_ed25519)
-                .unwrap()
+        // Uniqueness and KYC of different sessions are aggregated into full KYC, in any order
+        assert!(verified_kyc(
+            verify("uniqueness_token", &["kyc_token"]).await
+        ));
+        assert!(verified_kyc(
+            verify("kyc_token", &["uniqueness_token"]).await
+        ));
+
+        // Tokens of different users aren't aggregated
+        assert_matches!(
+            verify("uniqueness_token", &["other_user_token"]).await,
+            Err(AppError::TokenUserMismatch)
+        );
+
+        // Remaining tokens are skipped once fully verified
+        assert!(verified_kyc(
+            verify("full_token", &["other_user_token"]).await
+        ));
+
+        assert_matches!(
+            verify(
+                "uniqueness_token",
+                &["kyc_token"; MAX_ADDITIONAL_TOKENS + 1]
+            )
+            .await,
+            Err(AppError::MalformedRequest(_))
+        );
+
+        // Refreshed additional tokens are echoed back by their positions
+        let mut body = near_sdk::serde_json::to_value(gen_token("uniqueness_token")).unwrap();
+        body["claimer"] = "test.near".into();
+        let mut expired_token = gen_token("expired_kyc_token");
+        if let verification_provider::FractalTokenKind::OAuth { token, .. } = &mut expired_token {
+            token.expires_at = Utc::now() - chrono::Duration::minutes(1);
+        }
+        body["additional_tokens"] = near_sdk::serde_json::to_value([expired_token]).unwrap();
+        let req = near_sdk::serde_json::from_value::<VerificationReq>(body).unwrap();
+        match process_verification(state.clone(), HeaderMap::new(), req, &|_| ()).await {
+            Ok(Json(VerificationResponse::Approved(res))) => {
+                assert_eq!(res.token, None);
+                assert_matches!(
+                    &res.additional_tokens[..],
+                    [Some(OAuthToken { access_token, .. })] if access_token == "kyc_token"
+                );
+            }
+            _ => panic!("Not an approved verification"),
+        }
+
+        // Not echoed if none was refreshed
+        match verify("uniqueness_token", &["kyc_token"]).await {
+            Ok(Json(VerificationResponse::Approved(res))) => {
+                assert!(res.additional_tokens.is_empty())
+            }
+            _ => panic!("Not an approved verification"),
+        }
+    }
+
+    #[test]
+    fn test_response_field_aliases() {
+        let mut config = gen_app_config(false);
+        let verified_user = gen_verified_user(Uuid::default().into(), VerificationStatus::Approved);
+        let res = create_approved_response(
+            &config,
+            &config.signer.credentials.signing_key,
+            AccountId::new_unchecked("test.near".to_owned()),
+            verified_user,
+            false,
+            None,
         )
-        .unwrap()
-        .verify(&decoded_bytes, &credentials.signing_key.public_key()));
+        .unwrap();
 
-        let decoded_msg = VerifiedAccountToken::try_from_slice(&decoded_bytes).unwrap();
+        // Short names by default
+        let value = config.response_fields.apply(&res);
+        assert!(value["m"].is_string());
+        assert!(value["sig"].is_string());
+        assert!(value["kyc"].is_string());
 
-        assert_matches!(decoded_msg, VerifiedAccountToken {
-            claimer: claimer_res,
-            ext_account: ext_account_res,
-            timestamp: _,
-            verified_kyc: false,
-        } if claimer_res == claimer && ext_account_res == verified_user.user_id);
+        config.response_fields.aliases = std::collections::HashMap::from([
+            ("m".to_owned(), "message".to_owned()),
+            ("sig".to_owned(), "signature".to_owned()),
+            ("kyc".to_owned(), "kyc_status".to_owned()),
+            ("jwt".to_owned(), "token_jwt".to_owned()),
+        ]);
+        let value = config.response_fields.apply(&res);
+        assert_eq!(
+            value["message"],
+            near_sdk::serde_json::to_value(&res).unwrap()["m"]
+        );
+        assert!(value["signature"].is_string());
+        assert!(value["kyc_status"].is_string());
+        assert!(value.get("m").is_none());
+        assert!(value.get("sig").is_none());
+        assert!(value.get("kyc").is_none());
+        // Absent fields aren't aliased
+        assert!(value.get("token_jwt").is_none());
+
+        // Both naming schemes
+        config.response_fields.keep_default_names = true;
+        let value = config.response_fields.apply(&res);
+        assert_eq!(value["message"], value["m"]);
+        assert_eq!(value["signature"], value["sig"]);
+        assert_eq!(value["kyc_status"], value["kyc"]);
     }
 
     #[test]
-    fn test_approved_account_response_with_kyc() {
-        let config = gen_app_config(false);
+    fn test_response_field_aliases_check() {
+        let mut config = gen_app_config(false);
+        assert_matches!(
+            config.response_fields.check_aliases(RESPONSE_FIELD_NAMES),
+            Ok(())
+        );
 
-        let claimer = AccountId::new_unchecked("test.near".to_owned());
-        let verified_user = FractalUser {
-            user_id: Uuid::default().into(),
+        config.response_fields.aliases = std::collections::HashMap::from([
+            ("m".to_owned(), "message".to_owned()),
+            ("sig".to_owned(), "signature".to_owned()),
+        ]);
+        assert_matches!(
+            config.response_fields.check_aliases(RESPONSE_FIELD_NAMES),
+            Ok(())
+        );
+
+        // Aliases must be unique
+        config
+            .response_fields
+            .aliases
+            .insert("sigs".to_owned(), "signature".to_owned());
+        assert_matches!(
+            config.response_fields.check_aliases(RESPONSE_FIELD_NAMES),
+            Err(AppError::Generic(_))
+        );
+        config.response_fields.aliases.remove("sigs");
+
+        // Aliases can't take other field names
+        config
+            .response_fields
+            .aliases
+            .insert("kyc".to_owned(), "token".to_owned());
+        assert_matches!(
+            config.response_fields.check_aliases(RESPONSE_FIELD_NAMES),
+            Err(AppError::Generic(_))
+        );
+        assert_matches!(AppState::new(config).err(), Some(AppError::Generic(_)));
+    }
+
+    #[tokio::test]
+    async fn test_strict_request_parsing() {
+        let verify_req = |uri: &str, body: near_sdk::serde_json::Value| {
+            axum::http::Request::post(uri)
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap()
+        };
+        let req = near_sdk::serde_json::json!({
+            "claimer": "test1.test.near",
+            "code": "some_code",
+            "captcha": "some_captcha",
+            "redirect_uri": "https://some_url",
+            "redirect_url": "https://some_url",
+        });
+
+        // Unknown fields are ignored by default
+        let app = create_router(AppState::new(gen_app_config(false)).unwrap());
+        let res = app
+            .oneshot(verify_req("/verify", req.clone()))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["code"], "not_allowed_account");
+
+        let mut config = gen_app_config(false);
+        config.strict_request_parsing = true;
+        let app = create_router(AppState::new(config).unwrap());
+
+        let res = app
+            .clone()
+            .oneshot(verify_req("/verify", req.clone()))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["code"], "unknown_fields");
+        assert_eq!(
+            body["fields"],
+            near_sdk::serde_json::json!(["redirect_url"])
+        );
+
+        let res = app
+            .clone()
+            .oneshot(verify_req(
+                "/verify/batch",
+                near_sdk::serde_json::json!([req.clone()]),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(
+            body["fields"],
+            near_sdk::serde_json::json!(["[0].redirect_url"])
+        );
+
+        // Valid requests are parsed as usual
+        let mut req = req;
+        req.as_object_mut().unwrap().remove("redirect_url");
+        let res = app
+            .clone()
+            .oneshot(verify_req("/verify", req))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["code"], "not_allowed_account");
+
+        // Oracle encoded token requests and their additional tokens are valid as well
+        let oauth_token = verification_provider::FractalTokenKind::OAuth {
             token: OAuthToken {
                 access_token: "some_auth_token".to_owned(),
                 refresh_token: "some_refresh_token".to_owned(),
-                expires_at: Utc::now(),
+                expires_at: Utc::now() + chrono::Duration::days(1),
+                scopes: Some(vec!["uid:read".to_owned()]),
             },
-            fv_status: VerificationStatus::Approved,
-            kyc_status: VerificationStatus::Approved,
+            redirect_uri: "https://some_url".to_owned(),
         };
+        let mut req = near_sdk::serde_json::to_value(&oauth_token).unwrap();
+        req["claimer"] = "test1.test.near".into();
+        req["additional_tokens"] = near_sdk::serde_json::json!([oauth_token]);
+        let res = app
+            .clone()
+            .oneshot(verify_req("/verify", req.clone()))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["code"], "not_allowed_account");
 
-        let approved_res =
-            match create_approved_response(&config, claimer.clone(), verified_user.clone()) {
-                Ok(VerificationResponse::Approved(res)) => res,
-                _ => panic!("Not an approved verification"),
+        req["additional_tokens"][0]["redirect_url"] = "https://some_url".into();
+        let res = app.oneshot(verify_req("/verify", req)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(
+            body["fields"],
+            near_sdk::serde_json::json!(["additional_tokens[0].redirect_url"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_batch() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let provider_url = spawn_mock_server(Router::new().route(
+            "/users/me",
+            get({
+                let in_flight = in_flight.clone();
+                let max_in_flight = max_in_flight.clone();
+                move || async move {
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(current, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                    Json(near_sdk::serde_json::json!({
+                        "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                        "emails": [],
+                        "phones": [],
+                        "wallets": [],
+                        "verification_cases": [{
+                            "id": "37c01d4e-fe22-11ed-be56-0242ac120002",
+                            "created_at": "2023-05-19 22:08:09 UTC",
+                            "updated_at": "2023-05-19 22:09:23 UTC",
+                            "level": "uniqueness",
+                            "status": "done",
+                            "credential": "approved",
+                            "details": { "liveness": true }
+                        }]
+                    }))
+                }
+            }),
+        ));
+
+        let mut config = gen_app_config(false);
+        config.verification_provider.request_user_url = format!("{provider_url}/users/me");
+        config.max_batch_concurrency = 2;
+        config.max_batch_size = 6;
+        let app = create_router(AppState::new(config.clone()).unwrap());
+
+        let gen_req = |claimer: &str| {
+            let token = verification_provider::FractalTokenKind::OAuth {
+                token: OAuthToken {
+                    access_token: "some_auth_token".to_owned(),
+                    refresh_token: "some_refresh_token".to_owned(),
+                    expires_at: Utc::now() + chrono::Duration::days(1),
+                    scopes: None,
+                },
+                redirect_uri: "https://some_url".to_owned(),
             };
+            let mut req = near_sdk::serde_json::to_value(token).unwrap();
+            req["claimer"] = claimer.into();
+            req
+        };
+        let batch_req = |reqs: near_sdk::serde_json::Value| {
+            axum::http::Request::post("/verify/batch")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(reqs.to_string()))
+                .unwrap()
+        };
 
-        let credentials = &config.signer.credentials;
+        // Not allowed named sub-accounts fail, while the rest of the batch is verified
+        let claimers = [
+            "test0.near",
+            "test1.test.near",
+            "test2.near",
+            "test3.near",
+            "test4.test.near",
+            "test5.near",
+        ];
+        let reqs = claimers.iter().map(|claimer| gen_req(claimer)).collect();
+        let res = app
+            .clone()
+            .oneshot(batch_req(near_sdk::serde_json::Value::Array(reqs)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let results =
+            near_sdk::serde_json::from_slice::<Vec<near_sdk::serde_json::Value>>(&body).unwrap();
+        assert_eq!(results.len(), claimers.len());
 
-        let decoded_bytes = general_purpose::STANDARD
+        for (claimer, result) in claimers.iter().zip(results) {
+            if claimer.ends_with(".test.near") {
+                assert_eq!(result["code"], "not_allowed_account");
+            } else {
+                let message = general_purpose::STANDARD
+                    .decode(result["m"].as_str().unwrap())
+                    .unwrap();
+                let token = VerifiedAccountToken::try_from_slice(&message).unwrap();
+                assert_eq!(token.claimer.as_str(), *claimer);
+            }
+        }
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+
+        // Batches over the size limit are rejected as a whole
+        let reqs = (0..7).map(|_| gen_req("test.near")).collect();
+        let res = app
+            .clone()
+            .oneshot(batch_req(near_sdk::serde_json::Value::Array(reqs)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["code"], "malformed_request");
+
+        // Empty batch gets empty results
+        let res = app
+            .oneshot(batch_req(near_sdk::serde_json::json!([])))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"[]");
+
+        // Early failing items are padded to the minimum duration too
+        config.min_verify_duration_ms = Some(300);
+        let app = create_router(AppState::new(config).unwrap());
+        let started_at = std::time::Instant::now();
+        let res = app
+            .oneshot(batch_req(near_sdk::serde_json::json!([gen_req(
+                "test.test.near"
+            )])))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(started_at.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_user_hook() {
+        struct DowngradeKycHook;
+
+        impl VerifiedUserHook for DowngradeKycHook {
+            fn apply<'a>(
+                &'a self,
+                user: &'a mut FractalUser,
+            ) -> futures_util::future::BoxFuture<'a, Result<(), AppError>> {
+                Box::pin(async move {
+                    if user.kyc_status == VerificationStatus::Approved {
+                        user.kyc_status = VerificationStatus::Pending;
+                    }
+                    Ok(())
+                })
+            }
+        }
+
+        let provider_url = spawn_mock_server(Router::new().route(
+            "/users/me",
+            get(|| async {
+                Json(near_sdk::serde_json::json!({
+                    "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                    "emails": [],
+                    "phones": [],
+                    "wallets": [],
+                    "verification_cases": [{
+                        "id": "37c01d4e-fe22-11ed-be56-0242ac120002",
+                        "created_at": "2023-05-19 22:08:09 UTC",
+                        "updated_at": "2023-05-19 22:09:23 UTC",
+                        "level": "basic+liveness+uniqueness",
+                        "status": "done",
+                        "credential": "approved",
+                        "details": { "liveness": true }
+                    }]
+                }))
+            }),
+        ));
+
+        let mut config = gen_app_config(false);
+        config.verification_provider.request_user_url = format!("{provider_url}/users/me");
+        let mut state = AppState::new(config).unwrap();
+
+        let verify = |state: AppState| async move {
+            let token = verification_provider::FractalTokenKind::OAuth {
+                token: OAuthToken {
+                    access_token: "some_auth_token".to_owned(),
+                    refresh_token: "some_refresh_token".to_owned(),
+                    expires_at: Utc::now() + chrono::Duration::days(1),
+                    scopes: None,
+                },
+                redirect_uri: "https://some_url".to_owned(),
+            };
+            let mut body = near_sdk::serde_json::to_value(token).unwrap();
+            body["claimer"] = "test.near".into();
+            let req = near_sdk::serde_json::from_value::<VerificationReq>(body).unwrap();
+
+            match process_verification(state, HeaderMap::new(), req, &|_| ()).await {
+                Ok(Json(VerificationResponse::Approved(res))) => res,
+                _ => panic!("Not an approved verification"),
+            }
+        };
+
+        // Users are unchanged by default
+        let approved_res = verify(state.clone()).await;
+        assert_eq!(approved_res.kyc_status, Some(VerificationStatus::Approved));
+
+        state.user_hook = Arc::new(DowngradeKycHook);
+        let approved_res = verify(state).await;
+        assert_eq!(approved_res.kyc_status, Some(VerificationStatus::Pending));
+
+        let message = general_purpose::STANDARD
             .decode(&approved_res.message)
             .unwrap();
+        let token = VerifiedAccountToken::try_from_slice(&message).unwrap();
+        assert!(!token.verified_kyc);
+        assert_eq!(token.kyc_code, VerificationStatus::Pending.code());
+    }
 
-        assert!(Signature::from_parts(
-            KeyType::ED25519,
-            &general_purpose::STANDARD
-                .decode(&approved_res.signature_ed25519)
+    #[tokio::test]
+    async fn test_verify_stream() {
+        let provider_url = spawn_mock_server(Router::new().route(
+            "/users/me",
+            get(|| async {
+                Json(near_sdk::serde_json::json!({
+                    "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                    "emails": [],
+                    "phones": [],
+                    "wallets": [],
+                    "verification_cases": [{
+                        "id": "37c01d4e-fe22-11ed-be56-0242ac120002",
+                        "created_at": "2023-05-19 22:08:09 UTC",
+                        "updated_at": "2023-05-19 22:09:23 UTC",
+                        "level": "uniqueness",
+                        "status": "done",
+                        "credential": "approved",
+                        "details": { "liveness": true }
+                    }]
+                }))
+            }),
+        ));
+
+        let mut config = gen_app_config(false);
+        config.verification_provider.request_user_url = format!("{provider_url}/users/me");
+        let public_key = config.signer.credentials.signing_key.public_key();
+        let app = create_router(AppState::new(config).unwrap());
+
+        let token = verification_provider::FractalTokenKind::OAuth {
+            token: OAuthToken {
+                access_token: "some_auth_token".to_owned(),
+                refresh_token: "some_refresh_token".to_owned(),
+                expires_at: Utc::now() + chrono::Duration::days(1),
+                scopes: None,
+            },
+            redirect_uri: "https://some_url".to_owned(),
+        };
+        let mut body = near_sdk::serde_json::to_value(token).unwrap();
+        body["claimer"] = "test.near".into();
+
+        let res = app
+            .oneshot(
+                axum::http::Request::post("/verify/stream")
+                    .header("content-type", "application/json")
+                    .body(axum::body::Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], "text/event-stream");
+
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let events = body
+            .split("\n\n")
+            .filter_map(|event| {
+                let mut name = None;
+                let mut data = None;
+                for line in event.lines() {
+                    if let Some(value) = line.strip_prefix("event:") {
+                        name = Some(value.trim());
+                    } else if let Some(value) = line.strip_prefix("data:") {
+                        data = Some(value.trim());
+                    }
+                }
+                Some((name?, data?))
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            events[..3],
+            [
+                ("progress", r#""exchanging_token""#),
+                ("progress", r#""fetching_user""#),
+                ("progress", r#""signing""#),
+            ]
+        );
+        assert_eq!(events.len(), 4);
+
+        let (name, data) = events[3];
+        assert_eq!(name, "done");
+        let res = near_sdk::serde_json::from_str::<near_sdk::serde_json::Value>(data).unwrap();
+        let message = general_purpose::STANDARD
+            .decode(res["m"].as_str().unwrap())
+            .unwrap();
+        let signature = general_purpose::STANDARD
+            .decode(res["sig"].as_str().unwrap())
+            .unwrap();
+        assert!(Signature::from_parts(KeyType::ED25519, &signature)
+            .unwrap()
+            .verify(&message, &public_key));
+        assert_matches!(
+            VerifiedAccountToken::try_from_slice(&message),
+            Ok(VerifiedAccountToken { claimer, .. }) if claimer.as_str() == "test.near"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_single_use_nonce() {
+        let provider_url = spawn_mock_server(Router::new().route(
+            "/users/me",
+            get(|headers: HeaderMap| async move {
+                let status = match headers
+                    .get("authorization")
+                    .and_then(|value| value.to_str().ok())
+                {
+                    Some("Bearer approved_token") => "approved",
+                    _ => "pending",
+                };
+
+                Json(near_sdk::serde_json::json!({
+                    "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                    "emails": [],
+                    "phones": [],
+                    "wallets": [],
+                    "verification_cases": [{
+                        "id": "37c01d4e-fe22-11ed-be56-0242ac120002",
+                        "created_at": "2023-05-19 22:08:09 UTC",
+                        "updated_at": "2023-05-19 22:09:23 UTC",
+                        "level": "uniqueness",
+                        "status": "done",
+                        "credential": status,
+                        "details": { "liveness": true }
+                    }]
+                }))
+            }),
+        ));
+        let claimer_key = near_crypto::SecretKey::from_random(KeyType::ED25519);
+        let claimer = crate::challenge::tests::implicit_account(&claimer_key);
+
+        let verify = |app: Router, access_token: &str, challenge: &challenge::ChallengeResponse| {
+            let solution = crate::challenge::tests::solve(challenge, &claimer_key);
+            let token = verification_provider::FractalTokenKind::OAuth {
+                token: OAuthToken {
+                    access_token: access_token.to_owned(),
+                    refresh_token: "some_refresh_token".to_owned(),
+                    expires_at: Utc::now() + chrono::Duration::days(1),
+                    scopes: None,
+                },
+                redirect_uri: "https://some_url".to_owned(),
+            };
+            let mut body = near_sdk::serde_json::to_value(token).unwrap();
+            body["claimer"] = claimer.to_string().into();
+            body["challenge"] = near_sdk::serde_json::json!({
+                "m": solution.message,
+                "sig": solution.signature_ed25519,
+                "publicKey": solution.public_key,
+                "signature": solution.signature,
+            });
+
+            async move {
+                let res = app
+                    .oneshot(
+                        axum::http::Request::post("/verify")
+                            .header("content-type", "application/json")
+                            .body(axum::body::Body::from(body.to_string()))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let status = res.status();
+                let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                let body =
+                    near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+                (status, body)
+            }
+        };
+
+        let mut config = gen_app_config(false);
+        config.verification_provider.request_user_url = format!("{provider_url}/users/me");
+        config.challenge.single_use = true;
+        let signing_key = config.signer.credentials.signing_key.clone();
+        let app = create_router(AppState::new(config.clone()).unwrap());
+        let challenge = challenge::issue(claimer.clone(), &signing_key).unwrap();
+
+        // Nonce isn't used up without an issued token
+        let (status, body) = verify(app.clone(), "pending_token", &challenge).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.get("m").is_none());
+
+        // Fresh nonce is signed once
+        let (status, body) = verify(app.clone(), "approved_token", &challenge).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["m"].is_string());
+
+        let (status, body) = verify(app.clone(), "approved_token", &challenge).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "nonce_reused");
+
+        // Another nonce isn't affected
+        let other_challenge = challenge::issue(claimer.clone(), &signing_key).unwrap();
+        let (status, body) = verify(app, "approved_token", &other_challenge).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["m"].is_string());
+
+        // Nonces aren't tracked by default
+        config.challenge.single_use = false;
+        let app = create_router(AppState::new(config).unwrap());
+        for _ in 0..2 {
+            let (status, body) = verify(app.clone(), "approved_token", &challenge).await;
+            assert_eq!(status, StatusCode::OK);
+            assert!(body["m"].is_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_invalid_case_dates() {
+        let verify = |updated_at: &'static str, allow_invalid_case_dates: bool| async move {
+            let provider_url = spawn_mock_server(
+                Router::new()
+                    .route(
+                        "/oauth/token",
+                        post(|| async {
+                            Json(near_sdk::serde_json::json!({
+                                "access_token": "some_access_token",
+                                "refresh_token": "some_refresh_token",
+                                "token_type": "Bearer",
+                                "expires_in": 7200,
+                                "created_at": Utc::now().timestamp(),
+                                "scope": "uid:read",
+                            }))
+                        }),
+                    )
+                    .route(
+                        "/users/me",
+                        get(move || async move {
+                            Json(near_sdk::serde_json::json!({
+                                "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                                "emails": [],
+                                "phones": [],
+                                "wallets": [],
+                                "verification_cases": [{
+                                    "id": "37c01d4e-fe22-11ed-be56-0242ac120002",
+                                    "created_at": "2023-05-19 22:08:09 UTC",
+                                    "updated_at": updated_at,
+                                    "level": "uniqueness",
+                                    "status": "done",
+                                    "credential": "approved",
+                                    "details": { "liveness": true }
+                                }, {
+                                    "id": "4e4c3a2a-fe22-11ed-be56-0242ac120002",
+                                    "created_at": "not a date",
+                                    "updated_at": "not a date",
+                                    "level": "basic+liveness",
+                                    "status": "pending",
+                                    "credential": "pending",
+                                    "details": { "liveness": true }
+                                }]
+                            }))
+                        }),
+                    ),
+            );
+
+            let mut config = gen_app_config(false);
+            config.verification_provider.allow_invalid_case_dates = allow_invalid_case_dates;
+            config.verification_provider.request_token_url = format!("{provider_url}/oauth/token");
+            config.verification_provider.request_user_url = format!("{provider_url}/users/me");
+            let res = create_router(AppState::new(config).unwrap())
+                .oneshot(
+                    axum::http::Request::post("/verify")
+                        .header("content-type", "application/json")
+                        .body(axum::body::Body::from(
+                            near_sdk::serde_json::json!({
+                                "claimer": "test.near",
+                                "code": "some_code",
+                                "redirect_uri": "https://some_url",
+                            })
+                            .to_string(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let status = res.status();
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body =
+                near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+            (status, body)
+        };
+
+        // Invalid dates fail the whole request by default
+        let (status, body) = verify("2023-05-19 22:09:23 UTC", false).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "verification_failure");
+
+        // Invalid dates of other cases are ignored
+        let (status, body) = verify("2023-05-19 22:09:23 UTC", true).await;
+        assert_eq!(status, StatusCode::OK);
+        let message = general_purpose::STANDARD
+            .decode(body["m"].as_str().unwrap())
+            .unwrap();
+        let token = VerifiedAccountToken::try_from_slice(&message).unwrap();
+        assert_eq!(
+            token.verified_at,
+            Utc.with_ymd_and_hms(2023, 5, 19, 22, 9, 23)
                 .unwrap()
-        )
-        .unwrap()
-        .verify(&decoded_bytes, &credentials.signing_key.public_key()));
+                .timestamp() as u64
+        );
 
-        let decoded_msg = VerifiedAccountToken::try_from_slice(&decoded_bytes).unwrap();
+        // Uniqueness approved at unknown time is refused with a dedicated code
+        let (status, body) = verify("not a date", true).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "unknown_verification_time");
+    }
 
-        assert_matches!(decoded_msg, VerifiedAccountToken {
-            claimer: claimer_res,
-            ext_account: ext_account_res,
-            timestamp: _,
-            verified_kyc: true,
-        } if claimer_res == claimer && ext_account_res == verified_user.user_id);
+    #[tokio::test]
+    async fn test_dry_run_captcha_not_consumed() {
+        let provider_url = spawn_mock_server(
+            Router::new()
+                .route(
+                    "/siteverify",
+                    post(|| async {
+                        Json(near_sdk::serde_json::json!({
+                            "success": true,
+                            "score": 0.9,
+                            "action": "homepage",
+                            "challenge_ts": Utc::now(),
+                            "hostname": "some_url"
+                        }))
+                    }),
+                )
+                .route(
+                    "/oauth/token",
+                    post(|| async {
+                        Json(near_sdk::serde_json::json!({
+                            "access_token": "some_access_token",
+                            "refresh_token": "some_refresh_token",
+                            "token_type": "Bearer",
+                            "expires_in": 7200,
+                            "created_at": Utc::now().timestamp(),
+                            "scope": "uid:read",
+                        }))
+                    }),
+                )
+                .route(
+                    "/users/me",
+                    get(|| async {
+                        Json(near_sdk::serde_json::json!({
+                            "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                            "emails": [],
+                            "phones": [],
+                            "wallets": [],
+                            "verification_cases": [{
+                                "id": "37c01d4e-fe22-11ed-be56-0242ac120002",
+                                "created_at": "2023-05-19 22:08:09 UTC",
+                                "updated_at": "2023-05-19 22:09:23 UTC",
+                                "level": "uniqueness",
+                                "status": "done",
+                                "credential": "approved",
+                                "details": { "liveness": true }
+                            }]
+                        }))
+                    }),
+                ),
+        );
+
+        let mut config = gen_app_config(false);
+        config.captcha = near_sdk::serde_json::from_value(near_sdk::serde_json::json!({
+            "action": "homepage",
+            "threshold": 0.5,
+            "secret": "some_secret",
+            "reuseWindow": 60,
+            "verifyUrl": format!("{provider_url}/siteverify"),
+        }))
+        .unwrap();
+        config.verification_provider.request_token_url = format!("{provider_url}/oauth/token");
+        config.verification_provider.request_user_url = format!("{provider_url}/users/me");
+        let app = create_router(AppState::new(config).unwrap());
+
+        let verify = |dry_run: bool| {
+            let app = app.clone();
+            async move {
+                let body = near_sdk::serde_json::json!({
+                    "claimer": "test.near",
+                    "code": "some_code",
+                    "captcha": "some_captcha",
+                    "redirect_uri": "https://some_url",
+                    "dry_run": dry_run,
+                });
+                let res = app
+                    .oneshot(
+                        axum::http::Request::post("/verify")
+                            .header("content-type", "application/json")
+                            .body(axum::body::Body::from(body.to_string()))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let status = res.status();
+                let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                let body =
+                    near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+                (status, body)
+            }
+        };
+
+        // Pre-flight doesn't consume the captcha token used by the following request
+        let (status, body) = verify(true).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["would_succeed"], true);
+
+        let (status, body) = verify(false).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["m"].is_string());
+
+        // Token is consumed by the signing request
+        let (status, body) = verify(false).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["code"], "captcha_reused");
+    }
+
+    #[tokio::test]
+    async fn test_verify_challenge() {
+        let provider_url = spawn_mock_server(
+            Router::new()
+                .route(
+                    "/siteverify",
+                    post(|| async {
+                        Json(near_sdk::serde_json::json!({
+                            "success": true,
+                            "score": 0.9,
+                            "action": "homepage",
+                            "challenge_ts": Utc::now(),
+                            "hostname": "some_url"
+                        }))
+                    }),
+                )
+                .route(
+                    "/oauth/token",
+                    post(|| async {
+                        Json(near_sdk::serde_json::json!({
+                            "access_token": "some_access_token",
+                            "refresh_token": "some_refresh_token",
+                            "token_type": "Bearer",
+                            "expires_in": 7200,
+                            "created_at": Utc::now().timestamp(),
+                            "scope": "uid:read",
+                        }))
+                    }),
+                )
+                .route(
+                    "/users/me",
+                    get(|| async {
+                        Json(near_sdk::serde_json::json!({
+                            "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                            "emails": [],
+                            "phones": [],
+                            "wallets": [],
+                            "verification_cases": [{
+                                "id": "37c01d4e-fe22-11ed-be56-0242ac120002",
+                                "created_at": "2023-05-19 22:08:09 UTC",
+                                "updated_at": "2023-05-19 22:09:23 UTC",
+                                "level": "uniqueness",
+                                "status": "done",
+                                "credential": "approved",
+                                "details": { "liveness": true }
+                            }]
+                        }))
+                    }),
+                ),
+        );
+
+        let mut config = gen_app_config(false);
+        config.challenge.required = true;
+        config.captcha = near_sdk::serde_json::from_value(near_sdk::serde_json::json!({
+            "action": "homepage",
+            "threshold": 0.5,
+            "secret": "some_secret",
+            "verifyUrl": format!("{provider_url}/siteverify"),
+        }))
+        .unwrap();
+        config.verification_provider.request_token_url = format!("{provider_url}/oauth/token");
+        config.verification_provider.request_user_url = format!("{provider_url}/users/me");
+        let app = create_router(AppState::new(config).unwrap());
+
+        let claimer_key = near_crypto::SecretKey::from_random(KeyType::ED25519);
+        let claimer = crate::challenge::tests::implicit_account(&claimer_key);
+        let verify_req = |challenge: Option<near_sdk::serde_json::Value>| {
+            let mut body = near_sdk::serde_json::json!({
+                "claimer": claimer,
+                "code": "some_code",
+                "captcha": "some_captcha",
+                "redirect_uri": "https://some_url",
+            });
+            if let Some(challenge) = challenge {
+                body["challenge"] = challenge;
+            }
+
+            axum::http::Request::post("/verify")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        // Challenge is required
+        let res = app.clone().oneshot(verify_req(None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["code"], "challenge_failed");
+
+        let res = app
+            .clone()
+            .oneshot(
+                axum::http::Request::get(format!("/challenge?claimer={claimer}"))
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        let challenge = challenge::ChallengeResponse {
+            message: body["m"].as_str().unwrap().to_owned(),
+            signature_ed25519: body["sig"].as_str().unwrap().to_owned(),
+        };
+
+        // Signed by a key not controlling the claimer account
+        let other_key = near_crypto::SecretKey::from_random(KeyType::ED25519);
+        let solution = crate::challenge::tests::solve(&challenge, &other_key);
+        let res = app
+            .clone()
+            .oneshot(verify_req(Some(near_sdk::serde_json::json!({
+                "m": solution.message,
+                "sig": solution.signature_ed25519,
+                "publicKey": solution.public_key,
+                "signature": solution.signature,
+            }))))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        assert_eq!(body["code"], "challenge_failed");
+
+        // Proven claimer proceeds to the user verification
+        let solution = crate::challenge::tests::solve(&challenge, &claimer_key);
+        let res = app
+            .oneshot(verify_req(Some(near_sdk::serde_json::json!({
+                "m": solution.message,
+                "sig": solution.signature_ed25519,
+                "publicKey": solution.public_key,
+                "signature": solution.signature,
+            }))))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = near_sdk::serde_json::from_slice::<near_sdk::serde_json::Value>(&body).unwrap();
+        let message = general_purpose::STANDARD
+            .decode(body["m"].as_str().unwrap())
+            .unwrap();
+        let token = VerifiedAccountToken::try_from_slice(&message).unwrap();
+        assert_eq!(token.claimer, claimer);
+    }
+
+    #[test]
+    fn test_unverified_user_response() {
+        let claimer = AccountId::new_unchecked("test.near".to_owned());
+        let mut unverified_user =
+            gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Unavailable);
+        unverified_user.fv_status = VerificationStatus::Unavailable;
+        unverified_user.has_verification_cases = false;
+        unverified_user.created_at = None;
+
+        let state = AppState::new(gen_app_config(false)).unwrap();
+        assert_matches!(
+            create_verification_response(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                claimer.clone(),
+                unverified_user.clone(),
+                false,
+                None,
+                None
+            ),
+            Err(AppError::FaceVerificationMissed)
+        );
+
+        let mut config = gen_app_config(false);
+        config.unverified_user_response = UnverifiedUserResponse::Status;
+        let state = AppState::new(config).unwrap();
+        let res = create_verification_response(
+            &state,
+            &state.config.signer.credentials.signing_key,
+            claimer.clone(),
+            unverified_user,
+            false,
+            None,
+            None,
+        );
+        assert_matches!(
+            &res,
+            Ok(VerificationResponse::Unverified(UnverifiedResponse {
+                uniqueness: false,
+                kyc_status: VerificationStatus::Unavailable,
+            }))
+        );
+        assert_eq!(
+            near_sdk::serde_json::to_value(res.unwrap()).unwrap(),
+            near_sdk::serde_json::json!({ "uniqueness": false, "kyc": "unavailable" })
+        );
+
+        // User with verification cases still fails as missed face verification
+        let mut user_with_cases =
+            gen_verified_user(Uuid::new_v4().into(), VerificationStatus::Unavailable);
+        user_with_cases.fv_status = VerificationStatus::Unavailable;
+        assert_matches!(
+            create_verification_response(
+                &state,
+                &state.config.signer.credentials.signing_key,
+                claimer,
+                user_with_cases,
+                false,
+                None,
+                None
+            ),
+            Err(AppError::FaceVerificationMissed)
+        );
     }
 
     #[test]
@@ -341,7 +5725,11 @@ mod tests {
                 .unwrap()
                 .into(),
             timestamp: Utc::now().timestamp() as u64,
+            verified_at: Utc::now().timestamp() as u64,
             verified_kyc: true,
+            kyc_code: 2,
+            provider_id: ProviderId::Fractal,
+            audience: None,
         }
         .try_to_vec()
         .unwrap();
@@ -355,17 +5743,94 @@ mod tests {
         );
     }
 
+    fn gen_verified_user(
+        user_id: ExternalAccountId,
+        kyc_status: VerificationStatus,
+    ) -> FractalUser {
+        FractalUser {
+            user_id,
+            token: OAuthToken {
+                access_token: "some_auth_token".to_owned(),
+                refresh_token: "some_refresh_token".to_owned(),
+                expires_at: Utc::now(),
+                scopes: None,
+            },
+            fv_status: VerificationStatus::Approved,
+            kyc_status,
+            provider_id: ProviderId::Fractal,
+            created_at: Some(Utc::now() - chrono::Duration::days(365)),
+            has_verification_cases: true,
+            verified_at: Some(Utc::now() - chrono::Duration::days(30)),
+            raw_level: Some("uniqueness".to_owned()),
+            pending_steps: vec![],
+            email_count: 1,
+            phone_count: 0,
+            wallet_count: 2,
+            near_wallets: vec![],
+        }
+    }
+
     fn gen_app_config(allow_named_sub_accounts: bool) -> AppConfig {
         let signing_key = near_crypto::SecretKey::from_random(near_crypto::KeyType::ED25519);
 
         AppConfig {
             signer: SignerConfig {
                 credentials: SignerCredentials { signing_key },
+                max_signatures_per_second: None,
+                jwt_lifetime: 3600,
+                kyc_jwt_lifetime: None,
+                tenant_keys: false,
+                leaf_format: false,
+                ext_account_salt: None,
+                verify_own_signature: true,
+                extra_claims: vec![],
+                expected_public_key: None,
+                cosigning_keys: vec![],
+                raw_level_max_length: None,
+                cbor_token: false,
+                allowed_audiences: vec![],
+                typed_data: false,
+                not_before: None,
+                sign_hash: false,
+                policy_hash: false,
             },
             listen_address: "0.0.0.0:8080".to_owned(),
-            verification_provider: Default::default(),
+            verification_provider: VerificationProviderConfig {
+                client_id: "some_client_id".to_owned(),
+                client_secret: Some(Secret::from("some_client_secret")),
+                ..Default::default()
+            },
             captcha: Default::default(),
             allow_named_sub_accounts,
+            claimer_identity_window: None,
+            access_log_level: Default::default(),
+            maintenance_mode: false,
+            min_account_age: None,
+            challenge: Default::default(),
+            unverified_user_response: Default::default(),
+            refuse_on_kyc_rejected: false,
+            compression: Default::default(),
+            dev_skip_captcha: false,
+            dev_log_ext_account: false,
+            verify_signature_endpoint: false,
+            kyc_api_key: None,
+            max_requests_per_ip: None,
+            normalize_claimer: true,
+            require_claimer_binding: false,
+            pending_steps: false,
+            instance_id: None,
+            one_time_claims: Default::default(),
+            request_budget_ms: None,
+            min_verify_duration_ms: None,
+            accept_from: None,
+            accept_until: None,
+            captcha_failure_mode: Default::default(),
+            ext_account_rate_limit: None,
+            max_batch_concurrency: 8,
+            max_batch_size: 100,
+            response_fields: Default::default(),
+            strict_request_parsing: false,
+            error_rate_threshold: None,
         }
     }
 }
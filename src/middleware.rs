@@ -0,0 +1,397 @@
+use crate::{
+    config::AccessLogLevel,
+    error::{AppError, ErrorCode, ErrorMessage},
+    AppState, VerificationReq,
+};
+use axum::{
+    body::{boxed, Body, Bytes, HttpBody},
+    extract::{ConnectInfo, FromRequest, State},
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Response header with id of the oracle instance
+pub const INSTANCE_HEADER: &str = "x-oracle-instance";
+
+/// Limiter of concurrent requests per client IP
+#[derive(Debug, Clone)]
+pub struct IpConcurrencyLimiter {
+    max_per_ip: usize,
+    in_flight: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+/// Permit of a single in-flight request, released on drop
+#[derive(Debug)]
+pub struct IpPermit {
+    ip: IpAddr,
+    in_flight: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl IpConcurrencyLimiter {
+    pub fn new(max_per_ip: usize) -> Self {
+        Self {
+            max_per_ip,
+            in_flight: Default::default(),
+        }
+    }
+
+    /// Acquires a permit for a request from the IP. Returns `None` if the limit is exceeded
+    pub fn try_acquire(&self, ip: IpAddr) -> Option<IpPermit> {
+        let mut in_flight = self.in_flight.lock().expect("IP limiter lock is poisoned");
+        let count = in_flight.entry(ip).or_default();
+
+        if *count >= self.max_per_ip {
+            return None;
+        }
+
+        *count += 1;
+        Some(IpPermit {
+            ip,
+            in_flight: self.in_flight.clone(),
+        })
+    }
+}
+
+impl Drop for IpPermit {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.lock().expect("IP limiter lock is poisoned");
+
+        if let Some(count) = in_flight.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Rejects requests from a client IP exceeding the concurrent requests limit
+pub async fn limit_per_ip<B>(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let permit = match (&state.ip_limiter, connect_info) {
+        (Some(limiter), Some(ConnectInfo(addr))) => match limiter.try_acquire(addr.ip()) {
+            Some(permit) => Some(permit),
+            None => {
+                tracing::warn!("Concurrent requests limit exceeded for IP {}", addr.ip());
+                return AppError::TooManyRequests.into_response();
+            }
+        },
+        _ => None,
+    };
+
+    let res = next.run(req).await;
+
+    // Owned by the response body, so streamed responses, e.g. event streams, hold the permit
+    // until the body is finished or the client disconnects
+    match permit {
+        Some(permit) => res.map(|body| {
+            boxed(body.map_data(move |data| {
+                let _permit = &permit;
+                data
+            }))
+        }),
+        None => res,
+    }
+}
+
+/// Attaches id of the oracle instance to every response
+pub async fn instance_header<B>(
+    State(state): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut res = next.run(req).await;
+
+    if let Ok(instance_id) = HeaderValue::from_str(&state.instance_id) {
+        res.headers_mut().insert(INSTANCE_HEADER, instance_id);
+    }
+
+    res
+}
+
+/// Emits a structured access log line per request
+pub async fn access_log<B>(
+    State(state): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let started_at = Instant::now();
+
+    let res = next.run(req).await;
+
+    let latency_ms = started_at.elapsed().as_millis() as u64;
+    let status = res.status().as_u16();
+    // Error code is attached to a response by `AppError`, never contains secrets
+    let outcome = res
+        .extensions()
+        .get::<ErrorCode>()
+        .map(|code| code.0)
+        .unwrap_or("success");
+
+    macro_rules! log_access {
+        ($level:ident) => {
+            tracing::$level!(%method, %path, status, latency_ms, outcome, "Access log")
+        };
+    }
+
+    match state.config.access_log_level {
+        AccessLogLevel::Off => (),
+        AccessLogLevel::Trace => log_access!(trace),
+        AccessLogLevel::Debug => log_access!(debug),
+        AccessLogLevel::Info => log_access!(info),
+        AccessLogLevel::Warn => log_access!(warn),
+        AccessLogLevel::Error => log_access!(error),
+    }
+
+    res
+}
+
+/// Responds with plain text `code: message` errors to clients preferring `text/plain` over JSON
+pub async fn negotiate_error_format<B>(req: Request<B>, next: Next<B>) -> Response {
+    let plain_text = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(prefers_plain_text)
+        .unwrap_or(false);
+
+    let res = next.run(req).await;
+
+    if !plain_text {
+        return res;
+    }
+
+    let code = res.extensions().get::<ErrorCode>().copied();
+    let message = res.extensions().get::<ErrorMessage>().copied();
+
+    match (code, message) {
+        (Some(ErrorCode(code)), Some(ErrorMessage(message))) => {
+            let (mut parts, _) = res.into_parts();
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            );
+            parts.headers.remove(header::CONTENT_LENGTH);
+
+            Response::from_parts(parts, boxed(Body::from(format!("{code}: {message}"))))
+        }
+        _ => res,
+    }
+}
+
+/// Counts verification requests and server side failures among them, if readiness depends on the error rate
+pub async fn track_error_rate<B>(
+    State(state): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let res = next.run(req).await;
+    state.record_verification(res.status().is_server_error());
+
+    res
+}
+
+/// Rejects verification requests with fields unknown to the request type if strict parsing is configured.
+/// Batches are checked per item, while not JSON bodies are left to be rejected by the handler
+pub async fn reject_unknown_fields(
+    State(state): State<AppState>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if !state.config.strict_request_parsing {
+        return next.run(req).await;
+    }
+
+    let (parts, body) = req.into_parts();
+    let bytes = match Bytes::from_request(Request::new(body), &()).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return AppError::MalformedRequest(format!("Failed to read request body. Error: {e}"))
+                .into_response()
+        }
+    };
+
+    let unknown = match near_sdk::serde_json::from_slice(&bytes) {
+        Ok(near_sdk::serde_json::Value::Array(items)) => items
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, item)| {
+                VerificationReq::unknown_fields(item)
+                    .into_iter()
+                    .map(move |name| format!("[{idx}].{name}"))
+            })
+            .collect(),
+        Ok(value) => VerificationReq::unknown_fields(&value),
+        Err(_) => vec![],
+    };
+    if !unknown.is_empty() {
+        tracing::debug!("Request has unknown fields: {unknown:?}");
+        return AppError::UnknownFields(unknown).into_response();
+    }
+
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+/// Checks if `text/plain` is preferred over `application/json` in the `Accept` header value,
+/// by q-values of their most specific media ranges. On a tie, the one listed first is preferred
+fn prefers_plain_text(accept: &str) -> bool {
+    let ranges = accept
+        .split(',')
+        .enumerate()
+        .filter_map(|(idx, range)| {
+            let mut params = range.split(';');
+            let media_range = params.next()?.trim();
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((idx, media_range, q))
+        })
+        .collect::<Vec<_>>();
+
+    // Q-value and position of the most specific media range matching a media type
+    let preference = |media_type: &str| {
+        let main_type = media_type.split('/').next().unwrap_or_default();
+        ranges
+            .iter()
+            .filter_map(|(idx, media_range, q)| {
+                let specificity = if *media_range == media_type {
+                    2
+                } else if media_range.strip_suffix("/*") == Some(main_type) {
+                    1
+                } else if *media_range == "*/*" {
+                    0
+                } else {
+                    return None;
+                };
+                Some((specificity, *q, *idx))
+            })
+            .max_by_key(|(specificity, ..)| *specificity)
+            .map(|(_, q, idx)| (q, idx))
+    };
+
+    match (preference("text/plain"), preference("application/json")) {
+        (Some((plain_q, _)), None) => plain_q > 0.0,
+        (Some((plain_q, plain_idx)), Some((json_q, json_idx))) => {
+            plain_q > json_q || (plain_q > 0.0 && plain_q == json_q && plain_idx < json_idx)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prefers_plain_text, IpConcurrencyLimiter};
+    use crate::VerificationReq;
+    use near_sdk::serde_json::json;
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_ip_concurrency_limiter() {
+        let limiter = IpConcurrencyLimiter::new(2);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other_ip: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let first = limiter.try_acquire(ip).unwrap();
+        let _second = limiter.try_acquire(ip).unwrap();
+        assert!(limiter.try_acquire(ip).is_none());
+        assert!(limiter.try_acquire(other_ip).is_some());
+
+        drop(first);
+        assert!(limiter.try_acquire(ip).is_some());
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let code_req = json!({
+            "claimer": "test.near",
+            "code": "some_code",
+            "captcha": "some_captcha",
+            "redirect_uri": "https://some_url",
+            "jwt": true,
+        });
+        assert!(VerificationReq::unknown_fields(&code_req).is_empty());
+
+        let raw_token_req = json!({
+            "claimer": "test.near",
+            "token": {
+                "access_token": "some_auth_token",
+                "refresh_token": "some_refresh_token",
+                "token_type": "Bearer",
+                "expires_in": 7200,
+                "created_at": 1700000000,
+                "scope": "uid:read",
+            },
+            "redirect_uri": "https://some_url",
+        });
+        assert!(VerificationReq::unknown_fields(&raw_token_req).is_empty());
+
+        let mut req = code_req;
+        req["token"] = json!("some_token");
+        req["extra"] = json!(1);
+        let mut unknown = VerificationReq::unknown_fields(&req);
+        unknown.sort();
+        assert_eq!(unknown, vec!["extra", "token"]);
+
+        let mut req = raw_token_req;
+        req["token"]["extra"] = json!(1);
+        assert_eq!(VerificationReq::unknown_fields(&req), vec!["token.extra"]);
+
+        // Oracle encoded token in JSON form
+        let mut oauth_token_req = json!({
+            "claimer": "test.near",
+            "token": {
+                "access_token": "some_auth_token",
+                "refresh_token": "some_refresh_token",
+                "expires_at": "2023-05-19T22:08:09Z",
+                "scopes": ["uid:read"],
+            },
+            "redirect_uri": "https://some_url",
+            "additional_tokens": [{ "token": "some_token", "redirect_uri": "https://some_url" }],
+        });
+        assert!(VerificationReq::unknown_fields(&oauth_token_req).is_empty());
+
+        oauth_token_req["token"]["expires_in"] = json!(7200);
+        oauth_token_req["additional_tokens"][0]["extra"] = json!(1);
+        assert_eq!(
+            VerificationReq::unknown_fields(&oauth_token_req),
+            vec!["token.expires_in", "additional_tokens[0].extra"]
+        );
+    }
+
+    #[test]
+    fn test_prefers_plain_text() {
+        assert!(prefers_plain_text("text/plain"));
+        assert!(prefers_plain_text(
+            "text/plain;q=0.9, application/json;q=0.8"
+        ));
+        assert!(prefers_plain_text("text/html, text/plain"));
+        assert!(!prefers_plain_text("application/json"));
+        assert!(!prefers_plain_text("application/json, text/plain"));
+        assert!(!prefers_plain_text("*/*"));
+        assert!(!prefers_plain_text(""));
+
+        // Q-values take precedence over the order
+        assert!(prefers_plain_text("application/json;q=0.5, text/plain"));
+        assert!(prefers_plain_text("application/json; q=0.5, text/*"));
+        assert!(prefers_plain_text("*/*;q=0.1, text/plain"));
+        assert!(!prefers_plain_text("text/plain;q=0.5, application/json"));
+        assert!(!prefers_plain_text("text/plain;q=0, */*"));
+        assert!(!prefers_plain_text("text/plain;q=0"));
+        assert!(!prefers_plain_text(
+            "application/json;q=0.9, text/plain;q=0.9"
+        ));
+    }
+}
@@ -1,12 +1,81 @@
-use near_crypto::SecretKey;
+use crate::error::AppError;
+use crate::utils::Secret;
+use crate::verification_provider::ExtraClaim;
+use base64::{engine::general_purpose, Engine};
+use chrono::{DateTime, Utc};
+use hkdf::Hkdf;
+use near_crypto::{ED25519SecretKey, KeyType, SecretKey, Signature};
 use near_sdk::serde::de::{self, Error};
 use near_sdk::serde::Deserialize;
+use near_sdk::AccountId;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{env::VarError, str::FromStr};
 
+/// Length of raw ed25519 signature in bytes
+pub const ED25519_SIGNATURE_LENGTH: usize = 64;
+/// Length of raw secp256k1 signature in bytes (including recovery id)
+pub const SECP256K1_SIGNATURE_LENGTH: usize = 65;
+/// HKDF salt of tenant signing keys derivation
+const TENANT_KEY_SALT: &[u8] = b"verification-oracle-tenant-key";
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
 pub struct SignerConfig {
     pub credentials: SignerCredentials,
+    /// Maximum number of signatures issued per second, unlimited if not set
+    #[serde(default)]
+    pub max_signatures_per_second: Option<u32>,
+    /// Lifetime of issued JWT attestations in seconds
+    #[serde(default = "default_jwt_lifetime")]
+    pub jwt_lifetime: u64,
+    /// Lifetime of JWT attestations with approved KYC in seconds, `jwt_lifetime` if not set
+    #[serde(default)]
+    pub kyc_jwt_lifetime: Option<u64>,
+    /// Whether to sign tokens of tenants with signing keys derived from the master signing key
+    #[serde(default)]
+    pub tenant_keys: bool,
+    /// Whether to sign fixed-width merkle leaf encoding of tokens instead of borsh
+    #[serde(default)]
+    pub leaf_format: bool,
+    /// Salt to embed pseudonymous `sha256(salt || ext_account)` instead of the raw external account id
+    #[serde(default)]
+    pub ext_account_salt: Option<Secret>,
+    /// Whether to verify each issued signature before responding
+    #[serde(default = "default_verify_own_signature")]
+    pub verify_own_signature: bool,
+    /// User fields appended in order to signed tokens, a single byte each
+    #[serde(default)]
+    pub extra_claims: Vec<ExtraClaim>,
+    /// Public key expected to match the signing key, e.g. the one registered in the contract
+    #[serde(default)]
+    pub expected_public_key: Option<String>,
+    /// Additional ed25519 keys co-signing each token, so a contract could require a threshold of signatures
+    #[serde(default, deserialize_with = "de_cosigning_keys")]
+    pub cosigning_keys: Vec<SecretKey>,
+    /// Maximum length in bytes of the raw provider level string appended to signed tokens, not appended if not set
+    #[serde(default)]
+    pub raw_level_max_length: Option<usize>,
+    /// Whether to include CBOR encoded token fields into responses along with the signed message
+    #[serde(default)]
+    pub cbor_token: bool,
+    /// Contract account ids allowed as token audiences, tokens have no audience if empty
+    #[serde(default)]
+    pub allowed_audiences: Vec<AccountId>,
+    /// Whether to sign typed data digest of tokens instead of the borsh message
+    #[serde(default)]
+    pub typed_data: bool,
+    /// Time tokens become valid at, appended to signed tokens if set
+    #[serde(default)]
+    pub not_before: Option<DateTime<Utc>>,
+    /// Whether to append the hash of the effective verification policy to signed tokens
+    #[serde(default)]
+    pub policy_hash: bool,
+    /// Whether to sign SHA-512/256 digest of the message instead of the message itself
+    #[serde(default)]
+    pub sign_hash: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -19,33 +88,505 @@ impl<'de> Deserialize<'de> for SignerCredentials {
     where
         D: de::Deserializer<'de>,
     {
-        let properties: std::collections::HashMap<String, String> =
+        let properties: HashMap<String, String> =
             Deserialize::deserialize(deserializer).unwrap_or_default();
 
-        let raw_signing_key = match std::env::var("SIGNING_KEY") {
-          Err(VarError::NotPresent) => properties.get("signingKey").cloned(),
-          Err(VarError::NotUnicode(invalid_data)) => {
-              return Err(de::Error::custom(format!("Invalid SIGNING_KEY {:?}", invalid_data)))
-          },
-          Ok(value) => Some(value),
-        }.ok_or_else(|| {
-            D::Error::custom("Signing key should be provided either with SIGNING_KEY env variable or within configuration file")
-        })?;
+        Self::load(&properties).map_err(D::Error::custom)
+    }
+}
+
+impl SignerCredentials {
+    /// Loads the signing key from `SIGNING_KEY` env variable, a file at `SIGNING_KEY_FILE` env variable
+    /// or `signingKeyFile` property, or `signingKey` property, in order of precedence
+    pub fn load(properties: &HashMap<String, String>) -> Result<Self, AppError> {
+        let raw_signing_key = env_var("SIGNING_KEY")?;
+        let key_file =
+            env_var("SIGNING_KEY_FILE")?.or_else(|| properties.get("signingKeyFile").cloned());
+        let raw_signing_key = match key_file {
+            Some(_) => raw_signing_key,
+            None => raw_signing_key.or_else(|| properties.get("signingKey").cloned()),
+        };
+
+        Ok(Self {
+            signing_key: load_signing_key(raw_signing_key, key_file)?,
+        })
+    }
+}
+
+/// Reads an env variable, missing variable is `None`
+fn env_var(name: &str) -> Result<Option<String>, AppError> {
+    match std::env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(invalid_data)) => Err(AppError::KeyLoad(format!(
+            "Invalid {name} {invalid_data:?}"
+        ))),
+    }
+}
+
+/// Parses the raw signing key if provided, otherwise reads it from the key file
+fn load_signing_key(
+    raw_signing_key: Option<String>,
+    key_file: Option<String>,
+) -> Result<SecretKey, AppError> {
+    let raw_signing_key = match (raw_signing_key, key_file) {
+        (Some(raw_signing_key), _) => raw_signing_key,
+        (None, Some(path)) => std::fs::read_to_string(&path)
+            .map_err(|e| {
+                AppError::KeyLoad(format!(
+                    "Signing key file `{path}` reading failure. Error: {e}"
+                ))
+            })?
+            .trim()
+            .to_owned(),
+        (None, None) => {
+            return Err(AppError::KeyLoad(
+                "Signing key should be provided either with SIGNING_KEY env variable, a key file or within configuration file"
+                    .to_owned(),
+            ))
+        }
+    };
+
+    let signing_key = SecretKey::from_str(&raw_signing_key).map_err(|e| {
+        AppError::KeyLoad(format!("Signing key deserialization failure. Error: {e}"))
+    })?;
+
+    if !verify_signing_key(&signing_key) {
+        return Err(AppError::KeyLoad("Signing key is incorrect".to_owned()));
+    }
+
+    Ok(signing_key)
+}
+
+impl SignerConfig {
+    /// Returns a derived signing key of a tenant if provided, otherwise the master signing key
+    pub fn signing_key(&self, tenant: Option<&str>) -> Result<SecretKey, AppError> {
+        match tenant {
+            None => Ok(self.credentials.signing_key.clone()),
+            Some(tenant) if self.tenant_keys => {
+                derive_tenant_key(&self.credentials.signing_key, tenant)
+            }
+            Some(_) => Err(AppError::MalformedRequest(
+                "Tenant signing keys are disabled".to_owned(),
+            )),
+        }
+    }
+
+    /// Checks that typed data signing isn't combined with token fields its digest doesn't cover
+    pub fn check_typed_data(&self) -> Result<(), AppError> {
+        if self.typed_data
+            && (self.sign_hash
+                || self.leaf_format
+                || !self.extra_claims.is_empty()
+                || self.raw_level_max_length.is_some()
+                || !self.allowed_audiences.is_empty()
+                || self.not_before.is_some()
+                || self.policy_hash)
+        {
+            return Err(AppError::Generic(
+                "Typed data signing can't be combined with hash signing, leaf format, extra claims, raw level, audiences, not before time or policy hash"
+                    .to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the fixed-width leaf format isn't combined with fields appended to tokens
+    pub fn check_leaf_format(&self) -> Result<(), AppError> {
+        if self.leaf_format
+            && (!self.extra_claims.is_empty()
+                || self.raw_level_max_length.is_some()
+                || !self.allowed_audiences.is_empty()
+                || self.not_before.is_some()
+                || self.policy_hash)
+        {
+            return Err(AppError::Generic(
+                "Leaf format can't be combined with extra claims, raw level, audiences, not before time or policy hash"
+                    .to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that tokens signed from `now` on don't expire before they become valid
+    pub fn check_not_before(&self, now: DateTime<Utc>) -> Result<(), AppError> {
+        let not_before = match self.not_before {
+            Some(not_before) => not_before,
+            None => return Ok(()),
+        };
+        let min_lifetime = self.jwt_lifetime(false).min(self.jwt_lifetime(true));
+        let earliest_expiry = now.timestamp().saturating_add_unsigned(min_lifetime);
+
+        if not_before.timestamp() >= earliest_expiry {
+            return Err(AppError::Generic(
+                "Not before time must precede the expiry of tokens signed now".to_owned(),
+            ));
+        }
 
-        let signing_key = SecretKey::from_str(&raw_signing_key).map_err(|e| {
-            de::Error::custom(format!("Signing key deserialization failure. Error {e}"))
-        })?;
+        Ok(())
+    }
 
-        if !verify_signing_key(&signing_key) {
-            return Err(de::Error::custom("Signing key is incorrect"));
+    /// Lifetime of JWT attestations of the assurance tier, KYC or uniqueness only
+    pub fn jwt_lifetime(&self, verified_kyc: bool) -> u64 {
+        match self.kyc_jwt_lifetime {
+            Some(lifetime) if verified_kyc => lifetime,
+            _ => self.jwt_lifetime,
         }
+    }
 
-        Ok(Self { signing_key })
+    /// Checks that the signing key matches the expected public key if configured
+    pub fn check_expected_public_key(&self) -> Result<(), AppError> {
+        match &self.expected_public_key {
+            Some(expected) => check_public_key(&self.credentials.signing_key, expected),
+            None => Ok(()),
+        }
     }
 }
 
-fn verify_signing_key(signing_key: &SecretKey) -> bool {
+fn de_cosigning_keys<'de, D>(deserializer: D) -> Result<Vec<SecretKey>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|raw_key| {
+            let key = SecretKey::from_str(raw_key).map_err(|e| {
+                de::Error::custom(format!("Co-signing key deserialization failure. Error {e}"))
+            })?;
+
+            if key.key_type() != KeyType::ED25519 || !verify_signing_key(&key) {
+                return Err(de::Error::custom("Co-signing key is incorrect"));
+            }
+
+            Ok(key)
+        })
+        .collect()
+}
+
+fn default_jwt_lifetime() -> u64 {
+    3600
+}
+
+fn default_verify_own_signature() -> bool {
+    true
+}
+
+pub fn verify_signing_key(signing_key: &SecretKey) -> bool {
     let verification_data = "verify".as_bytes();
     let sig = signing_key.sign(verification_data);
     sig.verify(verification_data, &signing_key.public_key())
 }
+
+/// Checks that a public key of the signing key matches the expected one,
+/// given either as `ed25519:<base58>` or as base64 encoded raw key
+fn check_public_key(signing_key: &SecretKey, expected: &str) -> Result<(), AppError> {
+    let public_key = signing_key.public_key();
+
+    if expected == public_key.to_string()
+        || expected == general_purpose::STANDARD.encode(public_key.key_data())
+    {
+        Ok(())
+    } else {
+        Err(AppError::KeyLoad(format!(
+            "Signing key public key `{public_key}` doesn't match expected public key `{expected}`"
+        )))
+    }
+}
+
+/// Global limiter of signatures issued per second to bound signing key usage
+#[derive(Debug, Clone)]
+pub struct SigningRateLimiter {
+    max_per_second: Option<u32>,
+    window: Arc<Mutex<SigningWindow>>,
+}
+
+#[derive(Debug)]
+struct SigningWindow {
+    started_at: Instant,
+    count: u32,
+}
+
+impl SigningRateLimiter {
+    pub fn new(max_per_second: Option<u32>) -> Self {
+        Self {
+            max_per_second,
+            window: Arc::new(Mutex::new(SigningWindow {
+                started_at: Instant::now(),
+                count: 0,
+            })),
+        }
+    }
+
+    /// Acquires a permit to sign a single message. Returns `false` if the limit is exceeded
+    pub fn try_acquire(&self) -> bool {
+        let max_per_second = match self.max_per_second {
+            Some(max_per_second) => max_per_second,
+            None => return true,
+        };
+
+        let mut window = self
+            .window
+            .lock()
+            .expect("Signing rate limiter lock is poisoned");
+        let now = Instant::now();
+
+        if now.duration_since(window.started_at) >= Duration::from_secs(1) {
+            *window = SigningWindow {
+                started_at: now,
+                count: 0,
+            };
+        }
+
+        if window.count >= max_per_second {
+            return false;
+        }
+
+        window.count += 1;
+        true
+    }
+}
+
+/// Derives a deterministic ed25519 signing key of a tenant from the master ed25519 key with HKDF-SHA256
+pub fn derive_tenant_key(master_key: &SecretKey, tenant: &str) -> Result<SecretKey, AppError> {
+    let master_secret = match master_key {
+        SecretKey::ED25519(key) => &key.0[..ed25519_dalek::SECRET_KEY_LENGTH],
+        _ => return Err(AppError::SigningError),
+    };
+
+    let mut okm = [0u8; ed25519_dalek::SECRET_KEY_LENGTH];
+    Hkdf::<Sha256>::new(Some(TENANT_KEY_SALT), master_secret)
+        .expand(tenant.as_bytes(), &mut okm)
+        .map_err(|_| AppError::SigningError)?;
+
+    let secret = ed25519_dalek::SecretKey::from_bytes(&okm).map_err(|_| AppError::SigningError)?;
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    let keypair = ed25519_dalek::Keypair { secret, public };
+
+    Ok(SecretKey::ED25519(ED25519SecretKey(keypair.to_bytes())))
+}
+
+/// Decodes raw signature bytes of a given key type
+///
+/// Signature length is checked beforehand, so malformed input is reported with a clear error
+pub fn decode_signature(key_type: KeyType, raw: &[u8]) -> Result<Signature, AppError> {
+    let expected_len = match key_type {
+        KeyType::ED25519 => ED25519_SIGNATURE_LENGTH,
+        KeyType::SECP256K1 => SECP256K1_SIGNATURE_LENGTH,
+    };
+
+    if raw.len() != expected_len {
+        return Err(AppError::MalformedRequest(format!(
+            "Invalid {key_type} signature length {}, expected {expected_len}",
+            raw.len()
+        )));
+    }
+
+    Signature::from_parts(key_type, raw).map_err(|e| {
+        AppError::MalformedRequest(format!("Invalid {key_type} signature. Error: {e}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_public_key, decode_signature, derive_tenant_key, load_signing_key,
+        verify_signing_key, SigningRateLimiter, ED25519_SIGNATURE_LENGTH,
+        SECP256K1_SIGNATURE_LENGTH,
+    };
+    use crate::error::AppError;
+    use assert_matches::assert_matches;
+    use base64::{engine::general_purpose, Engine};
+    use near_crypto::{KeyType, SecretKey, Signature};
+    use std::io::Write;
+    use std::time::Duration;
+
+    #[test]
+    fn test_signing_rate_limiter_sheds_burst() {
+        let limiter = SigningRateLimiter::new(Some(3));
+
+        let permits = (0..10).filter(|_| limiter.try_acquire()).count();
+        assert_eq!(permits, 3);
+
+        // Limit is shared between clones
+        assert!(!limiter.clone().try_acquire());
+
+        // Permits are available again in the next window
+        std::thread::sleep(Duration::from_millis(1100));
+        assert!(limiter.try_acquire());
+    }
+
+    #[test]
+    fn test_signing_rate_limiter_unlimited() {
+        let limiter = SigningRateLimiter::new(None);
+        assert!((0..1000).all(|_| limiter.try_acquire()));
+    }
+
+    #[test]
+    fn test_decode_ed25519_signature() {
+        let signing_key = SecretKey::from_random(KeyType::ED25519);
+        let data = "test".as_bytes();
+        let raw = match signing_key.sign(data) {
+            Signature::ED25519(signature) => signature.to_bytes(),
+            _ => panic!("Not an ed25519 signature"),
+        };
+
+        let signature = decode_signature(KeyType::ED25519, &raw).unwrap();
+        assert!(signature.verify(data, &signing_key.public_key()));
+
+        assert_matches!(
+            decode_signature(KeyType::ED25519, &raw[..ED25519_SIGNATURE_LENGTH - 1]),
+            Err(AppError::MalformedRequest(_))
+        );
+        assert_matches!(
+            decode_signature(KeyType::ED25519, &[0u8; SECP256K1_SIGNATURE_LENGTH]),
+            Err(AppError::MalformedRequest(_))
+        );
+        assert_matches!(
+            decode_signature(KeyType::ED25519, &[]),
+            Err(AppError::MalformedRequest(_))
+        );
+    }
+
+    #[test]
+    fn test_decode_secp256k1_signature() {
+        let signing_key = SecretKey::from_random(KeyType::SECP256K1);
+        let data = "test".as_bytes();
+        let raw: [u8; SECP256K1_SIGNATURE_LENGTH] = match signing_key.sign(data) {
+            Signature::SECP256K1(signature) => signature.into(),
+            _ => panic!("Not a secp256k1 signature"),
+        };
+
+        let signature = decode_signature(KeyType::SECP256K1, &raw).unwrap();
+        assert!(signature.verify(data, &signing_key.public_key()));
+
+        assert_matches!(
+            decode_signature(KeyType::SECP256K1, &raw[..ED25519_SIGNATURE_LENGTH]),
+            Err(AppError::MalformedRequest(_))
+        );
+    }
+
+    #[test]
+    fn test_check_public_key() {
+        let signing_key = SecretKey::from_random(KeyType::ED25519);
+        let public_key = signing_key.public_key();
+
+        assert_matches!(
+            check_public_key(&signing_key, &public_key.to_string()),
+            Ok(())
+        );
+        assert_matches!(
+            check_public_key(
+                &signing_key,
+                &general_purpose::STANDARD.encode(public_key.key_data())
+            ),
+            Ok(())
+        );
+
+        let other_public_key = SecretKey::from_random(KeyType::ED25519).public_key();
+        assert_matches!(
+            check_public_key(&signing_key, &other_public_key.to_string()),
+            Err(AppError::KeyLoad(_))
+        );
+        assert_matches!(
+            check_public_key(
+                &signing_key,
+                &general_purpose::STANDARD.encode(other_public_key.key_data())
+            ),
+            Err(AppError::KeyLoad(_))
+        );
+    }
+
+    #[test]
+    fn test_load_signing_key() {
+        let signing_key = SecretKey::from_random(KeyType::ED25519);
+
+        assert_matches!(
+            load_signing_key(Some(signing_key.to_string()), None),
+            Ok(key) if key == signing_key
+        );
+
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "{signing_key}").unwrap();
+        let key_path = key_file.path().to_str().unwrap().to_owned();
+        assert_matches!(
+            load_signing_key(None, Some(key_path.clone())),
+            Ok(key) if key == signing_key
+        );
+
+        // Raw key takes precedence over the key file
+        let other_signing_key = SecretKey::from_random(KeyType::ED25519);
+        assert_matches!(
+            load_signing_key(Some(other_signing_key.to_string()), Some(key_path)),
+            Ok(key) if key == other_signing_key
+        );
+    }
+
+    #[test]
+    fn test_load_signing_key_failures() {
+        assert_matches!(load_signing_key(None, None), Err(AppError::KeyLoad(_)));
+
+        let missing_path = std::env::temp_dir()
+            .join("missing-signing-key")
+            .to_str()
+            .unwrap()
+            .to_owned();
+        assert_matches!(
+            load_signing_key(None, Some(missing_path)),
+            Err(AppError::KeyLoad(message)) if message.contains("missing-signing-key")
+        );
+
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "not a key").unwrap();
+        assert_matches!(
+            load_signing_key(None, Some(key_file.path().to_str().unwrap().to_owned())),
+            Err(AppError::KeyLoad(_))
+        );
+        assert_matches!(
+            load_signing_key(Some("ed25519:bad".to_owned()), None),
+            Err(AppError::KeyLoad(_))
+        );
+    }
+
+    #[test]
+    fn test_derive_tenant_key() {
+        let master_key = SecretKey::from_random(KeyType::ED25519);
+
+        let tenant_key = derive_tenant_key(&master_key, "tenant_a").unwrap();
+        assert!(verify_signing_key(&tenant_key));
+        assert_eq!(
+            derive_tenant_key(&master_key, "tenant_a").unwrap(),
+            tenant_key
+        );
+
+        let other_tenant_key = derive_tenant_key(&master_key, "tenant_b").unwrap();
+        assert_ne!(other_tenant_key, tenant_key);
+        assert_ne!(tenant_key, master_key);
+
+        let other_master_key = SecretKey::from_random(KeyType::ED25519);
+        assert_ne!(
+            derive_tenant_key(&other_master_key, "tenant_a").unwrap(),
+            tenant_key
+        );
+
+        assert_matches!(
+            derive_tenant_key(&SecretKey::from_random(KeyType::SECP256K1), "tenant_a"),
+            Err(AppError::SigningError)
+        );
+
+        // Derived keys are pinned, so already registered tenant keys stay valid
+        let zero_master_key = SecretKey::ED25519(near_crypto::ED25519SecretKey([0; 64]));
+        match derive_tenant_key(&zero_master_key, "tenant_a").unwrap() {
+            SecretKey::ED25519(key) => assert_eq!(
+                key.0[..32],
+                [
+                    0x69, 0x59, 0xa2, 0x74, 0xc8, 0x51, 0x13, 0xa1, 0x76, 0xea, 0x4b, 0xc3, 0x01,
+                    0x7a, 0xba, 0x2d, 0x52, 0x43, 0x62, 0x53, 0x56, 0xf5, 0x7f, 0x5a, 0x6f, 0x7c,
+                    0x61, 0x06, 0x08, 0x13, 0x64, 0xc4,
+                ]
+            ),
+            _ => panic!("Not an ed25519 key"),
+        }
+    }
+}
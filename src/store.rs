@@ -0,0 +1,519 @@
+use crate::{error::AppError, ExternalAccountId};
+use fs2::FileExt;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::hash::Hash;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// In-memory key-value store with entries expiring after configured time
+#[derive(Debug, Clone)]
+pub struct ExpiringStore<K, V> {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<K, Entry<V>>>>,
+}
+
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    inserted_at: Instant,
+}
+
+impl<K, V> ExpiringStore<K, V>
+where
+    K: Eq + Hash,
+    V: Clone,
+{
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Default::default(),
+        }
+    }
+
+    /// Returns a value if it's present and not expired yet
+    pub fn get(&self, key: &K) -> Option<V> {
+        let entries = self.entries.lock().expect("Store lock is poisoned");
+
+        entries
+            .get(key)
+            .filter(|entry| entry.inserted_at.elapsed() < self.ttl)
+            .map(|entry| entry.value.clone())
+    }
+
+    /// Inserts a value unless a non-expired one is present. Returns `false` if it's present
+    pub fn try_insert(&self, key: K, value: V) -> bool {
+        let mut entries = self.entries.lock().expect("Store lock is poisoned");
+
+        entries.retain(|_, entry| entry.inserted_at.elapsed() < self.ttl);
+        if entries.contains_key(&key) {
+            return false;
+        }
+
+        entries.insert(
+            key,
+            Entry {
+                value,
+                inserted_at: Instant::now(),
+            },
+        );
+        true
+    }
+
+    /// Inserts a value and purges expired entries
+    pub fn insert(&self, key: K, value: V) {
+        let mut entries = self.entries.lock().expect("Store lock is poisoned");
+
+        entries.retain(|_, entry| entry.inserted_at.elapsed() < self.ttl);
+        entries.insert(
+            key,
+            Entry {
+                value,
+                inserted_at: Instant::now(),
+            },
+        );
+    }
+}
+
+/// Fixed window limiter of events per key, e.g. tokens issued per external account
+#[derive(Debug, Clone)]
+pub struct KeyedRateLimiter<K> {
+    max_per_window: u32,
+    window: Duration,
+    counters: Arc<Mutex<HashMap<K, Entry<u32>>>>,
+}
+
+impl<K> KeyedRateLimiter<K>
+where
+    K: Eq + Hash,
+{
+    pub fn new(max_per_window: u32, window: Duration) -> Self {
+        Self {
+            max_per_window,
+            window,
+            counters: Default::default(),
+        }
+    }
+
+    /// Counts an event of a key. Returns `false` if the key reached the limit within its current window
+    pub fn try_acquire(&self, key: K) -> bool {
+        let mut counters = self.counters.lock().expect("Limiter lock is poisoned");
+
+        counters.retain(|_, entry| entry.inserted_at.elapsed() < self.window);
+        let entry = counters.entry(key).or_insert_with(|| Entry {
+            value: 0,
+            inserted_at: Instant::now(),
+        });
+        if entry.value >= self.max_per_window {
+            return false;
+        }
+
+        entry.value += 1;
+        true
+    }
+
+    /// Returns an event of a key acquired within its current window, e.g. of a request refused afterwards
+    pub fn release(&self, key: &K) {
+        let mut counters = self.counters.lock().expect("Limiter lock is poisoned");
+
+        if let Some(entry) = counters.get_mut(key) {
+            entry.value = entry.value.saturating_sub(1);
+        }
+    }
+}
+
+/// Fixed window counter of requests and their failures, reset when the window elapses
+#[derive(Debug, Clone)]
+pub struct ErrorRateWindow {
+    window: Duration,
+    /// Numbers of requests and failed ones within the current window
+    counts: Arc<Mutex<Entry<(u32, u32)>>>,
+}
+
+impl ErrorRateWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            counts: Arc::new(Mutex::new(Entry {
+                value: (0, 0),
+                inserted_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// Counts a request within the current window
+    pub fn record(&self, failed: bool) {
+        let mut counts = self.current_window();
+
+        counts.value.0 += 1;
+        if failed {
+            counts.value.1 += 1;
+        }
+    }
+
+    /// Returns the number of requests within the current window and the rate of failed ones
+    pub fn error_rate(&self) -> (u32, f64) {
+        let (requests, failures) = self.current_window().value;
+
+        match requests {
+            0 => (0, 0.0),
+            _ => (requests, failures as f64 / requests as f64),
+        }
+    }
+
+    fn current_window(&self) -> std::sync::MutexGuard<'_, Entry<(u32, u32)>> {
+        let mut counts = self.counts.lock().expect("Error rate lock is poisoned");
+
+        if counts.inserted_at.elapsed() >= self.window {
+            *counts = Entry {
+                value: (0, 0),
+                inserted_at: Instant::now(),
+            };
+        }
+        counts
+    }
+}
+
+/// Permanent set of external accounts ever issued a token.
+/// Methods are called from async request handlers, so implementations doing blocking IO should wrap it with `run_blocking`
+pub trait ClaimedAccounts: Send + Sync {
+    fn is_claimed(&self, ext_account: &ExternalAccountId) -> bool;
+
+    /// Marks an external account as claimed. Returns `false` if it's already claimed
+    fn try_claim(&self, ext_account: &ExternalAccountId) -> Result<bool, AppError>;
+}
+
+/// Claimed accounts kept in memory, lost on restart
+#[derive(Debug, Default)]
+pub struct MemoryClaimedAccounts {
+    claimed: Mutex<HashSet<String>>,
+}
+
+impl ClaimedAccounts for MemoryClaimedAccounts {
+    fn is_claimed(&self, ext_account: &ExternalAccountId) -> bool {
+        let claimed = self.claimed.lock().expect("Store lock is poisoned");
+        claimed.contains(ext_account.as_ref())
+    }
+
+    fn try_claim(&self, ext_account: &ExternalAccountId) -> Result<bool, AppError> {
+        let mut claimed = self.claimed.lock().expect("Store lock is poisoned");
+        Ok(claimed.insert(ext_account.to_string()))
+    }
+}
+
+/// Claimed accounts persisted to a file, one external account id per line.
+/// The file is locked while claiming, so instances sharing it across processes claim atomically
+#[derive(Debug)]
+pub struct FileClaimedAccounts {
+    path: String,
+    claims: Mutex<FileClaims>,
+}
+
+#[derive(Debug)]
+struct FileClaims {
+    claimed: HashSet<String>,
+    file: File,
+    /// Length of the file prefix already loaded into `claimed`
+    loaded_len: u64,
+}
+
+impl FileClaims {
+    /// Loads claims appended since the last load, possibly by other processes.
+    /// Must be called while holding the file lock
+    fn load_appended(&mut self) -> std::io::Result<()> {
+        let mut appended = String::new();
+        self.file.seek(SeekFrom::Start(self.loaded_len))?;
+        self.file.read_to_string(&mut appended)?;
+
+        // A trailing line without a newline is left to the next load
+        let complete_len = appended.rfind('\n').map_or(0, |idx| idx + 1);
+        self.claimed.extend(
+            appended[..complete_len]
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(ToOwned::to_owned),
+        );
+        self.loaded_len += complete_len as u64;
+
+        Ok(())
+    }
+}
+
+impl FileClaimedAccounts {
+    /// Opens or creates a file and loads previously claimed accounts
+    pub fn open(path: &str) -> Result<Self, AppError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| AppError::Generic(format!("Failed to open `{path}`. Error: {e}")))?;
+
+        let mut claims = FileClaims {
+            claimed: HashSet::new(),
+            file,
+            loaded_len: 0,
+        };
+        claims
+            .file
+            .lock_shared()
+            .and_then(|_| {
+                let res = claims.load_appended();
+                claims.file.unlock().and(res)
+            })
+            .map_err(|e| AppError::Generic(format!("Failed to read `{path}`. Error: {e}")))?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            claims: Mutex::new(claims),
+        })
+    }
+}
+
+/// Runs blocking file IO, letting a multi-threaded runtime move other tasks off the current worker
+/// meanwhile, so waiting for a file lock held by another process doesn't stall the runtime
+pub fn run_blocking<T>(f: impl FnOnce() -> T) -> T {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+impl ClaimedAccounts for FileClaimedAccounts {
+    fn is_claimed(&self, ext_account: &ExternalAccountId) -> bool {
+        run_blocking(|| self.is_claimed_blocking(ext_account))
+    }
+
+    fn try_claim(&self, ext_account: &ExternalAccountId) -> Result<bool, AppError> {
+        run_blocking(|| self.try_claim_blocking(ext_account))
+    }
+}
+
+impl FileClaimedAccounts {
+    fn is_claimed_blocking(&self, ext_account: &ExternalAccountId) -> bool {
+        let mut claims = self.claims.lock().expect("Store lock is poisoned");
+
+        // Claims of other processes are picked up on a best effort basis, `try_claim` is authoritative
+        let reloaded = claims.file.lock_shared().and_then(|_| {
+            let res = claims.load_appended();
+            claims.file.unlock().and(res)
+        });
+        if let Err(e) = reloaded {
+            tracing::warn!("Failed to reload claims from `{}`. Error: {e}", self.path);
+        }
+
+        claims.claimed.contains(ext_account.as_ref())
+    }
+
+    fn try_claim_blocking(&self, ext_account: &ExternalAccountId) -> Result<bool, AppError> {
+        let mut claims = self.claims.lock().expect("Store lock is poisoned");
+        let claims = &mut *claims;
+
+        // Check and append happen under an exclusive file lock, so concurrent processes can't
+        // both claim the same account
+        claims.file.lock_exclusive().map_err(|e| {
+            AppError::Generic(format!("Failed to lock `{}`. Error: {e}", self.path))
+        })?;
+        let res = claims.load_appended().and_then(|_| {
+            if claims.claimed.contains(ext_account.as_ref()) {
+                return Ok(false);
+            }
+
+            // Persisted before being reported as claimed, so a crash can't lead to a repeated claim
+            writeln!(claims.file, "{ext_account}").and_then(|_| claims.file.sync_data())?;
+            claims.claimed.insert(ext_account.to_string());
+            Ok(true)
+        });
+        let unlocked = claims.file.unlock();
+
+        let claimed =
+            res.map_err(|e| AppError::Generic(format!("Failed to persist claim. Error: {e}")))?;
+        unlocked.map_err(|e| {
+            AppError::Generic(format!("Failed to unlock `{}`. Error: {e}", self.path))
+        })?;
+
+        Ok(claimed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ClaimedAccounts, ErrorRateWindow, ExpiringStore, FileClaimedAccounts, KeyedRateLimiter,
+        MemoryClaimedAccounts,
+    };
+    use crate::ExternalAccountId;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_keyed_rate_limiter() {
+        let limiter = KeyedRateLimiter::new(2, Duration::from_millis(100));
+
+        assert!(limiter.try_acquire("a"));
+        assert!(limiter.try_acquire("a"));
+        assert!(!limiter.try_acquire("a"));
+        assert!(limiter.clone().try_acquire("b"));
+
+        limiter.release(&"a");
+        assert!(limiter.try_acquire("a"));
+        assert!(!limiter.try_acquire("a"));
+        limiter.release(&"c");
+
+        // Counter is reset after the window
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(limiter.try_acquire("a"));
+    }
+
+    #[test]
+    fn test_error_rate_window() {
+        let error_rate = ErrorRateWindow::new(Duration::from_millis(100));
+        assert_eq!(error_rate.error_rate(), (0, 0.0));
+
+        error_rate.record(false);
+        error_rate.record(true);
+        error_rate.clone().record(true);
+        error_rate.record(false);
+        assert_eq!(error_rate.error_rate(), (4, 0.5));
+
+        // Counts are reset after the window
+        std::thread::sleep(Duration::from_millis(150));
+        assert_eq!(error_rate.error_rate(), (0, 0.0));
+        error_rate.record(true);
+        assert_eq!(error_rate.error_rate(), (1, 1.0));
+    }
+
+    #[test]
+    fn test_expiring_store() {
+        let store = ExpiringStore::new(Duration::from_millis(100));
+
+        store.insert("key", 1);
+        assert_eq!(store.get(&"key"), Some(1));
+        assert_eq!(store.clone().get(&"key"), Some(1));
+        assert_eq!(store.get(&"other"), None);
+
+        std::thread::sleep(Duration::from_millis(150));
+        assert_eq!(store.get(&"key"), None);
+    }
+
+    #[test]
+    fn test_expiring_store_try_insert() {
+        let store = ExpiringStore::new(Duration::from_millis(100));
+
+        assert!(store.try_insert("key", 1));
+        assert!(!store.try_insert("key", 2));
+        assert_eq!(store.get(&"key"), Some(1));
+
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(store.try_insert("key", 2));
+        assert_eq!(store.get(&"key"), Some(2));
+    }
+
+    #[test]
+    fn test_memory_claimed_accounts() {
+        let store = MemoryClaimedAccounts::default();
+        let ext_account: ExternalAccountId = Uuid::new_v4().into();
+
+        assert!(!store.is_claimed(&ext_account));
+        assert!(store.try_claim(&ext_account).unwrap());
+        assert!(store.is_claimed(&ext_account));
+        assert!(!store.try_claim(&ext_account).unwrap());
+        assert!(store.try_claim(&Uuid::new_v4().into()).unwrap());
+    }
+
+    #[test]
+    fn test_file_claimed_accounts() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+        let ext_account: ExternalAccountId = Uuid::new_v4().into();
+
+        let store = FileClaimedAccounts::open(path).unwrap();
+        assert!(!store.is_claimed(&ext_account));
+        assert!(store.try_claim(&ext_account).unwrap());
+        assert!(!store.try_claim(&ext_account).unwrap());
+
+        // Claims survive reopening
+        let store = FileClaimedAccounts::open(path).unwrap();
+        assert!(store.is_claimed(&ext_account));
+        assert!(!store.try_claim(&ext_account).unwrap());
+        assert!(!store.is_claimed(&Uuid::new_v4().into()));
+    }
+
+    #[test]
+    fn test_file_claimed_accounts_shared() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+        let ext_account: ExternalAccountId = Uuid::new_v4().into();
+
+        // Instances opened on the same file, as by separate processes
+        let store_a = FileClaimedAccounts::open(path).unwrap();
+        let store_b = FileClaimedAccounts::open(path).unwrap();
+
+        assert!(store_a.try_claim(&ext_account).unwrap());
+        assert!(store_b.is_claimed(&ext_account));
+        assert!(!store_b.try_claim(&ext_account).unwrap());
+
+        let other_ext_account: ExternalAccountId = Uuid::new_v4().into();
+        assert!(store_b.try_claim(&other_ext_account).unwrap());
+        assert!(!store_a.try_claim(&other_ext_account).unwrap());
+    }
+
+    #[test]
+    fn test_file_claimed_accounts_concurrent() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap().to_owned();
+        let ext_account: ExternalAccountId = Uuid::new_v4().into();
+
+        let handles = (0..8)
+            .map(|_| {
+                let store = FileClaimedAccounts::open(&path).unwrap();
+                let ext_account = ext_account.clone();
+                std::thread::spawn(move || store.try_claim(&ext_account).unwrap())
+            })
+            .collect::<Vec<_>>();
+        let claimed = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|claimed| *claimed)
+            .count();
+
+        assert_eq!(claimed, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_file_claimed_accounts_lock_wait() {
+        use fs2::FileExt;
+        use std::sync::Arc;
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+        let store = Arc::new(FileClaimedAccounts::open(path).unwrap());
+        let ext_account: ExternalAccountId = Uuid::new_v4().into();
+
+        // File is locked by another process
+        let other_file = std::fs::File::open(path).unwrap();
+        other_file.lock_exclusive().unwrap();
+
+        let claim = tokio::spawn({
+            let store = store.clone();
+            let ext_account = ext_account.clone();
+            async move { store.try_claim(&ext_account).unwrap() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // The only worker keeps serving other tasks while the claim waits for the lock
+        tokio::time::timeout(Duration::from_secs(1), tokio::spawn(async {}))
+            .await
+            .expect("Runtime is stalled by the file lock")
+            .unwrap();
+        assert!(!claim.is_finished());
+
+        other_file.unlock().unwrap();
+        assert!(claim.await.unwrap());
+        assert!(store.is_claimed(&ext_account));
+    }
+}
@@ -1,5 +1,6 @@
-use crate::ExternalAccountId;
+use crate::{AppError, ExternalAccountId};
 use backtrace::Backtrace;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use near_sdk::{
     serde::{de, Deserialize},
     serde_json::Value,
@@ -9,6 +10,55 @@ use std::{panic, thread};
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{fmt, EnvFilter, Registry};
 use uuid::Uuid;
+use zeroize::Zeroize;
+
+/// Secret value which is never printed out and zeroed on drop
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(crate = "near_sdk::serde", transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Loads secret from a file path or an URI with `file://` or `env://` scheme
+    pub fn load(uri: &str) -> Result<Self, AppError> {
+        let mut value = match uri.strip_prefix("env://") {
+            Some(var) => std::env::var(var).map_err(|e| {
+                format!("Unable to read secret from env variable `{var}`. Error: {e}")
+            })?,
+            None => {
+                let path = uri.strip_prefix("file://").unwrap_or(uri);
+                std::fs::read_to_string(path)
+                    .map_err(|e| format!("Unable to read secret from file `{path}`. Error: {e}"))?
+            }
+        };
+
+        let secret = Self(value.trim().to_owned());
+        value.zeroize();
+
+        Ok(secret)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_owned())
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
 
 pub fn set_heavy_panic() {
     panic::set_hook(Box::new(|panic_info| {
@@ -90,6 +140,55 @@ where
     Ok(uuid.into())
 }
 
+/// Datetime formats accepted in addition to RFC3339
+static DATETIME_FORMATS: [&str; 2] = ["%Y-%m-%d %H:%M:%S UTC", "%Y-%m-%d %H:%M:%S%.f UTC"];
+
+/// Deserializes UTC datetime trying multiple formats, `None` if none of them matches
+pub fn de_datetime_lenient<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let raw = String::deserialize(deserializer)?;
+
+    let datetime = parse_datetime(&raw);
+    if datetime.is_none() {
+        tracing::warn!("Unable to parse datetime `{raw}`");
+    }
+    Ok(datetime)
+}
+
+pub fn parse_datetime(raw: &str) -> Option<DateTime<Utc>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(raw) {
+        return Some(datetime.with_timezone(&Utc));
+    }
+
+    DATETIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(raw, format).ok())
+        .map(|datetime| Utc.from_utc_datetime(&datetime))
+}
+
+/// Parses account id following NEAR validation rules, optionally trimmed and lowercased beforehand
+pub fn parse_account_id(raw: &str, normalize: bool) -> Result<near_sdk::AccountId, AppError> {
+    let raw = if normalize {
+        raw.trim().to_lowercase()
+    } else {
+        raw.to_owned()
+    };
+
+    near_sdk::AccountId::from_str(&raw)
+        .map_err(|e| AppError::MalformedRequest(format!("Invalid account id `{raw}`. Error: {e}")))
+}
+
+/// Returns the machine hostname from `HOSTNAME` env variable or `/etc/hostname`
+pub fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|hostname| hostname.trim().to_owned())
+        .filter(|hostname| !hostname.is_empty())
+}
+
 /// Checks if the provided named near account is an allowed sub-account
 ///
 /// Requires to be an implicit account id or named sub-account from .near root
@@ -104,10 +203,142 @@ pub fn is_allowed_named_sub_account(account_id: &near_sdk::AccountId) -> bool {
     number_of_dots <= 1
 }
 
+/// Checks if the account id is an implicit one, i.e. 64 lowercase hex chars of an ed25519 public key
+pub fn is_implicit_account(account_id: &near_sdk::AccountId) -> bool {
+    let account_id = account_id.as_str();
+
+    account_id.len() == 64
+        && account_id
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use axum::Router;
+
+    /// Spawns a mock http server for a provided router and returns its base url
+    pub fn spawn_mock_server(router: Router) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+
+        format!("http://{addr}")
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::is_allowed_named_sub_account;
+    use super::{
+        is_allowed_named_sub_account, is_implicit_account, parse_account_id, parse_datetime, Secret,
+    };
+    use crate::AppError;
+    use assert_matches::assert_matches;
+    use chrono::{DateTime, Duration, TimeZone, Utc};
     use near_sdk::AccountId;
+    use std::io::Write;
+
+    #[test]
+    fn test_secret_load() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "some_secret").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        assert_eq!(Secret::load(path).unwrap().expose(), "some_secret");
+        assert_eq!(
+            Secret::load(&format!("file://{path}")).unwrap().expose(),
+            "some_secret"
+        );
+
+        std::env::set_var("TEST_SECRET_LOAD", "env_secret");
+        assert_eq!(
+            Secret::load("env://TEST_SECRET_LOAD").unwrap().expose(),
+            "env_secret"
+        );
+
+        assert_matches!(
+            Secret::load("file:///non/existing/secret"),
+            Err(AppError::Generic(_))
+        );
+        assert_matches!(
+            Secret::load("env://TEST_SECRET_LOAD_MISSING"),
+            Err(AppError::Generic(_))
+        );
+    }
+
+    #[test]
+    fn test_de_datetime_lenient() {
+        #[derive(near_sdk::serde::Deserialize)]
+        #[serde(crate = "near_sdk::serde")]
+        struct Case {
+            #[serde(deserialize_with = "super::de_datetime_lenient")]
+            updated_at: Option<DateTime<Utc>>,
+        }
+
+        let expected = Utc.with_ymd_and_hms(2023, 5, 24, 19, 59, 19).unwrap();
+        let expected_ms = expected + Duration::milliseconds(123);
+
+        for (input, expected) in [
+            ("2023-05-24 19:59:19 UTC", Some(expected)),
+            ("2023-05-24 19:59:19.123 UTC", Some(expected_ms)),
+            ("2023-05-24T19:59:19Z", Some(expected)),
+            ("2023-05-24T19:59:19.123Z", Some(expected_ms)),
+            ("2023-05-24T21:59:19+02:00", Some(expected)),
+            ("24/05/2023 19:59", None),
+        ] {
+            let case = near_sdk::serde_json::from_str::<Case>(&format!(
+                r#"{{ "updated_at": "{input}" }}"#
+            ))
+            .unwrap();
+            assert_eq!(case.updated_at, expected, "Failed to parse `{input}`");
+        }
+
+        assert_eq!(parse_datetime("not a date"), None);
+    }
+
+    #[test]
+    fn test_secret_debug() {
+        let secret = Secret::from("some_secret");
+        assert!(!format!("{secret:?}").contains("some_secret"));
+    }
+
+    #[test]
+    fn test_parse_account_id() {
+        let expected = AccountId::new_unchecked("test.near".to_owned());
+
+        assert_eq!(parse_account_id("test.near", true).unwrap(), expected);
+        assert_eq!(parse_account_id("  test.near\n", true).unwrap(), expected);
+        assert_eq!(parse_account_id("Test.NEAR", true).unwrap(), expected);
+
+        assert_matches!(
+            parse_account_id("  test.near", false),
+            Err(AppError::MalformedRequest(_))
+        );
+        assert_matches!(
+            parse_account_id("Test.NEAR", false),
+            Err(AppError::MalformedRequest(_))
+        );
+
+        for invalid in [
+            "",
+            "a",
+            "test..near",
+            "test near",
+            "test@near!",
+            ".test.near",
+        ] {
+            assert_matches!(
+                parse_account_id(invalid, true),
+                Err(AppError::MalformedRequest(_)),
+                "Account id `{invalid}` should be invalid"
+            );
+        }
+    }
 
     #[test]
     fn test_is_allowed_named_sub_account() {
@@ -121,4 +352,17 @@ mod tests {
             "test1.test.near".to_owned()
         )));
     }
+
+    #[test]
+    fn test_is_implicit_account() {
+        assert!(is_implicit_account(&AccountId::new_unchecked(
+            "28cda90838b6fa11b629747cf8173edc2d5bc010d1300d544f39cc19d4d69edb".to_owned()
+        )));
+        assert!(!is_implicit_account(&AccountId::new_unchecked(
+            "test.near".to_owned()
+        )));
+        assert!(!is_implicit_account(&AccountId::new_unchecked(
+            "28cda90838b6fa11b629747cf8173edc2d5bc010d1300d544f39cc19d4d69ed".to_owned()
+        )));
+    }
 }
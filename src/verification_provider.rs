@@ -1,4 +1,7 @@
-use crate::{utils, AppError, ExternalAccountId};
+use crate::{
+    utils::{self, Secret},
+    AppError, ExternalAccountId,
+};
 use base64::{engine::general_purpose, Engine};
 use chrono::{DateTime, Duration, TimeZone, Utc};
 use near_sdk::{
@@ -10,10 +13,31 @@ use near_sdk::{
     },
     serde_json,
 };
-use reqwest::Client;
+use reqwest::{redirect, Client};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+/// Prefix of liveness audit file fields of verification case details
+const LIVENESS_AUDIT_PREFIX: &str = "liveness_audit_";
+
+/// Default maximum size in bytes of a fetched user response body
+const DEFAULT_MAX_USER_RESPONSE_SIZE: usize = 1024 * 1024;
+
+/// Maximum number of fetched user response pages aggregated
+const MAX_USER_PAGES: usize = 10;
 
-/// Minimum time required before oauth2 token expires in minutes
-static OAUTH_TOKEN_MINIMUM_LIFETIME: i64 = 5;
+/// Default time in seconds before oauth2 token expiry it gets refreshed at
+const DEFAULT_TOKEN_REFRESH_SKEW: u64 = 300;
+
+/// Time to cache verification provider health check result for
+static PROVIDER_HEALTH_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Timeout of a verification provider health check request
+static PROVIDER_HEALTH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Maximum number of redirects followed within configured provider hosts
+const MAX_PROVIDER_REDIRECTS: usize = 10;
 
 #[derive(Deserialize, Debug, Default, Clone)]
 #[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
@@ -21,7 +45,102 @@ pub struct VerificationProviderConfig {
     pub request_token_url: String,
     pub request_user_url: String,
     pub client_id: String,
-    pub client_secret: String,
+    #[serde(default)]
+    pub client_secret: Option<Secret>,
+    /// Path or URI with `file://` or `env://` scheme to read client secret from
+    #[serde(default)]
+    pub client_secret_path: Option<String>,
+    /// Whether to accept verification cases with unparsable dates (treated as unknown)
+    #[serde(default)]
+    pub allow_invalid_case_dates: bool,
+    #[serde(default)]
+    pub required_scopes: RequiredScopes,
+    /// Whether to reject client provided OAuth tokens with empty refresh token, since they can't be refreshed
+    #[serde(default)]
+    pub reject_empty_refresh_token: bool,
+    /// Time in seconds before expiry a client provided token is refreshed at, 300 if not set
+    #[serde(default)]
+    pub token_refresh_skew: Option<u64>,
+    /// Whether to require all liveness audit files to be present for a case to count as live
+    #[serde(default)]
+    pub require_liveness_audit: bool,
+    /// Period in seconds since the approving case update within which KYC stays valid, unlimited if not set
+    #[serde(default)]
+    pub kyc_validity_period: Option<u64>,
+    #[serde(default)]
+    pub uniqueness_case_statuses: UniquenessCaseStatuses,
+    /// Maximum number of the most recent verification cases taken into account, unlimited if not set
+    #[serde(default)]
+    pub max_verification_cases: Option<usize>,
+    /// Maximum size in bytes of a fetched user response body, 1 MiB if not set
+    #[serde(default)]
+    pub max_user_response_size: Option<usize>,
+    /// Whether to accept OAuth tokens submitted as raw Fractal token JSON
+    #[serde(default)]
+    pub accept_raw_tokens: bool,
+    /// Allowed redirect URIs by hint, used for code exchange instead of the client provided one
+    #[serde(default)]
+    pub redirect_uris: HashMap<String, String>,
+    /// Verification case ids excluded from the user status, e.g. compromised ones. Reloaded on SIGHUP
+    #[serde(default)]
+    pub ignored_case_ids: HashSet<String>,
+    /// Provider API version to check user responses against, not checked if not set
+    #[serde(default)]
+    pub expected_api_version: Option<ExpectedApiVersion>,
+    /// Whether to require the most recent approved KYC case to have liveness, otherwise KYC is pending
+    #[serde(default)]
+    pub strict_kyc_liveness: bool,
+}
+
+/// Provider API version expected in user responses to detect upstream breaking changes
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
+pub struct ExpectedApiVersion {
+    pub version: String,
+    /// Response header carrying the API version
+    #[serde(default = "default_api_version_header")]
+    pub header: String,
+    /// Whether to fail on a drifted or missing version instead of logging a warning
+    #[serde(default)]
+    pub strict: bool,
+}
+
+fn default_api_version_header() -> String {
+    "x-api-version".to_owned()
+}
+
+/// Case statuses at which an approved uniqueness case counts as approved, otherwise it's pending.
+/// All statuses are accepted by default, like before the statuses were configurable
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UniquenessCaseStatuses(pub Vec<CaseStatus>);
+
+impl Default for UniquenessCaseStatuses {
+    fn default() -> Self {
+        Self(vec![
+            CaseStatus::Pending,
+            CaseStatus::Contacted,
+            CaseStatus::Done,
+        ])
+    }
+}
+
+/// OAuth scopes required to be granted for a token per verification tier
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
+pub struct RequiredScopes {
+    /// Scopes required to verify a user at all
+    #[serde(default)]
+    pub uniqueness: Vec<String>,
+    /// Scopes required to attest KYC status, otherwise KYC is treated as unavailable
+    #[serde(default)]
+    pub kyc: Vec<String>,
+}
+
+impl RequiredScopes {
+    pub fn is_empty(&self) -> bool {
+        self.uniqueness.is_empty() && self.kyc.is_empty()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -30,12 +149,22 @@ pub enum FractalTokenKind {
     AuthorizationCode {
         code: String,
         captcha: String,
+        /// Ignored if redirect URIs are configured, then selected by the hint
+        #[serde(default)]
         redirect_uri: String,
+        /// Key of a configured redirect URI
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        redirect_hint: Option<String>,
     },
     OAuth {
         token: OAuthToken,
         redirect_uri: String,
     },
+    /// OAuth token as returned by Fractal, without client side encoding
+    RawOAuth {
+        token: RawFractalToken,
+        redirect_uri: String,
+    },
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
@@ -44,9 +173,11 @@ pub struct OAuthToken {
     pub access_token: String,
     pub refresh_token: String,
     pub expires_at: DateTime<Utc>,
+    /// Granted scopes, unknown for tokens issued before scopes tracking
+    pub scopes: Option<Vec<String>>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 #[serde(crate = "near_sdk::serde")]
 pub struct RawFractalToken {
     pub access_token: String,
@@ -54,10 +185,22 @@ pub struct RawFractalToken {
     pub token_type: String,
     #[serde(flatten)]
     pub lifetime: TokenLifetime,
+    /// Space separated granted scopes
+    #[serde(default)]
+    pub scope: Option<String>,
 }
 
+/// Standard OAuth error response
 #[derive(Deserialize, Debug)]
 #[serde(crate = "near_sdk::serde")]
+pub struct OAuthError {
+    pub error: String,
+    #[serde(default)]
+    pub error_description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
 pub struct TokenLifetime {
     pub expires_in: u64,
     pub created_at: u64,
@@ -98,15 +241,38 @@ pub struct Wallet {
 #[serde(crate = "near_sdk::serde")]
 pub struct VerificationCase {
     pub id: String,
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-    #[serde(deserialize_with = "utils::de_strings_joined_by_plus")]
-    pub level: Vec<VerificationLevel>,
+    /// Not set if unparsable, such cases are accepted only if configured
+    #[serde(deserialize_with = "utils::de_datetime_lenient")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(deserialize_with = "utils::de_datetime_lenient")]
+    pub updated_at: Option<DateTime<Utc>>,
+    pub level: CaseLevel,
     pub status: CaseStatus,
     pub credential: CredentialStatus,
     pub details: VerificationDetails,
 }
 
+/// Verification case levels along with the original provider string, e.g. `basic+liveness`
+#[derive(Debug, PartialEq)]
+pub struct CaseLevel {
+    pub raw: String,
+    /// Known levels, unknown ones are ignored
+    pub levels: Vec<VerificationLevel>,
+}
+
+impl<'de> Deserialize<'de> for CaseLevel {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = String::deserialize(deserializer)?;
+        let levels = utils::de_strings_joined_by_plus(serde_json::Value::from(raw.as_str()))
+            .map_err(de::Error::custom)?;
+
+        Ok(Self { raw, levels })
+    }
+}
+
 #[derive(Deserialize, Debug, PartialEq)]
 #[serde(crate = "near_sdk::serde", rename_all = "lowercase")]
 pub enum VerificationLevel {
@@ -122,7 +288,7 @@ pub enum VerificationLevel {
     Twitter,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
 #[serde(crate = "near_sdk::serde", rename_all = "lowercase")]
 pub enum CaseStatus {
     Pending,
@@ -130,7 +296,7 @@ pub enum CaseStatus {
     Done,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, PartialEq)]
 #[serde(crate = "near_sdk::serde", rename_all = "lowercase")]
 pub enum CredentialStatus {
     Pending,
@@ -138,16 +304,42 @@ pub enum CredentialStatus {
     Rejected,
 }
 
+/// Remaining step of a pending verification
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde", rename_all = "lowercase")]
+pub enum PendingStep {
+    /// Verification journey isn't completed by the user
+    Journey,
+    /// Provider requested additional information from the user
+    Contact,
+    /// Liveness check isn't passed yet
+    Liveness,
+    /// Awaiting for the provider decision
+    Review,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(crate = "near_sdk::serde")]
 pub struct VerificationDetails {
     pub liveness: bool,
+    /// Liveness audit files by field name, `None` if a file is missing
+    #[serde(flatten, deserialize_with = "de_liveness_audit")]
+    pub liveness_audit: HashMap<String, Option<String>>,
 }
 
 #[derive(Clone, Debug)]
 pub struct FractalClient {
     inner_client: Client,
     config: VerificationProviderConfig,
+    client_secret: Secret,
+    health: Arc<Mutex<Option<ProviderHealth>>>,
+    ignored_case_ids: Arc<RwLock<HashSet<String>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ProviderHealth {
+    checked_at: Instant,
+    ok: bool,
 }
 
 #[derive(Debug, PartialEq, Copy, Clone, Serialize)]
@@ -157,6 +349,27 @@ pub enum VerificationStatus {
     Pending,
     Approved,
     Rejected,
+    /// Approved, but longer ago than the configured validity period
+    Expired,
+}
+
+impl VerificationStatus {
+    /// Stable numeric code of the status embedded into signed tokens
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Unavailable => 0,
+            Self::Pending => 1,
+            Self::Approved => 2,
+            Self::Rejected => 3,
+            Self::Expired => 4,
+        }
+    }
+}
+
+/// Identifier of a verification provider, serialized as a single byte
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Copy, Clone)]
+pub enum ProviderId {
+    Fractal,
 }
 
 #[derive(Debug, Clone)]
@@ -165,55 +378,310 @@ pub struct FractalUser {
     pub token: OAuthToken,
     pub fv_status: VerificationStatus,
     pub kyc_status: VerificationStatus,
+    pub provider_id: ProviderId,
+    /// Creation time of the earliest verification case
+    pub created_at: Option<DateTime<Utc>>,
+    /// Whether the user has started any verification
+    pub has_verification_cases: bool,
+    /// Last update time of the case approving user uniqueness
+    pub verified_at: Option<DateTime<Utc>>,
+    /// Original level string of the case approving user uniqueness
+    pub raw_level: Option<String>,
+    /// Remaining steps of the most recent pending uniqueness case
+    pub pending_steps: Vec<PendingStep>,
+    pub email_count: usize,
+    pub phone_count: usize,
+    pub wallet_count: usize,
+    /// Lowercased addresses of linked NEAR wallets
+    pub near_wallets: Vec<String>,
+}
+
+impl FractalUser {
+    /// Whether both uniqueness and KYC are approved, so no other token could raise the assurance
+    pub fn is_fully_verified(&self) -> bool {
+        self.fv_status == VerificationStatus::Approved
+            && self.kyc_status == VerificationStatus::Approved
+    }
+
+    /// Aggregates the same user fetched with another token, taking approvals from either of them.
+    /// The own token is kept, so a token refreshed on the way is still echoed back
+    pub fn merge(mut self, other: FractalUser) -> Result<Self, AppError> {
+        if self.user_id != other.user_id || self.provider_id != other.provider_id {
+            tracing::debug!("Aggregated tokens belong to different users");
+            return Err(AppError::TokenUserMismatch);
+        }
+
+        if self.fv_status != VerificationStatus::Approved
+            && (other.fv_status == VerificationStatus::Approved
+                || self.fv_status == VerificationStatus::Unavailable)
+        {
+            self.fv_status = other.fv_status;
+            self.verified_at = other.verified_at;
+            self.raw_level = other.raw_level;
+            self.pending_steps = other.pending_steps;
+        }
+        if self.kyc_status != VerificationStatus::Approved
+            && (other.kyc_status == VerificationStatus::Approved
+                || self.kyc_status == VerificationStatus::Unavailable)
+        {
+            self.kyc_status = other.kyc_status;
+        }
+
+        self.created_at = match (self.created_at, other.created_at) {
+            (Some(created_at), Some(other_created_at)) => Some(created_at.min(other_created_at)),
+            (created_at, other_created_at) => created_at.or(other_created_at),
+        };
+        self.has_verification_cases |= other.has_verification_cases;
+        self.email_count = self.email_count.max(other.email_count);
+        self.phone_count = self.phone_count.max(other.phone_count);
+        self.wallet_count = self.wallet_count.max(other.wallet_count);
+        for wallet in other.near_wallets {
+            if !self.near_wallets.contains(&wallet) {
+                self.near_wallets.push(wallet);
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+/// Extra user field appended to signed tokens as a single byte
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum ExtraClaim {
+    HasEmail,
+    HasPhone,
+    HasWallet,
+    /// Number of linked wallets, saturated at 255
+    WalletCount,
+}
+
+impl ExtraClaim {
+    pub fn encode(&self, user: &FractalUser) -> u8 {
+        match self {
+            Self::HasEmail => (user.email_count > 0) as u8,
+            Self::HasPhone => (user.phone_count > 0) as u8,
+            Self::HasWallet => (user.wallet_count > 0) as u8,
+            Self::WalletCount => user.wallet_count.min(u8::MAX as usize) as u8,
+        }
+    }
 }
 
 impl FractalClient {
     pub fn create(config: VerificationProviderConfig) -> Result<Self, AppError> {
-        let inner_client = Client::builder().pool_max_idle_per_host(0).build()?;
+        // Redirects off configured hosts aren't followed to not leak credentials
+        let trusted_origins = [&config.request_token_url, &config.request_user_url]
+            .into_iter()
+            .filter_map(|url| reqwest::Url::parse(url).ok())
+            .map(|url| url.origin())
+            .collect::<Vec<_>>();
+        let redirect_policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_PROVIDER_REDIRECTS {
+                attempt.error("Too many redirects")
+            } else if trusted_origins.contains(&attempt.url().origin()) {
+                attempt.follow()
+            } else {
+                tracing::warn!("Refused provider redirect to untrusted `{}`", attempt.url());
+                attempt.stop()
+            }
+        });
+
+        let inner_client = Client::builder()
+            .pool_max_idle_per_host(0)
+            .redirect(redirect_policy)
+            .build()?;
+        let client_secret = config.load_client_secret()?;
 
         Ok(Self {
             inner_client,
+            ignored_case_ids: Arc::new(RwLock::new(config.ignored_case_ids.clone())),
             config,
+            client_secret,
+            health: Default::default(),
         })
     }
 
-    pub async fn fetch_user(
+    pub fn set_ignored_case_ids(&self, ignored_case_ids: HashSet<String>) {
+        *self
+            .ignored_case_ids
+            .write()
+            .expect("Ignored case ids lock is poisoned") = ignored_case_ids;
+    }
+
+    /// Checks if verification provider is reachable. Result is cached for a short period
+    pub async fn check_health(&self) -> bool {
+        if let Some(health) = *self.health.lock().expect("Health lock is poisoned") {
+            if health.checked_at.elapsed() < PROVIDER_HEALTH_CACHE_TTL {
+                return health.ok;
+            }
+        }
+
+        // Token-less request to the token endpoint, any response except server failure means provider is up
+        let ok = match self
+            .inner_client
+            .post(&self.config.request_token_url)
+            .form(&[("client_id", self.config.client_id.as_str())])
+            .timeout(PROVIDER_HEALTH_TIMEOUT)
+            .send()
+            .await
+        {
+            Ok(res) => !res.status().is_server_error(),
+            Err(e) => {
+                tracing::warn!("Verification provider health check failure. Error: {e:?}");
+                false
+            }
+        };
+
+        *self.health.lock().expect("Health lock is poisoned") = Some(ProviderHealth {
+            checked_at: Instant::now(),
+            ok,
+        });
+
+        ok
+    }
+
+    /// Exchanges an authorization code or takes a client provided token, refreshing it if needed
+    pub async fn acquire_user_token(
         &self,
         fractal_token: FractalTokenKind,
-    ) -> Result<FractalUser, AppError> {
+    ) -> Result<OAuthToken, AppError> {
         let mut oauth_token = match fractal_token {
             FractalTokenKind::AuthorizationCode {
-                code, redirect_uri, ..
-            } => self.acquire_oauth_token(&code, &redirect_uri).await?,
-            FractalTokenKind::OAuth { token, .. } => token,
+                code,
+                redirect_uri,
+                redirect_hint,
+                ..
+            } => {
+                let redirect_uri = self.select_redirect_uri(&redirect_uri, redirect_hint)?;
+                self.acquire_oauth_token(&code, redirect_uri).await?
+            }
+            FractalTokenKind::OAuth { mut token, .. } => {
+                self.check_refresh_token(&token)?;
+                // Scopes of a client provided token could be forged, only those from the token endpoint are trusted
+                token.scopes = None;
+                token
+            }
+            FractalTokenKind::RawOAuth { token, .. } => {
+                let token = self.convert_raw_token(token)?;
+                self.check_refresh_token(&token)?;
+                token
+            }
         };
 
-        if oauth_token.requires_refresh() {
-            oauth_token = self.refresh_oauth_token(oauth_token).await?;
+        let skew = self
+            .config
+            .token_refresh_skew
+            .unwrap_or(DEFAULT_TOKEN_REFRESH_SKEW);
+        if oauth_token.requires_refresh(Duration::seconds(skew as i64)) {
+            oauth_token = self
+                .refresh_oauth_token(oauth_token)
+                .await
+                .map_err(|e| match e {
+                    AppError::AuthorizationExpired | AppError::TimeoutError(_) => e,
+                    e => AppError::TokenRefreshFailed(e.to_string()),
+                })?;
+        } else if oauth_token.scopes.is_none() && !self.config.required_scopes.is_empty() {
+            // Scopes of a client provided token are learned from the token endpoint by a refresh.
+            // If it fails, the token is used as is and checked by the fetched user instead
+            match self.refresh_oauth_token(oauth_token.clone()).await {
+                Ok(refreshed_token) => oauth_token = refreshed_token,
+                Err(e) => tracing::warn!(
+                    "Unable to learn scopes of user token, falling back to fetched user check. Error: {e:?}"
+                ),
+            }
         }
 
         tracing::trace!("Acquired user token: {oauth_token:?}");
 
+        Ok(oauth_token)
+    }
+
+    pub async fn fetch_user(&self, oauth_token: OAuthToken) -> Result<FractalUser, AppError> {
+        // Tokens of unknown scopes are checked by the fetched user, which lacks `uid` without `uid:read` scope
+        if oauth_token.scopes.is_some()
+            && !oauth_token.has_scopes(&self.config.required_scopes.uniqueness)
+        {
+            tracing::debug!("User token lacks required scopes: {:?}", oauth_token.scopes);
+            return Err(AppError::InsufficientScope);
+        }
+        let kyc_scopes_granted = oauth_token.has_scopes(&self.config.required_scopes.kyc);
+
         let fetched_res = self
-            .inner_client
-            .get(&self.config.request_user_url)
-            .bearer_auth(&oauth_token.access_token)
-            .send()
-            .await?
-            .json::<RawFractalUser>()
+            .fetch_user_pages(&oauth_token)
             .await
-            .map_err(AppError::from);
+            .and_then(parse_user);
 
         match fetched_res {
+            Ok(user) if !self.config.allow_invalid_case_dates && user.has_invalid_case_dates() => {
+                tracing::error!("Fetched user has verification cases with invalid dates: {user:?}");
+                Err("Invalid verification case dates".to_owned().into())
+            }
             Ok(mut user) => {
                 tracing::debug!("Fetched raw user: {user:?}");
 
+                user.remove_ignored_cases(
+                    &self
+                        .ignored_case_ids
+                        .read()
+                        .expect("Ignored case ids lock is poisoned"),
+                );
+                if let Some(max_cases) = self.config.max_verification_cases {
+                    user.keep_recent_cases(max_cases);
+                }
+                if self.config.require_liveness_audit {
+                    user.ignore_unaudited_liveness();
+                }
+                user.hold_unaccepted_uniqueness(&self.config.uniqueness_case_statuses.0);
+
+                let (verified_at, raw_level) = user
+                    .get_approved_case(&[VerificationLevel::Uniqueness])
+                    .map(|case| {
+                        tracing::debug!(
+                            "Uniqueness approved by case `{}` updated at {:?}",
+                            case.id,
+                            case.updated_at
+                        );
+                        (case.updated_at, case.level.raw.clone())
+                    })
+                    .unzip();
+                let verified_at = verified_at.flatten();
+                let fv_status = user.get_status(&[VerificationLevel::Uniqueness]);
+
+                // Refused for a case with an unparsable date, so no token is signed with a made up time
+                if fv_status == VerificationStatus::Approved && verified_at.is_none() {
+                    tracing::error!("Uniqueness is approved at unknown time: {user:?}");
+                    return Err(AppError::UnknownVerificationTime);
+                }
+
+                let kyc_status = if !kyc_scopes_granted {
+                    tracing::debug!("User token lacks KYC scopes, KYC status is unavailable");
+                    VerificationStatus::Unavailable
+                } else if self.config.strict_kyc_liveness {
+                    user.get_strict_kyc_status(self.config.kyc_validity_period)
+                } else {
+                    user.get_kyc_status(self.config.kyc_validity_period)
+                };
+
                 Ok(FractalUser {
-                    fv_status: user.get_status(&[VerificationLevel::Uniqueness]),
-                    kyc_status: user
-                        .get_status(&[VerificationLevel::Basic, VerificationLevel::Liveness]),
+                    fv_status,
+                    kyc_status,
+                    created_at: user.get_earliest_case_created_at(),
+                    has_verification_cases: !user.verification_cases.is_empty(),
+                    verified_at,
+                    raw_level,
+                    pending_steps: user.get_pending_steps(&[VerificationLevel::Uniqueness]),
+                    email_count: user.emails.len(),
+                    phone_count: user.phones.len(),
+                    wallet_count: user.wallets.len(),
+                    near_wallets: user
+                        .wallets
+                        .iter()
+                        .filter(|wallet| wallet.currency.eq_ignore_ascii_case("near"))
+                        .map(|wallet| wallet.address.to_lowercase())
+                        .collect(),
                     user_id: user.uid,
                     token: oauth_token,
+                    provider_id: ProviderId::Fractal,
                 })
             }
             Err(e) => {
@@ -223,6 +691,175 @@ impl FractalClient {
         }
     }
 
+    fn check_refresh_token(&self, oauth_token: &OAuthToken) -> Result<(), AppError> {
+        if self.config.reject_empty_refresh_token && oauth_token.refresh_token.is_empty() {
+            return Err(AppError::MalformedRequest(
+                "OAuth token has empty refresh token".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Selects a configured redirect URI by the hint, or the client provided one if none is configured
+    fn select_redirect_uri<'a>(
+        &'a self,
+        redirect_uri: &'a str,
+        redirect_hint: Option<String>,
+    ) -> Result<&'a str, AppError> {
+        match redirect_hint {
+            None if self.config.redirect_uris.is_empty() => Ok(redirect_uri),
+            None => Err(AppError::MalformedRequest(
+                "Redirect URI hint is required".to_owned(),
+            )),
+            Some(hint) => self
+                .config
+                .redirect_uris
+                .get(&hint)
+                .map(String::as_str)
+                .ok_or_else(|| {
+                    AppError::MalformedRequest(format!("Unknown redirect URI hint `{hint}`"))
+                }),
+        }
+    }
+
+    /// Converts client submitted raw Fractal token, if accepted
+    fn convert_raw_token(&self, raw_token: RawFractalToken) -> Result<OAuthToken, AppError> {
+        if !self.config.accept_raw_tokens {
+            return Err(AppError::MalformedRequest(
+                "Raw OAuth tokens are not accepted".to_owned(),
+            ));
+        }
+
+        if !raw_token.token_type.eq_ignore_ascii_case("bearer") {
+            return Err(AppError::MalformedRequest(format!(
+                "Unsupported token type {}",
+                raw_token.token_type
+            )));
+        }
+
+        // Client claimed scopes aren't trusted, so the token has unknown scopes until refreshed
+        Ok(OAuthToken {
+            scopes: None,
+            ..OAuthToken::from(raw_token)
+        })
+    }
+
+    /// Fetches user data, following pagination by `Link` header or `next_cursor` field
+    /// and aggregating verification cases of all pages into the first one
+    async fn fetch_user_pages(
+        &self,
+        oauth_token: &OAuthToken,
+    ) -> Result<serde_json::Value, AppError> {
+        let max_response_size = self
+            .config
+            .max_user_response_size
+            .unwrap_or(DEFAULT_MAX_USER_RESPONSE_SIZE);
+
+        // Shared by all pages, so pagination can't buffer more than the limit in total
+        let mut remaining_size = max_response_size;
+        let mut user: Option<serde_json::Value> = None;
+        let mut page_req = self.inner_client.get(&self.config.request_user_url);
+        for page_num in 1..=MAX_USER_PAGES {
+            let res = page_req
+                .bearer_auth(&oauth_token.access_token)
+                .send()
+                .await
+                .map_err(AppError::from)
+                .and_then(check_user_status)
+                .and_then(check_json_content_type)
+                .and_then(|res| self.check_api_version(res))?;
+            let page_url = res.url().clone();
+            let next_link = next_page_link(res.headers());
+            let body = read_limited_body(res, remaining_size)
+                .await
+                .map_err(|e| match e {
+                    AppError::UpstreamResponseTooLarge(_) => {
+                        AppError::UpstreamResponseTooLarge(max_response_size)
+                    }
+                    e => e,
+                })?;
+            remaining_size -= body.len();
+            let mut page = parse_user_body(&body)?;
+            let next_cursor = page
+                .get("next_cursor")
+                .and_then(serde_json::Value::as_str)
+                .map(ToOwned::to_owned);
+
+            match &mut user {
+                Some(user) => {
+                    let page_cases = page
+                        .get_mut("verification_cases")
+                        .and_then(serde_json::Value::as_array_mut)
+                        .map(std::mem::take)
+                        .unwrap_or_default();
+                    if let Some(cases) = user
+                        .get_mut("verification_cases")
+                        .and_then(serde_json::Value::as_array_mut)
+                    {
+                        cases.extend(page_cases);
+                    }
+                }
+                None => user = Some(page),
+            }
+
+            let cases_count = user
+                .as_ref()
+                .and_then(|user| user.get("verification_cases"))
+                .and_then(serde_json::Value::as_array)
+                .map_or(0, Vec::len);
+            if self
+                .config
+                .max_verification_cases
+                .map_or(false, |max_cases| cases_count >= max_cases)
+            {
+                tracing::debug!("Fetched {cases_count} verification cases reaching the cap, further pages are skipped");
+                break;
+            }
+
+            page_req = match (next_link, next_cursor) {
+                (Some(link), _) => {
+                    let next_url = page_url.join(&link).map_err(|e| {
+                        AppError::from(format!("Invalid next page link {link:?}: {e}"))
+                    })?;
+                    // Bearer token is only sent to the configured provider origin
+                    if !is_same_origin(&next_url, &self.config.request_user_url) {
+                        return Err(AppError::from(format!(
+                            "Next page link {next_url} is outside of the user endpoint origin"
+                        )));
+                    }
+                    self.inner_client.get(next_url)
+                }
+                (None, Some(cursor)) => self
+                    .inner_client
+                    .get(&self.config.request_user_url)
+                    .query(&[("cursor", cursor)]),
+                (None, None) => break,
+            };
+
+            if page_num == MAX_USER_PAGES {
+                tracing::warn!(
+                    "User response has over {MAX_USER_PAGES} pages, the rest are skipped"
+                );
+            }
+        }
+
+        user.ok_or_else(|| "No user response pages fetched".to_owned().into())
+    }
+
+    /// Checks the API version of a user response, if an expected one is configured
+    fn check_api_version(&self, res: reqwest::Response) -> Result<reqwest::Response, AppError> {
+        if let Some(expected) = &self.config.expected_api_version {
+            let received = res
+                .headers()
+                .get(expected.header.as_str())
+                .and_then(|value| value.to_str().ok());
+            check_api_version(expected, received)?;
+        }
+
+        Ok(res)
+    }
+
     async fn acquire_oauth_token(
         &self,
         code: &str,
@@ -230,7 +867,7 @@ impl FractalClient {
     ) -> Result<OAuthToken, AppError> {
         let params: [(&str, &str); 5] = [
             ("client_id", &self.config.client_id),
-            ("client_secret", &self.config.client_secret),
+            ("client_secret", self.client_secret.expose()),
             ("code", code),
             ("grant_type", "authorization_code"),
             ("redirect_uri", redirect_uri),
@@ -241,17 +878,15 @@ impl FractalClient {
             .post(&self.config.request_token_url)
             .form(&params)
             .send()
-            .await?
+            .await
+            .map_err(AppError::from)
+            .and_then(check_json_content_type)?
             .text()
             .await?;
 
         tracing::trace!("Acquired raw fractal token response: {data}");
 
-        match serde_json::from_str::<RawFractalToken>(&data) {
-            Ok(token) if token.token_type.as_str() == "Bearer" => Ok(OAuthToken::from(token)),
-            Ok(token) => Err(format!("Unsupported token type {:?}", token).into()),
-            Err(_) => Err(format!("Failed to parse token response {:?}", data).into()),
-        }
+        parse_token_response(&data)
     }
 
     async fn refresh_oauth_token(&self, oauth_token: OAuthToken) -> Result<OAuthToken, AppError> {
@@ -259,7 +894,7 @@ impl FractalClient {
 
         let params: [(&str, &str); 4] = [
             ("client_id", &self.config.client_id),
-            ("client_secret", &self.config.client_secret),
+            ("client_secret", self.client_secret.expose()),
             ("refresh_token", &oauth_token.refresh_token),
             ("grant_type", "refresh_token"),
         ];
@@ -269,18 +904,191 @@ impl FractalClient {
             .post(&self.config.request_token_url)
             .form(&params)
             .send()
-            .await?
+            .await
+            .map_err(AppError::from)
+            .and_then(check_json_content_type)?
             .text()
             .await?;
 
-        match serde_json::from_str::<RawFractalToken>(&data) {
-            Ok(token) if token.token_type.as_str() == "Bearer" => Ok(OAuthToken::from(token)),
-            Ok(token) => Err(format!("Unsupported token type {:?}", token).into()),
-            Err(_) => Err(format!("Failed to parse token response {:?}", data).into()),
+        parse_token_response(&data)
+    }
+}
+
+impl VerificationProviderConfig {
+    /// Loads client secret from a configured path, falls back to inline value otherwise
+    fn load_client_secret(&self) -> Result<Secret, AppError> {
+        match (&self.client_secret_path, &self.client_secret) {
+            (Some(path), _) => Secret::load(path),
+            (None, Some(secret)) => Ok(secret.clone()),
+            (None, None) => Err("Fractal client secret should be provided either with clientSecretPath or clientSecret".to_owned().into()),
         }
     }
 }
 
+/// Maps a failed user response status before its body is parsed, so an error body isn't mistaken
+/// for a user without granted scopes. Rejected access token means the authorization has to be restarted
+fn check_user_status(res: reqwest::Response) -> Result<reqwest::Response, AppError> {
+    match res.status() {
+        reqwest::StatusCode::UNAUTHORIZED => {
+            tracing::debug!(
+                "Provider rejected access token of user from `{}`",
+                res.url()
+            );
+            Err(AppError::AuthorizationExpired)
+        }
+        _ => res.error_for_status().map_err(|e| {
+            tracing::error!("Provider failed to respond with user. Error: {e:?}");
+            AppError::from(e)
+        }),
+    }
+}
+
+/// Checks that a provider response is JSON to tell misrouted requests apart from malformed responses
+fn check_json_content_type(res: reqwest::Response) -> Result<reqwest::Response, AppError> {
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+
+    if media_type.eq_ignore_ascii_case("application/json") {
+        Ok(res)
+    } else {
+        tracing::error!(
+            "Provider responded {} from `{}` with unexpected content type `{content_type}`",
+            res.status(),
+            res.url()
+        );
+        Err(AppError::UnexpectedContentType(content_type.to_owned()))
+    }
+}
+
+/// Compares received provider API version with the expected one, failing only in strict mode
+fn check_api_version(
+    expected: &ExpectedApiVersion,
+    received: Option<&str>,
+) -> Result<(), AppError> {
+    let drift = match received {
+        Some(version) if version == expected.version => return Ok(()),
+        Some(version) => format!(
+            "Provider API version `{version}` differs from expected `{}`",
+            expected.version
+        ),
+        None => format!(
+            "Provider API version is missing in `{}` header, expected `{}`",
+            expected.header, expected.version
+        ),
+    };
+
+    if expected.strict {
+        tracing::error!("{drift}");
+        Err(AppError::UnexpectedApiVersion(
+            received.unwrap_or_default().to_owned(),
+        ))
+    } else {
+        tracing::warn!("{drift}");
+        Ok(())
+    }
+}
+
+/// Parses token endpoint response, surfacing standard OAuth errors
+fn parse_token_response(data: &str) -> Result<OAuthToken, AppError> {
+    if let Ok(OAuthError {
+        error,
+        error_description,
+    }) = serde_json::from_str::<OAuthError>(data)
+    {
+        tracing::debug!("Token request failed with `{error}`: {error_description:?}");
+
+        return Err(match error.as_str() {
+            "invalid_grant" => AppError::AuthorizationExpired,
+            _ => AppError::Generic(format!(
+                "OAuth error `{error}`: {}",
+                error_description.as_deref().unwrap_or("no description")
+            )),
+        });
+    }
+
+    match serde_json::from_str::<RawFractalToken>(data) {
+        Ok(token) if token.token_type.as_str() == "Bearer" => Ok(OAuthToken::from(token)),
+        Ok(token) => Err(format!("Unsupported token type {:?}", token).into()),
+        Err(_) => Err(format!("Failed to parse token response {:?}", data).into()),
+    }
+}
+
+/// Reads a response body, failing as soon as its declared or received size exceeds the limit
+async fn read_limited_body(mut res: reqwest::Response, limit: usize) -> Result<Vec<u8>, AppError> {
+    let body_error = |e: reqwest::Error| {
+        if e.is_body() {
+            AppError::UpstreamTruncated(e.to_string())
+        } else {
+            AppError::from(e)
+        }
+    };
+
+    if res.content_length().map_or(false, |len| len > limit as u64) {
+        tracing::error!(
+            "Upstream response declares {:?} bytes over the limit of {limit}",
+            res.content_length()
+        );
+        return Err(AppError::UpstreamResponseTooLarge(limit));
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = res.chunk().await.map_err(body_error)? {
+        if body.len() + chunk.len() > limit {
+            tracing::error!("Upstream response exceeds the limit of {limit} bytes");
+            return Err(AppError::UpstreamResponseTooLarge(limit));
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(body)
+}
+
+/// Checks if a URL has the same scheme, host and port as a base URL
+fn is_same_origin(url: &reqwest::Url, base_url: &str) -> bool {
+    reqwest::Url::parse(base_url).map_or(false, |base_url| url.origin() == base_url.origin())
+}
+
+/// Extracts the `rel="next"` target of a `Link` header, e.g. `<https://host/users/me?page=2>; rel="next"`
+fn next_page_link(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    headers
+        .get_all(reqwest::header::LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|link| {
+            let mut parts = link.split(';').map(str::trim);
+            let target = parts.next()?.strip_prefix('<')?.strip_suffix('>')?;
+            parts
+                .any(|param| param == "rel=\"next\"" || param == "rel=next")
+                .then(|| target.to_owned())
+        })
+}
+
+/// Parses fetched user response body, telling a truncated body apart from malformed JSON
+fn parse_user_body(body: &[u8]) -> Result<serde_json::Value, AppError> {
+    serde_json::from_slice(body).map_err(|e| {
+        if e.is_eof() {
+            AppError::UpstreamTruncated(e.to_string())
+        } else {
+            AppError::from(e)
+        }
+    })
+}
+
+/// Parses fetched user data, missing `uid` means the token lacks `uid:read` scope
+fn parse_user(value: serde_json::Value) -> Result<RawFractalUser, AppError> {
+    if value.get("uid").is_none() {
+        tracing::debug!("Fetched user lacks `uid`, token scope is insufficient");
+        return Err(AppError::InsufficientScope);
+    }
+
+    serde_json::from_value(value).map_err(AppError::from)
+}
+
 impl RawFractalUser {
     fn get_status(&mut self, levels: &[VerificationLevel]) -> VerificationStatus {
         // Sort by updated_at timestamp, most recent first
@@ -292,26 +1100,24 @@ impl RawFractalUser {
             .iter()
             .filter_map(|case| {
                 // Ignore cases other than related to requested levels
-                for level in levels {
-                    if !case.level.iter().any(|l| l == level) {
-                        return None;
-                    }
+                if !case.has_levels(levels) {
+                    return None;
                 }
 
                 match case {
                     VerificationCase {
                         credential: CredentialStatus::Approved,
-                        details: VerificationDetails { liveness: true },
+                        details: VerificationDetails { liveness: true, .. },
                         ..
                     } => Some(VerificationStatus::Approved),
                     VerificationCase {
                         credential: CredentialStatus::Pending,
-                        details: VerificationDetails { liveness: true },
+                        details: VerificationDetails { liveness: true, .. },
                         ..
                     } => Some(VerificationStatus::Pending),
                     VerificationCase {
                         credential: CredentialStatus::Rejected,
-                        details: VerificationDetails { liveness: true },
+                        details: VerificationDetails { liveness: true, .. },
                         ..
                     } => Some(VerificationStatus::Rejected),
                     // Ignore verification cases without `liveness: true`
@@ -333,6 +1139,207 @@ impl RawFractalUser {
             .first()
             .unwrap_or(&VerificationStatus::Unavailable)
     }
+
+    /// Returns KYC status, treating approval older than the validity period as expired
+    fn get_kyc_status(&mut self, validity_period: Option<u64>) -> VerificationStatus {
+        let levels = [VerificationLevel::Basic, VerificationLevel::Liveness];
+        let status = self.get_status(&levels);
+
+        match validity_period {
+            Some(period) if status == VerificationStatus::Approved => {
+                let valid_since = Utc::now() - Duration::seconds(period as i64);
+
+                match self.get_approved_case(&levels) {
+                    // Unknown time goes before any, as an approval can't be proven to be within the period
+                    Some(case) if case.updated_at < Some(valid_since) => {
+                        tracing::debug!(
+                            "KYC approved by case `{}` updated at {:?} is expired",
+                            case.id,
+                            case.updated_at
+                        );
+                        VerificationStatus::Expired
+                    }
+                    _ => status,
+                }
+            }
+            _ => status,
+        }
+    }
+
+    /// Returns KYC status, downgrading approval to pending if the most recent approved case lacks liveness
+    fn get_strict_kyc_status(&mut self, validity_period: Option<u64>) -> VerificationStatus {
+        let status = self.get_kyc_status(validity_period);
+        if status != VerificationStatus::Approved {
+            return status;
+        }
+
+        // Cases are sorted by `get_status`, most recent first
+        let levels = [VerificationLevel::Basic, VerificationLevel::Liveness];
+        match self
+            .verification_cases
+            .iter()
+            .find(|case| case.has_levels(&levels) && case.credential == CredentialStatus::Approved)
+        {
+            Some(case) if !case.details.liveness => {
+                tracing::debug!(
+                    "Most recent approved KYC case `{}` lacks liveness, KYC is pending",
+                    case.id
+                );
+                VerificationStatus::Pending
+            }
+            _ => status,
+        }
+    }
+
+    /// Checks if any verification case dates failed to parse
+    fn has_invalid_case_dates(&self) -> bool {
+        self.verification_cases
+            .iter()
+            .any(|case| case.created_at.is_none() || case.updated_at.is_none())
+    }
+
+    /// Returns creation time of the earliest verification case, ignoring unparsed dates
+    fn get_earliest_case_created_at(&self) -> Option<DateTime<Utc>> {
+        self.verification_cases
+            .iter()
+            .filter_map(|case| case.created_at)
+            .min()
+    }
+
+    /// Treats cases with missing liveness audit files as lacking liveness
+    fn ignore_unaudited_liveness(&mut self) {
+        for case in &mut self.verification_cases {
+            if case.details.liveness && !case.details.has_liveness_audit() {
+                tracing::debug!("Verification case `{}` lacks liveness audit files", case.id);
+                case.details.liveness = false;
+            }
+        }
+    }
+
+    /// Removes cases excluded by operators
+    fn remove_ignored_cases(&mut self, ignored_case_ids: &HashSet<String>) {
+        self.verification_cases.retain(|case| {
+            let ignored = ignored_case_ids.contains(&case.id);
+            if ignored {
+                tracing::warn!("Verification case `{}` is ignored", case.id);
+            }
+            !ignored
+        });
+    }
+
+    /// Keeps only the most recent cases by `updated_at`, ignoring older ones
+    fn keep_recent_cases(&mut self, max_cases: usize) {
+        if self.verification_cases.len() <= max_cases {
+            return;
+        }
+
+        tracing::debug!(
+            "User has {} verification cases, only {max_cases} most recent are considered",
+            self.verification_cases.len()
+        );
+        self.verification_cases
+            .sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        self.verification_cases.truncate(max_cases);
+    }
+
+    /// Treats approved uniqueness cases at not accepted case statuses as pending
+    fn hold_unaccepted_uniqueness(&mut self, accepted_statuses: &[CaseStatus]) {
+        for case in &mut self.verification_cases {
+            if case.has_levels(&[VerificationLevel::Uniqueness])
+                && case.credential == CredentialStatus::Approved
+                && !accepted_statuses.contains(&case.status)
+            {
+                tracing::debug!(
+                    "Approved uniqueness case `{}` has not accepted status {:?}",
+                    case.id,
+                    case.status
+                );
+                case.credential = CredentialStatus::Pending;
+            }
+        }
+    }
+
+    /// Returns the most recent approved case related to requested levels
+    fn get_approved_case(&mut self, levels: &[VerificationLevel]) -> Option<&VerificationCase> {
+        // Sort by updated_at timestamp, most recent first
+        self.verification_cases
+            .sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+
+        self.verification_cases.iter().find(|case| {
+            case.has_levels(levels)
+                && matches!(
+                    case,
+                    VerificationCase {
+                        credential: CredentialStatus::Approved,
+                        details: VerificationDetails { liveness: true, .. },
+                        ..
+                    }
+                )
+        })
+    }
+
+    /// Returns remaining steps of the most recent pending case related to requested levels
+    fn get_pending_steps(&mut self, levels: &[VerificationLevel]) -> Vec<PendingStep> {
+        // Sort by updated_at timestamp, most recent first
+        self.verification_cases
+            .sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+
+        let pending_case = self
+            .verification_cases
+            .iter()
+            .find(|case| case.has_levels(levels) && case.credential == CredentialStatus::Pending);
+
+        let case = match pending_case {
+            Some(case) => case,
+            None => return vec![],
+        };
+
+        let mut steps = vec![];
+        match case.status {
+            CaseStatus::Pending => steps.push(PendingStep::Journey),
+            CaseStatus::Contacted => steps.push(PendingStep::Contact),
+            CaseStatus::Done => (),
+        }
+        if !case.details.liveness {
+            steps.push(PendingStep::Liveness);
+        }
+        steps.push(PendingStep::Review);
+
+        steps
+    }
+}
+
+impl VerificationDetails {
+    /// Checks if any liveness audit file is provided and none is missing
+    fn has_liveness_audit(&self) -> bool {
+        !self.liveness_audit.is_empty() && self.liveness_audit.values().all(Option::is_some)
+    }
+}
+
+/// Collects liveness audit file fields out of verification case details
+fn de_liveness_audit<'de, D>(deserializer: D) -> Result<HashMap<String, Option<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let fields = HashMap::<String, serde_json::Value>::deserialize(deserializer)?;
+
+    Ok(fields
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(LIVENESS_AUDIT_PREFIX))
+        .map(|(name, value)| match value {
+            serde_json::Value::String(file) => (name, Some(file)),
+            _ => (name, None),
+        })
+        .collect())
+}
+
+impl VerificationCase {
+    /// Checks if the case covers all requested levels
+    fn has_levels(&self, levels: &[VerificationLevel]) -> bool {
+        levels
+            .iter()
+            .all(|level| self.level.levels.iter().any(|l| l == level))
+    }
 }
 
 impl<'de> Deserialize<'de> for OAuthToken {
@@ -360,7 +1367,16 @@ impl<'a> FractalTokenKind {
     pub fn captcha(&'a self) -> Option<&'a str> {
         match self {
             Self::AuthorizationCode { captcha, .. } => Some(captcha),
-            Self::OAuth { .. } => None,
+            Self::OAuth { .. } | Self::RawOAuth { .. } => None,
+        }
+    }
+
+    /// Access token of a client provided token
+    pub fn access_token(&'a self) -> Option<&'a str> {
+        match self {
+            Self::AuthorizationCode { .. } => None,
+            Self::OAuth { token, .. } => Some(&token.access_token),
+            Self::RawOAuth { token, .. } => Some(&token.access_token),
         }
     }
 }
@@ -372,8 +1388,18 @@ impl TokenLifetime {
 }
 
 impl OAuthToken {
-    pub fn requires_refresh(&self) -> bool {
-        Utc::now() + Duration::minutes(OAUTH_TOKEN_MINIMUM_LIFETIME) >= self.expires_at
+    /// Checks if the token expires within the skew
+    pub fn requires_refresh(&self, skew: Duration) -> bool {
+        Utc::now() + skew >= self.expires_at
+    }
+
+    /// Checks if all required scopes are granted. Tokens with unknown scopes satisfy empty requirements only
+    pub fn has_scopes(&self, required: &[String]) -> bool {
+        match &self.scopes {
+            _ if required.is_empty() => true,
+            Some(scopes) => required.iter().all(|scope| scopes.contains(scope)),
+            None => false,
+        }
     }
 }
 
@@ -383,6 +1409,9 @@ impl From<RawFractalToken> for OAuthToken {
             access_token: token.access_token,
             refresh_token: token.refresh_token,
             expires_at: token.lifetime.expires_at(),
+            scopes: token
+                .scope
+                .map(|scope| scope.split_whitespace().map(ToOwned::to_owned).collect()),
         }
     }
 }
@@ -406,11 +1435,18 @@ impl BorshDeserialize for OAuthToken {
         let access_token = BorshDeserialize::deserialize(buf)?;
         let refresh_token = BorshDeserialize::deserialize(buf)?;
         let ts = BorshDeserialize::deserialize(buf)?;
+        // Tokens issued before scopes tracking have no trailing scopes
+        let scopes = if buf.is_empty() {
+            None
+        } else {
+            Some(BorshDeserialize::deserialize(buf)?)
+        };
 
         Ok(OAuthToken {
             access_token,
             refresh_token,
             expires_at: Utc.timestamp_nanos(ts),
+            scopes,
         })
     }
 }
@@ -423,16 +1459,716 @@ impl BorshSerialize for OAuthToken {
         let ts = self.expires_at.timestamp_nanos();
         BorshSerialize::serialize(&self.access_token, writer)?;
         BorshSerialize::serialize(&self.refresh_token, writer)?;
-        BorshSerialize::serialize(&ts, writer)
+        BorshSerialize::serialize(&ts, writer)?;
+
+        match &self.scopes {
+            Some(scopes) => BorshSerialize::serialize(scopes, writer),
+            None => Ok(()),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::test_utils::spawn_mock_server;
     use assert_matches::assert_matches;
     use chrono::{DateTime, Duration, TimeZone, Utc};
     use near_sdk::serde_json;
+    use std::io::Write;
+
+    #[test]
+    fn test_client_secret_from_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "file_secret").unwrap();
+
+        let client = FractalClient::create(VerificationProviderConfig {
+            client_secret: Some(Secret::from("inline_secret")),
+            client_secret_path: Some(file.path().to_str().unwrap().to_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(client.client_secret.expose(), "file_secret");
+        assert!(!format!("{client:?}").contains("file_secret"));
+
+        let client = FractalClient::create(VerificationProviderConfig {
+            client_secret: Some(Secret::from("inline_secret")),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(client.client_secret.expose(), "inline_secret");
+        assert!(!format!("{client:?}").contains("inline_secret"));
+
+        assert_matches!(
+            FractalClient::create(VerificationProviderConfig::default()),
+            Err(AppError::Generic(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acquire_user_token_refresh() {
+        use axum::{http::StatusCode, routing::post, Form, Json, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let url = spawn_mock_server(Router::new().route(
+            "/oauth/token",
+            post({
+                let refreshes = refreshes.clone();
+                move |Form(params): Form<HashMap<String, String>>| async move {
+                    refreshes.fetch_add(1, Ordering::SeqCst);
+                    match params.get("refresh_token").map(String::as_str) {
+                        Some("revoked_refresh_token") => Err((
+                            StatusCode::BAD_REQUEST,
+                            Json(serde_json::json!({ "error": "invalid_grant" })),
+                        )),
+                        Some("some_refresh_token") => Ok(Json(serde_json::json!({
+                            "access_token": "refreshed_access_token",
+                            "refresh_token": "refreshed_refresh_token",
+                            "token_type": "Bearer",
+                            "expires_in": 7200,
+                            "created_at": Utc::now().timestamp(),
+                            "scope": "uid:read",
+                        }))),
+                        _ => Err((
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(serde_json::json!({ "error": "server_error" })),
+                        )),
+                    }
+                }
+            }),
+        ));
+        let client = FractalClient::create(VerificationProviderConfig {
+            request_token_url: format!("{url}/oauth/token"),
+            client_id: "some_client_id".to_owned(),
+            client_secret: Some(Secret::from("some_client_secret")),
+            token_refresh_skew: Some(30),
+            ..Default::default()
+        })
+        .unwrap();
+        let gen_token = |refresh_token: &str, expires_at| FractalTokenKind::OAuth {
+            token: OAuthToken {
+                access_token: "some_auth_token".to_owned(),
+                refresh_token: refresh_token.to_owned(),
+                expires_at,
+                scopes: Some(vec![
+                    "uid:read".to_owned(),
+                    "verification.basic:read".to_owned(),
+                ]),
+            },
+            redirect_uri: "https://some_url".to_owned(),
+        };
+
+        // Not yet expired token is used as is, but its client claimed scopes aren't trusted
+        assert_matches!(
+            client
+                .acquire_user_token(gen_token("some_refresh_token", Utc::now() + Duration::minutes(5)))
+                .await,
+            Ok(OAuthToken { access_token, scopes: None, .. }) if access_token == "some_auth_token"
+        );
+        assert_eq!(refreshes.load(Ordering::SeqCst), 0);
+
+        // Expired or expiring within the skew token is refreshed
+        assert_matches!(
+            client
+                .acquire_user_token(gen_token("some_refresh_token", Utc::now() - Duration::hours(1)))
+                .await,
+            Ok(OAuthToken { access_token, refresh_token, scopes: Some(scopes), .. })
+                if access_token == "refreshed_access_token"
+                    && refresh_token == "refreshed_refresh_token"
+                    && scopes == vec!["uid:read".to_owned()]
+        );
+        assert_matches!(
+            client
+                .acquire_user_token(gen_token("some_refresh_token", Utc::now() + Duration::seconds(10)))
+                .await,
+            Ok(OAuthToken { access_token, .. }) if access_token == "refreshed_access_token"
+        );
+        assert_eq!(refreshes.load(Ordering::SeqCst), 2);
+
+        // Rejected refresh fails distinctly
+        assert_matches!(
+            client
+                .acquire_user_token(gen_token("revoked_refresh_token", Utc::now()))
+                .await,
+            Err(AppError::AuthorizationExpired)
+        );
+        assert_matches!(
+            client
+                .acquire_user_token(gen_token("other_refresh_token", Utc::now()))
+                .await,
+            Err(AppError::TokenRefreshFailed(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resubmitted_token_required_scopes() {
+        use axum::{
+            http::StatusCode,
+            routing::{get, post},
+            Form, Json, Router,
+        };
+
+        let url = spawn_mock_server(
+            Router::new()
+                .route(
+                    "/oauth/token",
+                    post(|Form(params): Form<HashMap<String, String>>| async move {
+                        match params.get("refresh_token").map(String::as_str) {
+                            Some("some_refresh_token") => Ok(Json(serde_json::json!({
+                                "access_token": "refreshed_access_token",
+                                "refresh_token": "refreshed_refresh_token",
+                                "token_type": "Bearer",
+                                "expires_in": 7200,
+                                "created_at": Utc::now().timestamp(),
+                                "scope": "uid:read verification.basic:read",
+                            }))),
+                            _ => Err((
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                Json(serde_json::json!({ "error": "server_error" })),
+                            )),
+                        }
+                    }),
+                )
+                .route(
+                    "/users/me",
+                    get(|| async {
+                        Json(serde_json::json!({
+                            "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                            "emails": [],
+                            "phones": [],
+                            "wallets": [],
+                            "verification_cases": []
+                        }))
+                    }),
+                ),
+        );
+        let client = FractalClient::create(VerificationProviderConfig {
+            request_token_url: format!("{url}/oauth/token"),
+            request_user_url: format!("{url}/users/me"),
+            client_id: "some_client_id".to_owned(),
+            client_secret: Some(Secret::from("some_client_secret")),
+            required_scopes: RequiredScopes {
+                uniqueness: vec!["uid:read".to_owned()],
+                kyc: vec!["verification.basic:read".to_owned()],
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        // Token from a pending response, resubmitted with its scopes unknown
+        let gen_token = |refresh_token: &str| FractalTokenKind::OAuth {
+            token: OAuthToken {
+                access_token: "some_auth_token".to_owned(),
+                refresh_token: refresh_token.to_owned(),
+                expires_at: Utc::now() + Duration::hours(1),
+                scopes: None,
+            },
+            redirect_uri: "https://some_url".to_owned(),
+        };
+
+        // Scopes are learned by a refresh
+        let token = client
+            .acquire_user_token(gen_token("some_refresh_token"))
+            .await
+            .unwrap();
+        assert_eq!(token.access_token, "refreshed_access_token");
+        assert_eq!(
+            token.scopes,
+            Some(vec![
+                "uid:read".to_owned(),
+                "verification.basic:read".to_owned()
+            ])
+        );
+        assert_matches!(client.fetch_user(token).await, Ok(_));
+
+        // Otherwise the token is checked by the fetched user
+        let token = client
+            .acquire_user_token(gen_token("other_refresh_token"))
+            .await
+            .unwrap();
+        assert_eq!(token.access_token, "some_auth_token");
+        assert_eq!(token.scopes, None);
+        assert_matches!(
+            client.fetch_user(token).await,
+            Ok(FractalUser {
+                kyc_status: VerificationStatus::Unavailable,
+                ..
+            })
+        );
+
+        // Known insufficient scopes are still rejected
+        let token = OAuthToken {
+            access_token: "some_auth_token".to_owned(),
+            refresh_token: "some_refresh_token".to_owned(),
+            expires_at: Utc::now() + Duration::hours(1),
+            scopes: Some(vec!["email:read".to_owned()]),
+        };
+        assert_matches!(
+            client.fetch_user(token).await,
+            Err(AppError::InsufficientScope)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_redirect_not_followed() {
+        use axum::{http::HeaderMap, response::Redirect, routing::get, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let leaked = Arc::new(AtomicUsize::new(0));
+        let counter = leaked.clone();
+        let untrusted_url = spawn_mock_server(Router::new().route(
+            "/leak",
+            get(move |headers: HeaderMap| async move {
+                if headers.contains_key("authorization") {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+                "{}"
+            }),
+        ));
+        let trusted_url =
+            spawn_mock_server(
+                Router::new()
+                    .route(
+                        "/users/me",
+                        get(move || async move {
+                            Redirect::temporary(&format!("{untrusted_url}/leak"))
+                        }),
+                    )
+                    .route(
+                        "/users/moved",
+                        get(|| async { Redirect::temporary("/users/me/v2") }),
+                    )
+                    .route("/users/me/v2", get(|| async { "{}" })),
+            );
+
+        let client = FractalClient::create(VerificationProviderConfig {
+            request_user_url: format!("{trusted_url}/users/me"),
+            client_secret: Some(Secret::from("some_client_secret")),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let res = client
+            .inner_client
+            .get(format!("{trusted_url}/users/me"))
+            .bearer_auth("some_auth_token")
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_redirection());
+        assert_eq!(leaked.load(Ordering::SeqCst), 0);
+
+        // Redirects within the configured host are followed
+        let res = client
+            .inner_client
+            .get(format!("{trusted_url}/users/moved"))
+            .bearer_auth("some_auth_token")
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_content_type() {
+        use axum::{
+            http::header,
+            routing::{get, post},
+            Router,
+        };
+
+        let html = || async { ([(header::CONTENT_TYPE, "text/html")], "<html></html>") };
+        let url = spawn_mock_server(
+            Router::new()
+                .route("/oauth/token", post(html))
+                .route("/users/me", get(html)),
+        );
+        let client = FractalClient::create(VerificationProviderConfig {
+            request_token_url: format!("{url}/oauth/token"),
+            request_user_url: format!("{url}/users/me"),
+            client_id: "some_client_id".to_owned(),
+            client_secret: Some(Secret::from("some_client_secret")),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_matches!(
+            client.acquire_oauth_token("some_code", "https://some_url").await,
+            Err(AppError::UnexpectedContentType(content_type)) if content_type == "text/html"
+        );
+        assert_matches!(
+            client
+                .fetch_user(OAuthToken {
+                    access_token: "some_auth_token".to_owned(),
+                    refresh_token: "some_refresh_token".to_owned(),
+                    expires_at: Utc::now() + Duration::days(1),
+                    scopes: None,
+                })
+                .await,
+            Err(AppError::UnexpectedContentType(content_type)) if content_type == "text/html"
+        );
+    }
+
+    #[test]
+    fn test_check_api_version() {
+        let mut expected = ExpectedApiVersion {
+            version: "2".to_owned(),
+            header: default_api_version_header(),
+            strict: false,
+        };
+
+        // Matching
+        assert_matches!(check_api_version(&expected, Some("2")), Ok(()));
+        // Drifted or missing version is only warned about
+        assert_matches!(check_api_version(&expected, Some("3")), Ok(()));
+        assert_matches!(check_api_version(&expected, None), Ok(()));
+
+        expected.strict = true;
+        assert_matches!(check_api_version(&expected, Some("2")), Ok(()));
+        assert_matches!(
+            check_api_version(&expected, Some("3")),
+            Err(AppError::UnexpectedApiVersion(version)) if version == "3"
+        );
+        assert_matches!(
+            check_api_version(&expected, None),
+            Err(AppError::UnexpectedApiVersion(version)) if version.is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_user_api_version() {
+        use axum::{http::HeaderMap, routing::get, Json, Router};
+
+        let url = spawn_mock_server(Router::new().route(
+            "/users/me",
+            get(|| async {
+                let mut headers = HeaderMap::new();
+                headers.insert("x-api-version", "3".parse().unwrap());
+                (
+                    headers,
+                    Json(serde_json::json!({
+                        "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                        "emails": [],
+                        "phones": [],
+                        "wallets": [],
+                        "verification_cases": []
+                    })),
+                )
+            }),
+        ));
+        let gen_client = |strict| {
+            FractalClient::create(VerificationProviderConfig {
+                request_user_url: format!("{url}/users/me"),
+                client_secret: Some(Secret::from("some_client_secret")),
+                expected_api_version: Some(ExpectedApiVersion {
+                    version: "2".to_owned(),
+                    header: default_api_version_header(),
+                    strict,
+                }),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let token = OAuthToken {
+            access_token: "some_auth_token".to_owned(),
+            refresh_token: "some_refresh_token".to_owned(),
+            expires_at: Utc::now() + Duration::days(1),
+            scopes: None,
+        };
+
+        assert_matches!(gen_client(false).fetch_user(token.clone()).await, Ok(_));
+        assert_matches!(
+            gen_client(true).fetch_user(token).await,
+            Err(AppError::UnexpectedApiVersion(version)) if version == "3"
+        );
+    }
+
+    #[test]
+    fn test_parse_user_body() {
+        assert_matches!(parse_user_body(br#"{"uid": "de223722"}"#), Ok(_));
+        assert_matches!(
+            parse_user_body(br#"{"uid": "de22"#),
+            Err(AppError::UpstreamTruncated(_))
+        );
+        assert_matches!(
+            parse_user_body(br#"{"uid": ]"#),
+            Err(AppError::ParseError(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_user_truncated_response() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            // Connection is closed before the declared content length is sent
+            socket
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 1000\r\n\r\n{\"uid\": \"de22",
+                )
+                .await
+                .unwrap();
+        });
+
+        let client = FractalClient::create(VerificationProviderConfig {
+            request_user_url: format!("http://{addr}/users/me"),
+            client_secret: Some(Secret::from("some_client_secret")),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_matches!(
+            client
+                .fetch_user(OAuthToken {
+                    access_token: "some_auth_token".to_owned(),
+                    refresh_token: "some_refresh_token".to_owned(),
+                    expires_at: Utc::now() + Duration::days(1),
+                    scopes: None,
+                })
+                .await,
+            Err(AppError::UpstreamTruncated(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_user_failed_status() {
+        use axum::{http::StatusCode, routing::get, Json, Router};
+
+        let error_body = || Json(serde_json::json!({ "error": "some_error" }));
+        let url = spawn_mock_server(
+            Router::new()
+                .route(
+                    "/unauthorized",
+                    get(move || async move { (StatusCode::UNAUTHORIZED, error_body()) }),
+                )
+                .route(
+                    "/too_many_requests",
+                    get(move || async move { (StatusCode::TOO_MANY_REQUESTS, error_body()) }),
+                )
+                .route(
+                    "/internal_error",
+                    get(move || async move { (StatusCode::INTERNAL_SERVER_ERROR, error_body()) }),
+                ),
+        );
+        let fetch_user = |path: &str| {
+            let client = FractalClient::create(VerificationProviderConfig {
+                request_user_url: format!("{url}/{path}"),
+                client_secret: Some(Secret::from("some_client_secret")),
+                ..Default::default()
+            })
+            .unwrap();
+            async move {
+                client
+                    .fetch_user(OAuthToken {
+                        access_token: "some_auth_token".to_owned(),
+                        refresh_token: "some_refresh_token".to_owned(),
+                        expires_at: Utc::now() + Duration::days(1),
+                        scopes: None,
+                    })
+                    .await
+            }
+        };
+
+        assert_matches!(
+            fetch_user("unauthorized").await,
+            Err(AppError::AuthorizationExpired)
+        );
+        assert_matches!(
+            fetch_user("too_many_requests").await,
+            Err(AppError::ReqwestError(e)) if e.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS)
+        );
+        assert_matches!(
+            fetch_user("internal_error").await,
+            Err(AppError::ReqwestError(e)) if e.status() == Some(reqwest::StatusCode::INTERNAL_SERVER_ERROR)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_user_response_too_large() {
+        use axum::{extract::Query, routing::get, Json, Router};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let url = spawn_mock_server(Router::new().route(
+            "/users/me",
+            get(|| async {
+                Json(serde_json::json!({
+                    "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                    "emails": [],
+                    "phones": [],
+                    "wallets": [],
+                    "verification_cases": [],
+                    "padding": "x".repeat(2048),
+                }))
+            }),
+        ));
+        let gen_client = |url: String, max_user_response_size| {
+            FractalClient::create(VerificationProviderConfig {
+                request_user_url: url,
+                client_secret: Some(Secret::from("some_client_secret")),
+                max_user_response_size,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let token = OAuthToken {
+            access_token: "some_auth_token".to_owned(),
+            refresh_token: "some_refresh_token".to_owned(),
+            expires_at: Utc::now() + Duration::days(1),
+            scopes: None,
+        };
+
+        assert_matches!(
+            gen_client(format!("{url}/users/me"), Some(1024))
+                .fetch_user(token.clone())
+                .await,
+            Err(AppError::UpstreamResponseTooLarge(1024))
+        );
+        assert_matches!(
+            gen_client(format!("{url}/users/me"), None)
+                .fetch_user(token.clone())
+                .await,
+            Ok(_)
+        );
+
+        // Pages fitting the limit one by one are limited in total
+        let url = spawn_mock_server(Router::new().route(
+            "/users/me",
+            get(|Query(query): Query<HashMap<String, String>>| async move {
+                let mut page = serde_json::json!({
+                    "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                    "emails": [],
+                    "phones": [],
+                    "wallets": [],
+                    "verification_cases": [],
+                    "padding": "x".repeat(600),
+                });
+                if query.get("cursor").is_none() {
+                    page["next_cursor"] = "some_cursor".into();
+                }
+                Json(page)
+            }),
+        ));
+        assert_matches!(
+            gen_client(format!("{url}/users/me"), Some(1024))
+                .fetch_user(token.clone())
+                .await,
+            Err(AppError::UpstreamResponseTooLarge(1024))
+        );
+        assert_matches!(
+            gen_client(format!("{url}/users/me"), Some(2048))
+                .fetch_user(token.clone())
+                .await,
+            Ok(_)
+        );
+
+        // Body without declared length is cut off while being read
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            socket
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let chunk = format!("400\r\n{}\r\n", " ".repeat(0x400));
+            for _ in 0..4 {
+                if socket.write_all(chunk.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        assert_matches!(
+            gen_client(format!("http://{addr}/users/me"), Some(2048))
+                .fetch_user(token)
+                .await,
+            Err(AppError::UpstreamResponseTooLarge(2048))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_empty_refresh_token() {
+        let gen_token = |refresh_token: &str| OAuthToken {
+            access_token: "some_auth_token".to_owned(),
+            refresh_token: refresh_token.to_owned(),
+            expires_at: Utc::now() + Duration::days(1),
+            scopes: None,
+        };
+        let gen_client = |reject_empty_refresh_token| {
+            FractalClient::create(VerificationProviderConfig {
+                client_secret: Some(Secret::from("some_client_secret")),
+                reject_empty_refresh_token,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+
+        let client = gen_client(true);
+        assert_matches!(
+            client.check_refresh_token(&gen_token("some_refresh_token")),
+            Ok(())
+        );
+        assert_matches!(
+            client.check_refresh_token(&gen_token("")),
+            Err(AppError::MalformedRequest(_))
+        );
+        assert_matches!(
+            client
+                .acquire_user_token(FractalTokenKind::OAuth {
+                    token: gen_token(""),
+                    redirect_uri: "https://some_url".to_owned(),
+                })
+                .await,
+            Err(AppError::MalformedRequest(_))
+        );
+
+        let client = gen_client(false);
+        assert_matches!(client.check_refresh_token(&gen_token("")), Ok(()));
+        assert_matches!(
+            client.check_refresh_token(&gen_token("some_refresh_token")),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_parse_token_response_oauth_error() {
+        assert_matches!(
+            parse_token_response(
+                r#"{"error":"invalid_grant","error_description":"The provided authorization grant is invalid, expired or revoked"}"#
+            ),
+            Err(AppError::AuthorizationExpired)
+        );
+        assert_matches!(
+            parse_token_response(
+                r#"{"error":"invalid_client","error_description":"Client authentication failed"}"#
+            ),
+            Err(AppError::Generic(msg)) if msg == "OAuth error `invalid_client`: Client authentication failed"
+        );
+        assert_matches!(
+            parse_token_response(r#"{"error":"server_error"}"#),
+            Err(AppError::Generic(msg)) if msg == "OAuth error `server_error`: no description"
+        );
+        assert_matches!(
+            parse_token_response(&format!(
+                r#"{{"access_token":"some_token","refresh_token":"some_refresh_token","token_type":"Bearer","expires_in":7200,"created_at":{}}}"#,
+                Utc::now().timestamp()
+            )),
+            Ok(OAuthToken { access_token, .. }) if access_token == "some_token"
+        );
+        assert_matches!(
+            parse_token_response("not a json"),
+            Err(AppError::Generic(_))
+        );
+    }
 
     #[test]
     fn test_oauth_token() {
@@ -450,36 +2186,294 @@ mod tests {
         }}"#
         );
 
-        let raw_token = serde_json::from_str::<RawFractalToken>(&json).unwrap();
-        let mut oauth_token = OAuthToken::from(raw_token);
+        let raw_token = serde_json::from_str::<RawFractalToken>(&json).unwrap();
+        let mut oauth_token = OAuthToken::from(raw_token);
+
+        assert_eq!(
+            oauth_token,
+            OAuthToken {
+                access_token: "7rgojfemuk-aq8RcA7xWxJQKv6Ux0VWJ1DQtU6178B8".to_owned(),
+                refresh_token: "thPSSHGnk3NGU5vV4V_g-Qrs47RibO9KEEhfKYEgJOw".to_owned(),
+                expires_at: Utc.timestamp_opt(now_secs, 0).unwrap(),
+                scopes: Some(vec!["uid:read".to_owned(), "email:read".to_owned()]),
+            }
+        );
+
+        assert!(oauth_token.requires_refresh(Duration::minutes(5)));
+        oauth_token.expires_at = Utc::now() + Duration::days(1);
+        assert!(!oauth_token.requires_refresh(Duration::minutes(5)));
+        assert!(oauth_token.requires_refresh(Duration::days(2)));
+    }
+
+    #[test]
+    fn test_oauth_token_serde() {
+        let token = FractalTokenKind::OAuth {
+            redirect_uri: "https://some_url".to_owned(),
+            token: OAuthToken {
+                access_token: "some_auth_token".to_owned(),
+                refresh_token: "some_refresh_token".to_owned(),
+                expires_at: Utc::now(),
+                scopes: Some(vec!["uid:read".to_owned()]),
+            },
+        };
+        let json = serde_json::to_string(&token).unwrap();
+        let deserialized = serde_json::from_str::<FractalTokenKind>(&json).unwrap();
+        assert_eq!(deserialized, token);
+    }
+
+    #[test]
+    fn test_raw_oauth_token_serde() {
+        let json = r#"{
+            "redirect_uri": "https://some_url",
+            "token": {
+                "access_token": "some_auth_token",
+                "refresh_token": "some_refresh_token",
+                "token_type": "Bearer",
+                "expires_in": 7200,
+                "created_at": 1700000000,
+                "scope": "uid:read email:read"
+            }
+        }"#;
+
+        let deserialized = serde_json::from_str::<FractalTokenKind>(json).unwrap();
+        let raw_token = match &deserialized {
+            FractalTokenKind::RawOAuth { token, .. } => token,
+            other => panic!("Unexpected token kind {other:?}"),
+        };
+        assert_eq!(
+            raw_token,
+            &RawFractalToken {
+                access_token: "some_auth_token".to_owned(),
+                refresh_token: "some_refresh_token".to_owned(),
+                token_type: "Bearer".to_owned(),
+                lifetime: TokenLifetime {
+                    expires_in: 7200,
+                    created_at: 1_700_000_000,
+                },
+                scope: Some("uid:read email:read".to_owned()),
+            }
+        );
+
+        let json = serde_json::to_string(&deserialized).unwrap();
+        assert_eq!(
+            serde_json::from_str::<FractalTokenKind>(&json).unwrap(),
+            deserialized
+        );
+
+        // Encoded token is still deserialized as such
+        let encoded = FractalTokenKind::OAuth {
+            redirect_uri: "https://some_url".to_owned(),
+            token: OAuthToken::from(RawFractalToken {
+                access_token: "some_auth_token".to_owned(),
+                refresh_token: "some_refresh_token".to_owned(),
+                token_type: "Bearer".to_owned(),
+                lifetime: TokenLifetime {
+                    expires_in: 7200,
+                    created_at: 1_700_000_000,
+                },
+                scope: None,
+            }),
+        };
+        let json = serde_json::to_string(&encoded).unwrap();
+        assert_matches!(
+            serde_json::from_str::<FractalTokenKind>(&json),
+            Ok(FractalTokenKind::OAuth { .. })
+        );
+    }
+
+    #[test]
+    fn test_select_redirect_uri() {
+        let client = FractalClient::create(VerificationProviderConfig {
+            client_secret: Some(Secret::from("some_secret")),
+            ..Default::default()
+        })
+        .unwrap();
+        // Client provided redirect URI is used as is without configured ones
+        assert_matches!(
+            client.select_redirect_uri("https://client_url", None),
+            Ok("https://client_url")
+        );
+        assert_matches!(
+            client.select_redirect_uri("https://client_url", Some("app".to_owned())),
+            Err(AppError::MalformedRequest(_))
+        );
+
+        let client = FractalClient::create(VerificationProviderConfig {
+            client_secret: Some(Secret::from("some_secret")),
+            redirect_uris: HashMap::from([
+                ("app".to_owned(), "https://app_url".to_owned()),
+                ("wallet".to_owned(), "https://wallet_url".to_owned()),
+            ]),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_matches!(
+            client.select_redirect_uri("https://client_url", Some("wallet".to_owned())),
+            Ok("https://wallet_url")
+        );
+        assert_matches!(
+            client.select_redirect_uri("https://client_url", Some("unknown".to_owned())),
+            Err(AppError::MalformedRequest(_))
+        );
+        assert_matches!(
+            client.select_redirect_uri("https://client_url", None),
+            Err(AppError::MalformedRequest(_))
+        );
+
+        // Hint could be sent instead of the redirect URI
+        let token = serde_json::from_str::<FractalTokenKind>(
+            r#"{ "code": "some_code", "captcha": "some_captcha", "redirect_hint": "app" }"#,
+        )
+        .unwrap();
+        assert_matches!(
+            token,
+            FractalTokenKind::AuthorizationCode { redirect_uri, redirect_hint: Some(hint), .. }
+                if redirect_uri.is_empty() && hint == "app"
+        );
+    }
+
+    #[test]
+    fn test_convert_raw_token() {
+        let gen_raw_token = |token_type: &str| RawFractalToken {
+            access_token: "some_auth_token".to_owned(),
+            refresh_token: "some_refresh_token".to_owned(),
+            token_type: token_type.to_owned(),
+            lifetime: TokenLifetime {
+                expires_in: 7200,
+                created_at: 1_700_000_000,
+            },
+            scope: Some("uid:read".to_owned()),
+        };
+
+        let client = FractalClient::create(VerificationProviderConfig {
+            client_secret: Some(Secret::from("some_secret")),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_matches!(
+            client.convert_raw_token(gen_raw_token("Bearer")),
+            Err(AppError::MalformedRequest(_))
+        );
+
+        let client = FractalClient::create(VerificationProviderConfig {
+            client_secret: Some(Secret::from("some_secret")),
+            accept_raw_tokens: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            client.convert_raw_token(gen_raw_token("Bearer")).unwrap(),
+            OAuthToken {
+                access_token: "some_auth_token".to_owned(),
+                refresh_token: "some_refresh_token".to_owned(),
+                expires_at: Utc.timestamp_opt(1_700_007_200, 0).unwrap(),
+                scopes: None,
+            }
+        );
+        assert_matches!(
+            client.convert_raw_token(gen_raw_token("mac")),
+            Err(AppError::MalformedRequest(_))
+        );
+    }
+
+    #[test]
+    fn test_oauth_token_without_scopes_borsh_serde() {
+        let expires_at = Utc.timestamp_opt(Utc::now().timestamp(), 0).unwrap();
+
+        // Token serialized before scopes tracking
+        let mut legacy = vec![];
+        BorshSerialize::serialize(&"some_auth_token".to_owned(), &mut legacy).unwrap();
+        BorshSerialize::serialize(&"some_refresh_token".to_owned(), &mut legacy).unwrap();
+        BorshSerialize::serialize(&expires_at.timestamp_nanos(), &mut legacy).unwrap();
 
+        let token = OAuthToken::try_from_slice(&legacy).unwrap();
         assert_eq!(
-            oauth_token,
+            token,
             OAuthToken {
-                access_token: "7rgojfemuk-aq8RcA7xWxJQKv6Ux0VWJ1DQtU6178B8".to_owned(),
-                refresh_token: "thPSSHGnk3NGU5vV4V_g-Qrs47RibO9KEEhfKYEgJOw".to_owned(),
-                expires_at: Utc.timestamp_opt(now_secs, 0).unwrap(),
+                access_token: "some_auth_token".to_owned(),
+                refresh_token: "some_refresh_token".to_owned(),
+                expires_at,
+                scopes: None,
             }
         );
-
-        assert!(oauth_token.requires_refresh());
-        oauth_token.expires_at = Utc::now() + Duration::days(1);
-        assert!(!oauth_token.requires_refresh());
+        assert_eq!(token.try_to_vec().unwrap(), legacy);
     }
 
     #[test]
-    fn test_oauth_token_serde() {
-        let token = FractalTokenKind::OAuth {
-            redirect_uri: "https://some_url".to_owned(),
-            token: OAuthToken {
+    fn test_oauth_token_has_scopes() {
+        struct TestCase {
+            name: &'static str,
+            scopes: Option<Vec<&'static str>>,
+            required: Vec<&'static str>,
+            expected: bool,
+        }
+
+        let uniqueness_tier = vec!["uid:read"];
+        let kyc_tier = vec!["uid:read", "verification.basic:read"];
+
+        let test_cases = [
+            TestCase {
+                name: "Uniqueness tier, sufficient scopes",
+                scopes: Some(vec!["uid:read", "email:read"]),
+                required: uniqueness_tier.clone(),
+                expected: true,
+            },
+            TestCase {
+                name: "Uniqueness tier, insufficient scopes",
+                scopes: Some(vec!["email:read"]),
+                required: uniqueness_tier.clone(),
+                expected: false,
+            },
+            TestCase {
+                name: "KYC tier, sufficient scopes",
+                scopes: Some(vec!["verification.basic:read", "uid:read"]),
+                required: kyc_tier.clone(),
+                expected: true,
+            },
+            TestCase {
+                name: "KYC tier, insufficient scopes",
+                scopes: Some(vec!["uid:read"]),
+                required: kyc_tier.clone(),
+                expected: false,
+            },
+            TestCase {
+                name: "KYC tier, unknown scopes",
+                scopes: None,
+                required: kyc_tier,
+                expected: false,
+            },
+            TestCase {
+                name: "No requirements, unknown scopes",
+                scopes: None,
+                required: vec![],
+                expected: true,
+            },
+        ];
+
+        for TestCase {
+            name,
+            scopes,
+            required,
+            expected,
+        } in test_cases
+        {
+            let token = OAuthToken {
                 access_token: "some_auth_token".to_owned(),
                 refresh_token: "some_refresh_token".to_owned(),
                 expires_at: Utc::now(),
-            },
-        };
-        let json = serde_json::to_string(&token).unwrap();
-        let deserialized = serde_json::from_str::<FractalTokenKind>(&json).unwrap();
-        assert_eq!(deserialized, token);
+                scopes: scopes.map(|scopes| scopes.into_iter().map(ToOwned::to_owned).collect()),
+            };
+            let required = required
+                .into_iter()
+                .map(ToOwned::to_owned)
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                token.has_scopes(&required),
+                expected,
+                "Test case `{name}` failed"
+            );
+        }
     }
 
     #[test]
@@ -757,6 +2751,594 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_user_get_kyc_status_expired() {
+        let now = Utc::now();
+        let validity_period = Some(Duration::days(365).num_seconds() as u64);
+
+        let gen_kyc_user = |updated_at| {
+            gen_user(vec![gen_verification_case(
+                updated_at - Duration::days(1),
+                updated_at,
+                VerificationLevelState::Kyc(CaseStatus::Done, CredentialStatus::Approved),
+                true,
+            )])
+        };
+
+        // recently approved case
+        let mut user = gen_kyc_user(now - Duration::days(30));
+        assert_eq!(
+            user.get_kyc_status(validity_period),
+            VerificationStatus::Approved
+        );
+
+        // long ago approved case
+        let mut user = gen_kyc_user(now - Duration::days(400));
+        assert_eq!(
+            user.get_kyc_status(validity_period),
+            VerificationStatus::Expired
+        );
+        assert_eq!(user.get_kyc_status(None), VerificationStatus::Approved);
+
+        // long ago rejected case isn't affected
+        let mut user = gen_user(vec![gen_verification_case(
+            now - Duration::days(401),
+            now - Duration::days(400),
+            VerificationLevelState::Kyc(CaseStatus::Done, CredentialStatus::Rejected),
+            true,
+        )]);
+        assert_eq!(
+            user.get_kyc_status(validity_period),
+            VerificationStatus::Rejected
+        );
+    }
+
+    #[test]
+    fn test_user_get_strict_kyc_status() {
+        let now = Utc::now();
+        let gen_kyc_case = |updated_at, liveness| {
+            gen_verification_case(
+                updated_at - Duration::days(1),
+                updated_at,
+                VerificationLevelState::Kyc(CaseStatus::Done, CredentialStatus::Approved),
+                liveness,
+            )
+        };
+
+        // More recent approved case lacks liveness
+        let gen_conflicting_user = || {
+            gen_user(vec![
+                gen_kyc_case(now - Duration::days(30), true),
+                gen_kyc_case(now - Duration::days(1), false),
+            ])
+        };
+        assert_eq!(
+            gen_conflicting_user().get_kyc_status(None),
+            VerificationStatus::Approved
+        );
+        assert_eq!(
+            gen_conflicting_user().get_strict_kyc_status(None),
+            VerificationStatus::Pending
+        );
+
+        // Older approved case lacks liveness
+        let gen_live_user = || {
+            gen_user(vec![
+                gen_kyc_case(now - Duration::days(30), false),
+                gen_kyc_case(now - Duration::days(1), true),
+            ])
+        };
+        assert_eq!(
+            gen_live_user().get_kyc_status(None),
+            VerificationStatus::Approved
+        );
+        assert_eq!(
+            gen_live_user().get_strict_kyc_status(None),
+            VerificationStatus::Approved
+        );
+
+        // Not approved statuses aren't affected
+        let mut user = gen_user(vec![gen_verification_case(
+            now - Duration::days(2),
+            now - Duration::days(1),
+            VerificationLevelState::Kyc(CaseStatus::Done, CredentialStatus::Rejected),
+            true,
+        )]);
+        assert_eq!(
+            user.get_strict_kyc_status(None),
+            VerificationStatus::Rejected
+        );
+    }
+
+    #[test]
+    fn test_user_keep_recent_cases() {
+        let now = Utc::now();
+        let gen_user_with_cases = || {
+            gen_user(vec![
+                // oldest approved case
+                gen_verification_case(
+                    now - Duration::days(10),
+                    now - Duration::days(9),
+                    VerificationLevelState::Uniqueness(
+                        CaseStatus::Done,
+                        CredentialStatus::Approved,
+                    ),
+                    true,
+                ),
+                gen_verification_case(
+                    now - Duration::days(2),
+                    now - Duration::days(1),
+                    VerificationLevelState::Uniqueness(
+                        CaseStatus::Pending,
+                        CredentialStatus::Pending,
+                    ),
+                    true,
+                ),
+                gen_verification_case(
+                    now - Duration::days(4),
+                    now - Duration::days(3),
+                    VerificationLevelState::Uniqueness(
+                        CaseStatus::Done,
+                        CredentialStatus::Rejected,
+                    ),
+                    true,
+                ),
+            ])
+        };
+
+        let mut user = gen_user_with_cases();
+        assert_eq!(
+            user.get_status(&[VerificationLevel::Uniqueness]),
+            VerificationStatus::Approved
+        );
+
+        // Approved case is beyond the cap
+        let mut user = gen_user_with_cases();
+        user.keep_recent_cases(2);
+        assert_eq!(user.verification_cases.len(), 2);
+        assert_eq!(
+            user.get_status(&[VerificationLevel::Uniqueness]),
+            VerificationStatus::Pending
+        );
+
+        let mut user = gen_user_with_cases();
+        user.keep_recent_cases(3);
+        assert_eq!(
+            user.get_status(&[VerificationLevel::Uniqueness]),
+            VerificationStatus::Approved
+        );
+    }
+
+    #[test]
+    fn test_user_remove_ignored_cases() {
+        let now = Utc::now();
+        let approved_case = gen_verification_case(
+            now - Duration::days(2),
+            now - Duration::days(1),
+            VerificationLevelState::Uniqueness(CaseStatus::Done, CredentialStatus::Approved),
+            true,
+        );
+        let kyc_case = gen_verification_case(
+            now - Duration::days(2),
+            now - Duration::days(1),
+            VerificationLevelState::Kyc(CaseStatus::Done, CredentialStatus::Approved),
+            true,
+        );
+        let ignored_case_ids = HashSet::from([approved_case.id.clone(), kyc_case.id.clone()]);
+
+        let mut user = gen_user(vec![approved_case, kyc_case]);
+        user.remove_ignored_cases(&HashSet::new());
+        assert_eq!(
+            user.get_status(&[VerificationLevel::Uniqueness]),
+            VerificationStatus::Approved
+        );
+        assert_eq!(user.get_kyc_status(None), VerificationStatus::Approved);
+
+        user.remove_ignored_cases(&ignored_case_ids);
+        assert_eq!(
+            user.get_status(&[VerificationLevel::Uniqueness]),
+            VerificationStatus::Unavailable
+        );
+        assert_eq!(user.get_kyc_status(None), VerificationStatus::Unavailable);
+    }
+
+    #[test]
+    fn test_next_page_link() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(next_page_link(&headers), None);
+
+        headers.insert(
+            reqwest::header::LINK,
+            "<https://host/users/me?page=1>; rel=\"prev\", </users/me?page=3>; rel=\"next\""
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            next_page_link(&headers),
+            Some("/users/me?page=3".to_owned())
+        );
+
+        headers.insert(
+            reqwest::header::LINK,
+            "<https://host/users/me?page=1>; rel=\"prev\""
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(next_page_link(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_user_pages() {
+        use axum::{extract::Query, routing::get, Json, Router};
+
+        let gen_page = |case: serde_json::Value| {
+            serde_json::json!({
+                "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                "emails": [],
+                "phones": [],
+                "wallets": [],
+                "verification_cases": [case]
+            })
+        };
+        let pending_case = serde_json::json!({
+            "id": "37c01d4e-fe22-11ed-be56-0242ac120002",
+            "created_at": "2023-05-20 22:08:09 UTC",
+            "updated_at": "2023-05-20 22:09:23 UTC",
+            "level": "uniqueness",
+            "status": "pending",
+            "credential": "pending",
+            "details": { "liveness": true }
+        });
+        let approved_case = serde_json::json!({
+            "id": "4a8c7d2e-fe22-11ed-be56-0242ac120002",
+            "created_at": "2023-05-19 22:08:09 UTC",
+            "updated_at": "2023-05-19 22:09:23 UTC",
+            "level": "uniqueness",
+            "status": "done",
+            "credential": "approved",
+            "details": { "liveness": true }
+        });
+
+        let url = spawn_mock_server(
+            Router::new()
+                .route(
+                    "/users/me",
+                    get({
+                        let (first, second) = (
+                            gen_page(pending_case.clone()),
+                            gen_page(approved_case.clone()),
+                        );
+                        move |Query(query): Query<HashMap<String, String>>| async move {
+                            match query.get("page").map(String::as_str) {
+                                Some("2") => ([(reqwest::header::LINK.as_str(), "")], Json(second)),
+                                _ => (
+                                    [(
+                                        reqwest::header::LINK.as_str(),
+                                        "</users/me?page=2>; rel=\"next\"",
+                                    )],
+                                    Json(first),
+                                ),
+                            }
+                        }
+                    }),
+                )
+                .route(
+                    "/users/foreign",
+                    get({
+                        let first = gen_page(pending_case.clone());
+                        move || async move {
+                            (
+                                [(
+                                    reqwest::header::LINK.as_str(),
+                                    "<https://other.example/users/me?page=2>; rel=\"next\"",
+                                )],
+                                Json(first),
+                            )
+                        }
+                    }),
+                )
+                .route(
+                    "/users/cursor",
+                    get({
+                        let mut first = gen_page(pending_case);
+                        first["next_cursor"] = "some_cursor".into();
+                        let second = gen_page(approved_case);
+                        move |Query(query): Query<HashMap<String, String>>| async move {
+                            match query.get("cursor").map(String::as_str) {
+                                Some("some_cursor") => Json(second),
+                                _ => Json(first),
+                            }
+                        }
+                    }),
+                ),
+        );
+        let gen_client = |url: String, max_verification_cases| {
+            FractalClient::create(VerificationProviderConfig {
+                request_user_url: url,
+                client_secret: Some(Secret::from("some_client_secret")),
+                max_verification_cases,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let token = OAuthToken {
+            access_token: "some_auth_token".to_owned(),
+            refresh_token: "some_refresh_token".to_owned(),
+            expires_at: Utc::now() + Duration::days(1),
+            scopes: None,
+        };
+
+        // Approved case on the second page is considered
+        for path in ["/users/me", "/users/cursor"] {
+            assert_matches!(
+                gen_client(format!("{url}{path}"), None)
+                    .fetch_user(token.clone())
+                    .await,
+                Ok(FractalUser {
+                    fv_status: VerificationStatus::Approved,
+                    ..
+                })
+            );
+        }
+
+        // Next pages of other origins aren't followed with the bearer token
+        assert_matches!(
+            gen_client(format!("{url}/users/foreign"), None)
+                .fetch_user(token.clone())
+                .await,
+            Err(AppError::Generic(_))
+        );
+
+        // Further pages aren't fetched once the cases cap is reached
+        assert_matches!(
+            gen_client(format!("{url}/users/me"), Some(1))
+                .fetch_user(token)
+                .await,
+            Ok(FractalUser {
+                fv_status: VerificationStatus::Pending,
+                ..
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_user_ignored_cases() {
+        use axum::{routing::get, Json, Router};
+
+        let url = spawn_mock_server(Router::new().route(
+            "/users/me",
+            get(|| async {
+                Json(serde_json::json!({
+                    "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                    "emails": [],
+                    "phones": [],
+                    "wallets": [],
+                    "verification_cases": [{
+                        "id": "37c01d4e-fe22-11ed-be56-0242ac120002",
+                        "created_at": "2023-05-19 22:08:09 UTC",
+                        "updated_at": "2023-05-19 22:09:23 UTC",
+                        "level": "uniqueness",
+                        "status": "done",
+                        "credential": "approved",
+                        "details": { "liveness": true }
+                    }]
+                }))
+            }),
+        ));
+        let client = FractalClient::create(VerificationProviderConfig {
+            request_user_url: format!("{url}/users/me"),
+            client_secret: Some(Secret::from("some_client_secret")),
+            ..Default::default()
+        })
+        .unwrap();
+        let token = OAuthToken {
+            access_token: "some_auth_token".to_owned(),
+            refresh_token: "some_refresh_token".to_owned(),
+            expires_at: Utc::now() + Duration::days(1),
+            scopes: None,
+        };
+
+        assert_matches!(
+            client.fetch_user(token.clone()).await,
+            Ok(FractalUser {
+                fv_status: VerificationStatus::Approved,
+                ..
+            })
+        );
+
+        // Takes effect without recreating the client, e.g. on configuration reload
+        client.set_ignored_case_ids(HashSet::from([
+            "37c01d4e-fe22-11ed-be56-0242ac120002".to_owned()
+        ]));
+        assert_matches!(
+            client.fetch_user(token).await,
+            Ok(FractalUser {
+                fv_status: VerificationStatus::Unavailable,
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn test_user_hold_unaccepted_uniqueness() {
+        let now = Utc::now();
+        let gen_contacted_user = || {
+            gen_user(vec![
+                gen_verification_case(
+                    now - Duration::days(2),
+                    now - Duration::days(1),
+                    VerificationLevelState::Uniqueness(
+                        CaseStatus::Contacted,
+                        CredentialStatus::Approved,
+                    ),
+                    true,
+                ),
+                gen_verification_case(
+                    now - Duration::days(2),
+                    now - Duration::days(1),
+                    VerificationLevelState::Kyc(CaseStatus::Contacted, CredentialStatus::Approved),
+                    true,
+                ),
+            ])
+        };
+
+        // Default accepts any status
+        let mut user = gen_contacted_user();
+        user.hold_unaccepted_uniqueness(&UniquenessCaseStatuses::default().0);
+        assert_eq!(
+            user.get_status(&[VerificationLevel::Uniqueness]),
+            VerificationStatus::Approved
+        );
+
+        // Stricter setting accepts `done` only
+        let mut user = gen_contacted_user();
+        user.hold_unaccepted_uniqueness(&[CaseStatus::Done]);
+        assert_eq!(
+            user.get_status(&[VerificationLevel::Uniqueness]),
+            VerificationStatus::Pending
+        );
+        // KYC cases aren't affected
+        assert_eq!(
+            user.get_status(&[VerificationLevel::Basic, VerificationLevel::Liveness]),
+            VerificationStatus::Approved
+        );
+
+        let mut user = gen_contacted_user();
+        user.hold_unaccepted_uniqueness(&[CaseStatus::Done, CaseStatus::Contacted]);
+        assert_eq!(
+            user.get_status(&[VerificationLevel::Uniqueness]),
+            VerificationStatus::Approved
+        );
+    }
+
+    #[test]
+    fn test_user_get_approved_uniqueness_case() {
+        let now = Utc::now();
+
+        let mut user = gen_user(vec![
+            // older approved case
+            gen_verification_case(
+                now - Duration::days(10),
+                now - Duration::days(5),
+                VerificationLevelState::Uniqueness(CaseStatus::Done, CredentialStatus::Approved),
+                true,
+            ),
+            // most recent approved case
+            gen_verification_case(
+                now - Duration::days(4),
+                now - Duration::days(2),
+                VerificationLevelState::Uniqueness(CaseStatus::Done, CredentialStatus::Approved),
+                true,
+            ),
+            // newest, but still pending case
+            gen_verification_case(
+                now - Duration::days(1),
+                now,
+                VerificationLevelState::Uniqueness(CaseStatus::Pending, CredentialStatus::Pending),
+                true,
+            ),
+            // approved KYC case isn't related to uniqueness
+            gen_verification_case(
+                now - Duration::days(1),
+                now - Duration::days(1),
+                VerificationLevelState::Kyc(CaseStatus::Done, CredentialStatus::Approved),
+                true,
+            ),
+        ]);
+
+        assert_matches!(
+            user.get_approved_case(&[VerificationLevel::Uniqueness]),
+            Some(VerificationCase { updated_at, .. }) if *updated_at == Some(now - Duration::days(2))
+        );
+
+        let mut user = gen_user(vec![
+            // approved case without liveness
+            gen_verification_case(
+                now - Duration::days(2),
+                now - Duration::days(1),
+                VerificationLevelState::Uniqueness(CaseStatus::Done, CredentialStatus::Approved),
+                false,
+            ),
+            // rejected case
+            gen_verification_case(
+                now - Duration::days(1),
+                now,
+                VerificationLevelState::Uniqueness(CaseStatus::Done, CredentialStatus::Rejected),
+                true,
+            ),
+        ]);
+
+        assert_matches!(
+            user.get_approved_case(&[VerificationLevel::Uniqueness]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_user_get_pending_steps() {
+        let now = Utc::now();
+
+        let mut user = gen_user(vec![
+            // older pending case
+            gen_verification_case(
+                now - Duration::days(4),
+                now - Duration::days(3),
+                VerificationLevelState::Uniqueness(CaseStatus::Done, CredentialStatus::Pending),
+                true,
+            ),
+            // most recent pending case without liveness
+            gen_verification_case(
+                now - Duration::days(2),
+                now - Duration::days(1),
+                VerificationLevelState::Uniqueness(CaseStatus::Pending, CredentialStatus::Pending),
+                false,
+            ),
+            // pending KYC case isn't related to uniqueness
+            gen_verification_case(
+                now - Duration::days(1),
+                now,
+                VerificationLevelState::Kyc(CaseStatus::Contacted, CredentialStatus::Pending),
+                true,
+            ),
+        ]);
+
+        assert_eq!(
+            user.get_pending_steps(&[VerificationLevel::Uniqueness]),
+            vec![
+                PendingStep::Journey,
+                PendingStep::Liveness,
+                PendingStep::Review
+            ]
+        );
+        assert_eq!(
+            user.get_pending_steps(&[VerificationLevel::Basic, VerificationLevel::Liveness]),
+            vec![PendingStep::Contact, PendingStep::Review]
+        );
+
+        let mut user = gen_user(vec![gen_verification_case(
+            now - Duration::days(2),
+            now - Duration::days(1),
+            VerificationLevelState::Uniqueness(CaseStatus::Done, CredentialStatus::Pending),
+            true,
+        )]);
+
+        assert_eq!(
+            user.get_pending_steps(&[VerificationLevel::Uniqueness]),
+            vec![PendingStep::Review]
+        );
+
+        let mut user = gen_user(vec![gen_verification_case(
+            now - Duration::days(2),
+            now - Duration::days(1),
+            VerificationLevelState::Uniqueness(CaseStatus::Done, CredentialStatus::Approved),
+            true,
+        )]);
+
+        assert!(user
+            .get_pending_steps(&[VerificationLevel::Uniqueness])
+            .is_empty());
+    }
+
     #[test]
     fn test_parse_user() {
         let user_json = r#"{
@@ -833,7 +3415,7 @@ mod tests {
                 id: id0,
                 credential: CredentialStatus::Approved,
                 status: CaseStatus::Done,
-                level: levels0,
+                level: CaseLevel { raw: raw_level0, levels: levels0 },
                 created_at: created_at0,
                 updated_at: updated_at0,
                 ..
@@ -842,14 +3424,210 @@ mod tests {
                 id: id1,
                 credential: CredentialStatus::Approved,
                 status: CaseStatus::Done,
-                level: levels1,
+                level: CaseLevel { levels: levels1, .. },
                 ..
             },
-        ] if id0.as_str() == "07f10ea2-fe22-11ed-be56-0242ac120002" && levels0.as_slice() == [VerificationLevel::Basic, VerificationLevel::Liveness] && 
-             created_at0.to_string().as_str() == "2023-05-19 21:57:42 UTC" && updated_at0.to_string().as_str() == "2023-05-24 19:59:19 UTC" &&
+        ] if id0.as_str() == "07f10ea2-fe22-11ed-be56-0242ac120002" && raw_level0.as_str() == "basic+liveness" && levels0.as_slice() == [VerificationLevel::Basic, VerificationLevel::Liveness] && 
+             created_at0.unwrap().to_string().as_str() == "2023-05-19 21:57:42 UTC" && updated_at0.unwrap().to_string().as_str() == "2023-05-24 19:59:19 UTC" &&
              id1.as_str() == "37c01d4e-fe22-11ed-be56-0242ac120002" && levels1.as_slice() == [VerificationLevel::Uniqueness]);
     }
 
+    #[test]
+    fn test_user_get_earliest_case_created_at() {
+        let now = Utc::now();
+
+        let user = gen_user(vec![
+            gen_verification_case(
+                now - Duration::days(5),
+                now - Duration::days(1),
+                VerificationLevelState::Kyc(CaseStatus::Done, CredentialStatus::Approved),
+                true,
+            ),
+            gen_verification_case(
+                now - Duration::days(30),
+                now - Duration::days(29),
+                VerificationLevelState::Uniqueness(CaseStatus::Done, CredentialStatus::Rejected),
+                true,
+            ),
+            // unparsed date is ignored
+            VerificationCase {
+                created_at: None,
+                ..gen_verification_case(
+                    now - Duration::days(60),
+                    now,
+                    VerificationLevelState::Uniqueness(
+                        CaseStatus::Done,
+                        CredentialStatus::Approved,
+                    ),
+                    true,
+                )
+            },
+        ]);
+        assert_eq!(
+            user.get_earliest_case_created_at(),
+            Some(now - Duration::days(30))
+        );
+
+        assert_eq!(gen_user(vec![]).get_earliest_case_created_at(), None);
+    }
+
+    #[test]
+    fn test_liveness_audit() {
+        let audited = serde_json::from_str::<VerificationDetails>(
+            r#"{
+                "liveness": true,
+                "liveness_audit_best_file": "https://some_url",
+                "liveness_audit_open_eyes_file": "https://some_url",
+                "identification_document_front_file": null
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(audited.liveness_audit.len(), 2);
+        assert!(audited.has_liveness_audit());
+
+        let unaudited = serde_json::from_str::<VerificationDetails>(
+            r#"{
+                "liveness": true,
+                "liveness_audit_best_file": "https://some_url",
+                "liveness_audit_open_eyes_file": null
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            unaudited
+                .liveness_audit
+                .get("liveness_audit_open_eyes_file"),
+            Some(&None)
+        );
+        assert!(!unaudited.has_liveness_audit());
+
+        let no_audit =
+            serde_json::from_str::<VerificationDetails>(r#"{ "liveness": true }"#).unwrap();
+        assert!(!no_audit.has_liveness_audit());
+
+        let gen_case = |details: VerificationDetails| VerificationCase {
+            details,
+            ..gen_verification_case(
+                Utc::now(),
+                Utc::now(),
+                VerificationLevelState::Uniqueness(CaseStatus::Done, CredentialStatus::Approved),
+                true,
+            )
+        };
+
+        let mut user = gen_user(vec![gen_case(audited)]);
+        user.ignore_unaudited_liveness();
+        assert_eq!(
+            user.get_status(&[VerificationLevel::Uniqueness]),
+            VerificationStatus::Approved
+        );
+
+        let mut user = gen_user(vec![gen_case(unaudited)]);
+        assert_eq!(
+            user.get_status(&[VerificationLevel::Uniqueness]),
+            VerificationStatus::Approved
+        );
+        user.ignore_unaudited_liveness();
+        assert_eq!(
+            user.get_status(&[VerificationLevel::Uniqueness]),
+            VerificationStatus::Unavailable
+        );
+    }
+
+    #[test]
+    fn test_parse_user_without_uid() {
+        assert_matches!(
+            parse_user(serde_json::json!({
+                "emails": [],
+                "phones": [],
+                "wallets": [],
+                "verification_cases": []
+            })),
+            Err(AppError::InsufficientScope)
+        );
+
+        assert_matches!(
+            parse_user(serde_json::json!({
+                "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                "emails": [],
+                "phones": [],
+                "wallets": [],
+                "verification_cases": []
+            })),
+            Ok(RawFractalUser { uid, .. }) if uid.as_ref() == "de223722fe2111edbe560242ac120002"
+        );
+
+        // Malformed user with `uid` is still a parse failure
+        assert_matches!(
+            parse_user(serde_json::json!({
+                "uid": "de223722-fe21-11ed-be56-0242ac120002"
+            })),
+            Err(AppError::ParseError(_))
+        );
+    }
+
+    #[test]
+    fn test_parse_user_case_dates() {
+        let gen_user_json = |created_at: &str, updated_at: &str| {
+            format!(
+                r#"{{
+                "emails": [],
+                "phones": [],
+                "wallets": [],
+                "uid": "de223722-fe21-11ed-be56-0242ac120002",
+                "verification_cases": [
+                  {{
+                    "created_at": "{created_at}",
+                    "credential": "approved",
+                    "details": {{ "liveness": true }},
+                    "id": "37c01d4e-fe22-11ed-be56-0242ac120002",
+                    "level": "uniqueness",
+                    "status": "done",
+                    "updated_at": "{updated_at}"
+                  }}
+                ]
+            }}"#
+            )
+        };
+
+        let user = serde_json::from_str::<RawFractalUser>(&gen_user_json(
+            "2023-05-19 22:08:09 UTC",
+            "2023-05-19T22:09:23.000Z",
+        ))
+        .unwrap();
+        assert_eq!(
+            user.verification_cases[0].created_at,
+            Some(Utc.with_ymd_and_hms(2023, 5, 19, 22, 8, 9).unwrap())
+        );
+        assert_eq!(
+            user.verification_cases[0].updated_at,
+            Some(Utc.with_ymd_and_hms(2023, 5, 19, 22, 9, 23).unwrap())
+        );
+        assert!(!user.has_invalid_case_dates());
+
+        let user = serde_json::from_str::<RawFractalUser>(&gen_user_json(
+            "2023-05-19 22:08:09 UTC",
+            "19 May 2023",
+        ))
+        .unwrap();
+        assert_eq!(user.verification_cases[0].updated_at, None);
+        assert!(user.has_invalid_case_dates());
+
+        // Unparsed dates are unknown rather than made up
+        let mut user = user;
+        assert_matches!(
+            user.get_approved_case(&[VerificationLevel::Uniqueness]),
+            Some(VerificationCase {
+                updated_at: None,
+                ..
+            })
+        );
+        assert_eq!(
+            user.get_earliest_case_created_at(),
+            Some(Utc.with_ymd_and_hms(2023, 5, 19, 22, 8, 9).unwrap())
+        );
+    }
+
     enum VerificationLevelState {
         Uniqueness(CaseStatus, CredentialStatus),
         Kyc(CaseStatus, CredentialStatus),
@@ -862,11 +3640,19 @@ mod tests {
         liveness: bool,
     ) -> VerificationCase {
         let (level, status, credential) = match state {
-            VerificationLevelState::Uniqueness(status, credential) => {
-                (vec![VerificationLevel::Uniqueness], status, credential)
-            }
+            VerificationLevelState::Uniqueness(status, credential) => (
+                CaseLevel {
+                    raw: "uniqueness".to_owned(),
+                    levels: vec![VerificationLevel::Uniqueness],
+                },
+                status,
+                credential,
+            ),
             VerificationLevelState::Kyc(status, credential) => (
-                vec![VerificationLevel::Basic, VerificationLevel::Liveness],
+                CaseLevel {
+                    raw: "basic+liveness".to_owned(),
+                    levels: vec![VerificationLevel::Basic, VerificationLevel::Liveness],
+                },
                 status,
                 credential,
             ),
@@ -874,12 +3660,18 @@ mod tests {
 
         VerificationCase {
             id: uuid::Uuid::new_v4().to_string(),
-            created_at,
-            updated_at,
+            created_at: Some(created_at),
+            updated_at: Some(updated_at),
             level,
             status,
             credential,
-            details: VerificationDetails { liveness },
+            details: VerificationDetails {
+                liveness,
+                liveness_audit: HashMap::from([(
+                    "liveness_audit_best_file".to_owned(),
+                    Some("https://some_url".to_owned()),
+                )]),
+            },
         }
     }
 